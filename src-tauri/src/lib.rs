@@ -1,9 +1,16 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 
 mod script_to_audio;
+mod text_normalize;
 mod ttslib;
 
-use script_to_audio::generate_audio;
+use script_to_audio::{
+    compare_voices, generate_audio, generate_audio_batch, list_presets, list_sound_effects,
+    load_project, play_audio, preview_audio, profile_script, refresh_assets, register_preset,
+    register_sound_effect, save_project, set_max_concurrent_jobs, stop_playback, validate_script,
+    verify_models, warm_up_tts, AssetCatalog, JobQueue, PlaybackState, PresetRegistry,
+    SoundRegistry, TtsState,
+};
 
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -17,7 +24,33 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_cors_fetch::init())
-        .invoke_handler(tauri::generate_handler![greet, generate_audio])
+        .manage(TtsState::default())
+        .manage(SoundRegistry::default())
+        .manage(PresetRegistry::default())
+        .manage(AssetCatalog::default())
+        .manage(JobQueue::default())
+        .manage(PlaybackState::default())
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            generate_audio,
+            generate_audio_batch,
+            validate_script,
+            warm_up_tts,
+            register_sound_effect,
+            list_sound_effects,
+            register_preset,
+            list_presets,
+            save_project,
+            load_project,
+            set_max_concurrent_jobs,
+            play_audio,
+            stop_playback,
+            preview_audio,
+            compare_voices,
+            refresh_assets,
+            profile_script,
+            verify_models
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
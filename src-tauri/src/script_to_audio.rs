@@ -7,22 +7,77 @@ use anyhow::{Context, Result};
 use hound::{SampleFormat, WavReader, WavSpec};
 use kuchiki::traits::TendrilSink;
 use kuchiki::NodeRef;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use rustfft::{num_complex::Complex, FftPlanner};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
-
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use ort::execution_providers::{
+    CUDAExecutionProvider, CoreMLExecutionProvider, DirectMLExecutionProvider,
+};
+use ort::session::{builder::SessionBuilder, Session};
 use tauri::{AppHandle, Emitter, Manager};
 
-use crate::ttslib::{load_cfgs, load_voice_style, Style, TextToSpeech, UnicodeProcessor};
+use crate::text_normalize::{Currency, TextNormalizer};
+use crate::ttslib::{
+    load_cfgs, load_voice_style, Style, TextToSpeech, UnicodeProcessor, ABBREVIATIONS,
+};
 
 // ============================================================================
 // Constants and Configuration
 // ============================================================================
 
 const SAMPLE_RATE: u32 = 24000;
+const DEFAULT_MAX_CHUNK_CHARS: usize = 300;
+const DEFAULT_CHUNK_PAUSE_SECS: f32 = 0.15;
+const DEFAULT_TTS_STEPS: usize = 50;
+const DEFAULT_TTS_TEMPERATURE: f32 = 1.0;
+const MIN_TTS_STEPS: usize = 5;
+/// `<quality value>` -> diffusion step count
+const QUALITY_PRESETS: &[(&str, usize)] = &[("fast", 15), ("balanced", 30), ("best", 60)];
+const MAX_TTS_STEPS: usize = 200;
+const DEFAULT_TRIM_THRESHOLD: f32 = 0.002;
+const DEFAULT_TRIM_MIN_SILENCE_MS: f32 = 20.0;
+const DEFAULT_TTS_GAIN: f32 = 0.85;
+/// Share of the progress bar reserved for downloading model files
+const DEFAULT_DOWNLOAD_WEIGHT: f32 = 0.1;
+/// Minimum time between throttled progress events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+/// Progress change that is emitted even within `PROGRESS_INTERVAL`
+const PROGRESS_MIN_DELTA: f32 = 0.01;
+/// Largest relative speed change `humanize = 1.0` applies to a text chunk
+const DEFAULT_HUMANIZE_RANGE: f32 = 0.08;
+/// Longest audio a `<loop>` may extend the render to (two hours)
+const DEFAULT_MAX_OUTPUT_SECS: f32 = 7200.0;
+/// Fade length `concat_declicked` uses at segment boundaries
+const DECLICK_FADE_SECS: f32 = 0.003;
+/// Peak level `merge_normalized` scales hot mixes down to (-0.1 dBFS)
+const NORMALIZE_CEILING_DB: f32 = -0.1;
+/// Share of clipped samples in the written file above which the user is warned
+const CLIP_WARNING_RATIO: f32 = 0.0001;
+/// Environment variable that relocates the model, voice and sound caches
+const DATA_DIR_ENV: &str = "DOMGPT_DATA_DIR";
 const MODEL_REPO: &str = "https://huggingface.co/Supertone/supertonic/resolve/main";
+/// Files fetched from `{MODEL_REPO}/onnx`
+const MODEL_FILES: &[&str] = &[
+    "duration_predictor.onnx",
+    "text_encoder.onnx",
+    "vector_estimator.onnx",
+    "vocoder.onnx",
+    "tts.json",
+    "unicode_indexer.json",
+];
+/// Files fetched from `{MODEL_REPO}/voice_styles`
+const VOICE_FILES: &[&str] = &["F1.json", "F2.json", "M1.json", "M2.json"];
 
 // ============================================================================
 // Embedded Sound Effects
@@ -76,6 +131,56 @@ fn get_voices() -> HashMap<&'static str, &'static str> {
     map
 }
 
+// ============================================================================
+// Execution Providers
+// ============================================================================
+
+/// ONNX Runtime execution provider used for the TTS sessions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionProvider {
+    #[default]
+    Cpu,
+    Cuda,
+    CoreMl,
+    DirectMl,
+}
+
+impl ExecutionProvider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExecutionProvider::Cpu => "CPU",
+            ExecutionProvider::Cuda => "CUDA",
+            ExecutionProvider::CoreMl => "CoreML",
+            ExecutionProvider::DirectMl => "DirectML",
+        }
+    }
+}
+
+/// Create a session builder with the requested execution provider registered.
+/// Falls back to the default CPU provider if registration fails, and returns
+/// the provider that was actually used.
+fn session_builder(provider: ExecutionProvider) -> Result<(SessionBuilder, ExecutionProvider)> {
+    let dispatch = match provider {
+        ExecutionProvider::Cpu => return Ok((Session::builder()?, ExecutionProvider::Cpu)),
+        ExecutionProvider::Cuda => CUDAExecutionProvider::default().build(),
+        ExecutionProvider::CoreMl => CoreMLExecutionProvider::default().build(),
+        ExecutionProvider::DirectMl => DirectMLExecutionProvider::default().build(),
+    };
+
+    match Session::builder()?.with_execution_providers([dispatch.error_on_failure()]) {
+        Ok(builder) => Ok((builder, provider)),
+        Err(e) => {
+            eprintln!(
+                "Failed to register {} execution provider, falling back to CPU: {}",
+                provider.name(),
+                e
+            );
+            Ok((Session::builder()?, ExecutionProvider::Cpu))
+        }
+    }
+}
+
 // ============================================================================
 // Progress Event Types
 // ============================================================================
@@ -86,6 +191,42 @@ pub struct TtsProgressEvent {
     pub message: String,
     pub progress: f32,
     pub stage: String,
+    /// Position of the script in a `generate_audio_batch` call
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+}
+
+/// Receiver for the progress events of a render, so rendering does not
+/// depend on Tauri. The app passes its `AppHandle`, which emits each event
+/// as `tts-progress`.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, event: TtsProgressEvent);
+}
+
+impl ProgressSink for AppHandle {
+    fn report(&self, event: TtsProgressEvent) {
+        let _ = self.emit("tts-progress", event);
+    }
+}
+
+/// One finished top-level segment of a streamed render, as a standalone WAV
+#[derive(Clone, Serialize)]
+pub struct AudioChunkEvent {
+    pub job_id: String,
+    /// Position of this chunk in the stream, starting at 0
+    pub index: usize,
+    /// Set on the last chunk of the job
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    /// Base64-encoded WAV bytes
+    pub data: String,
+}
+
+/// A rendered top-level segment handed to `ScriptToAudioContext::chunk_sink`
+pub struct AudioChunk {
+    pub index: usize,
+    pub is_final: bool,
+    pub audio: AudioBuffer,
 }
 
 // ============================================================================
@@ -105,10 +246,41 @@ pub struct EffectOptions {
     pub fade_ms: Option<f32>,
     // Pan options (-1.0 = full left, 0.0 = center, 1.0 = full right)
     pub pan: Option<f32>,
+    // Bitcrush options (target bit depth, sample-and-hold factor)
+    pub bits: Option<u32>,
+    pub rate_reduction: Option<u32>,
+    // Ring modulation / robot carrier frequency in Hz
+    pub carrier: Option<f32>,
+    // Haas widener: right-channel delay in milliseconds
+    pub delay_ms: Option<f32>,
+    // Doppler options (m/s, metres, +1 = left to right / -1 = right to left)
+    pub speed: Option<f32>,
+    pub closest_distance: Option<f32>,
+    pub direction: Option<f32>,
+    // Spatial options in degrees (azimuth: 0 = front, 90 = right; elevation: 90 = above)
+    pub azimuth: Option<f32>,
+    pub elevation: Option<f32>,
+    // Auto-wah options (envelope to sweep scaling, sweep range in Hz, filter Q)
+    pub sensitivity: Option<f32>,
+    pub min_freq: Option<f32>,
+    pub max_freq: Option<f32>,
+    pub q: Option<f32>,
+    // Distortion options (pre-gain, post low-pass cutoff in Hz, wet share 0..1)
+    pub drive: Option<f32>,
+    pub tone: Option<f32>,
+    pub mix: Option<f32>,
+    // Denoise over-subtraction factor (0 = off, up to 4)
+    pub strength: Option<f32>,
 }
 
 impl EffectOptions {
+    /// Parse options from JSON, falling back to no options at all if it is
+    /// malformed
     pub fn from_json(json: &str) -> Self {
+        Self::try_from_json(json).unwrap_or_default()
+    }
+
+    pub fn try_from_json(json: &str) -> Result<Self> {
         #[derive(Deserialize, Default)]
         struct Opts {
             delay: Option<f32>,
@@ -120,10 +292,32 @@ impl EffectOptions {
             #[serde(rename = "fadeMs")]
             fade_ms: Option<f32>,
             pan: Option<f32>,
+            bits: Option<u32>,
+            #[serde(rename = "rateReduction", alias = "rate_reduction")]
+            rate_reduction: Option<u32>,
+            carrier: Option<f32>,
+            #[serde(rename = "delayMs", alias = "delay_ms")]
+            delay_ms: Option<f32>,
+            speed: Option<f32>,
+            #[serde(rename = "closestDistance", alias = "closest_distance")]
+            closest_distance: Option<f32>,
+            direction: Option<f32>,
+            azimuth: Option<f32>,
+            elevation: Option<f32>,
+            sensitivity: Option<f32>,
+            #[serde(rename = "minFreq", alias = "min_freq")]
+            min_freq: Option<f32>,
+            #[serde(rename = "maxFreq", alias = "max_freq")]
+            max_freq: Option<f32>,
+            q: Option<f32>,
+            drive: Option<f32>,
+            tone: Option<f32>,
+            mix: Option<f32>,
+            strength: Option<f32>,
         }
 
-        let opts: Opts = serde_json::from_str(json).unwrap_or_default();
-        EffectOptions {
+        let opts: Opts = serde_json::from_str(json)?;
+        Ok(EffectOptions {
             delay: opts.delay,
             decay: opts.decay,
             repeats: opts.repeats,
@@ -132,7 +326,24 @@ impl EffectOptions {
             amplitude: opts.amplitude,
             fade_ms: opts.fade_ms,
             pan: opts.pan,
-        }
+            bits: opts.bits,
+            rate_reduction: opts.rate_reduction,
+            carrier: opts.carrier,
+            delay_ms: opts.delay_ms,
+            speed: opts.speed,
+            closest_distance: opts.closest_distance,
+            direction: opts.direction,
+            azimuth: opts.azimuth,
+            elevation: opts.elevation,
+            sensitivity: opts.sensitivity,
+            min_freq: opts.min_freq,
+            max_freq: opts.max_freq,
+            q: opts.q,
+            drive: opts.drive,
+            tone: opts.tone,
+            mix: opts.mix,
+            strength: opts.strength,
+        })
     }
 
     pub fn merge(&self, other: &EffectOptions) -> EffectOptions {
@@ -145,6 +356,23 @@ impl EffectOptions {
             amplitude: other.amplitude.or(self.amplitude),
             fade_ms: other.fade_ms.or(self.fade_ms),
             pan: other.pan.or(self.pan),
+            bits: other.bits.or(self.bits),
+            rate_reduction: other.rate_reduction.or(self.rate_reduction),
+            carrier: other.carrier.or(self.carrier),
+            delay_ms: other.delay_ms.or(self.delay_ms),
+            speed: other.speed.or(self.speed),
+            closest_distance: other.closest_distance.or(self.closest_distance),
+            direction: other.direction.or(self.direction),
+            azimuth: other.azimuth.or(self.azimuth),
+            elevation: other.elevation.or(self.elevation),
+            sensitivity: other.sensitivity.or(self.sensitivity),
+            min_freq: other.min_freq.or(self.min_freq),
+            max_freq: other.max_freq.or(self.max_freq),
+            q: other.q.or(self.q),
+            drive: other.drive.or(self.drive),
+            tone: other.tone.or(self.tone),
+            mix: other.mix.or(self.mix),
+            strength: other.strength.or(self.strength),
         }
     }
 }
@@ -245,6 +473,151 @@ fn get_pan_presets() -> HashMap<&'static str, EffectOptions> {
     map
 }
 
+fn get_distortion_presets() -> HashMap<&'static str, EffectOptions> {
+    let mut map = HashMap::new();
+    map.insert(
+        "warm",
+        EffectOptions {
+            drive: Some(2.0),
+            tone: Some(4000.0),
+            mix: Some(0.6),
+            ..Default::default()
+        },
+    );
+    map.insert(
+        "crunch",
+        EffectOptions {
+            drive: Some(6.0),
+            tone: Some(3500.0),
+            mix: Some(1.0),
+            ..Default::default()
+        },
+    );
+    map.insert(
+        "fuzz",
+        EffectOptions {
+            drive: Some(25.0),
+            tone: Some(2500.0),
+            mix: Some(1.0),
+            ..Default::default()
+        },
+    );
+    map
+}
+
+/// Voice style used for unknown voice keys and voices that fail to load
+const DEFAULT_VOICE_FILE: &str = "F1.json";
+
+/// Effects understood by `<effect value="...">`
+const KNOWN_EFFECTS: &[&str] = &[
+    "echo",
+    "binaural",
+    "pan",
+    "telephone",
+    "radio",
+    "bitcrush",
+    "ringmod",
+    "pingpong",
+    "haas",
+    "robot",
+    "doppler",
+    "spatial",
+    "autowah",
+    "distortion",
+    "denoise",
+];
+
+/// File in the data directory, next to `sounds/`, holding user effect
+/// presets as `{ "echo": { "cathedral": { "delay": 0.4 } } }`
+pub const PRESETS_FILE: &str = "presets.json";
+
+/// Effect name -> preset name -> options
+pub type EffectPresets = HashMap<String, HashMap<String, EffectOptions>>;
+
+/// Read user effect presets from `path`, returning them with a warning for
+/// every entry that was skipped. A missing file just means no presets.
+pub fn load_effect_presets(path: &Path) -> (EffectPresets, Vec<String>) {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return (EffectPresets::new(), Vec::new())
+        }
+        Err(e) => {
+            let warning = format!("Ignoring presets in {}: {}", path.display(), e);
+            return (EffectPresets::new(), vec![warning]);
+        }
+    };
+    match serde_json::from_str(&json) {
+        Ok(effects) => parse_effect_presets(effects),
+        Err(e) => {
+            let warning = format!("Ignoring presets in {}: {}", path.display(), e);
+            (EffectPresets::new(), vec![warning])
+        }
+    }
+}
+
+/// Read presets laid out as in `presets.json`, returning them with a warning
+/// for every entry that was skipped
+pub fn parse_effect_presets(
+    effects: serde_json::Map<String, serde_json::Value>,
+) -> (EffectPresets, Vec<String>) {
+    let mut presets = EffectPresets::new();
+    let mut warnings = Vec::new();
+    for (effect, entries) in effects {
+        if !KNOWN_EFFECTS.contains(&effect.as_str()) {
+            warnings.push(format!(
+                "Skipping presets for unknown effect \"{}\"",
+                effect
+            ));
+            continue;
+        }
+        let Some(entries) = entries.as_object() else {
+            warnings.push(format!("Skipping {} presets: expected an object", effect));
+            continue;
+        };
+        for (name, options) in entries {
+            let parsed = if options.is_object() {
+                EffectOptions::try_from_json(&options.to_string())
+            } else {
+                Err(anyhow::anyhow!("expected an object"))
+            };
+            match parsed {
+                Ok(options) => {
+                    presets
+                        .entry(effect.clone())
+                        .or_default()
+                        .insert(name.clone(), options);
+                }
+                Err(e) => warnings.push(format!("Skipping {} preset \"{}\": {}", effect, name, e)),
+            }
+        }
+    }
+    (presets, warnings)
+}
+
+/// Look up a named preset for an effect
+fn get_effect_preset(effect_name: &str, preset_name: &str) -> Option<EffectOptions> {
+    match effect_name {
+        "echo" => get_echo_presets().get(preset_name).cloned(),
+        "binaural" => get_binaural_presets().get(preset_name).cloned(),
+        "pan" => get_pan_presets().get(preset_name).cloned(),
+        "distortion" => get_distortion_presets().get(preset_name).cloned(),
+        _ => None,
+    }
+}
+
+/// Names of the built-in presets for an effect
+fn builtin_preset_names(effect_name: &str) -> Vec<String> {
+    let presets = match effect_name {
+        "echo" => get_echo_presets(),
+        "binaural" => get_binaural_presets(),
+        "pan" => get_pan_presets(),
+        "distortion" => get_distortion_presets(),
+        _ => HashMap::new(),
+    };
+    presets.into_keys().map(String::from).collect()
+}
+
 // ============================================================================
 // Audio Buffer Implementation
 // ============================================================================
@@ -255,6 +628,31 @@ pub struct AudioBuffer {
     pub sample_rate: u32,
 }
 
+/// Sample rate `concat_at`/`merge_at` convert their inputs to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RatePolicy {
+    /// The first buffer's rate, as `concat` and `merge` use
+    #[default]
+    First,
+    /// The highest rate among the inputs, so nothing is downsampled
+    Max,
+    Fixed(u32),
+}
+
+impl RatePolicy {
+    fn resolve(self, buffers: &[AudioBuffer]) -> u32 {
+        match self {
+            RatePolicy::First => buffers.first().map_or(SAMPLE_RATE, |b| b.sample_rate),
+            RatePolicy::Max => buffers
+                .iter()
+                .map(|b| b.sample_rate)
+                .max()
+                .unwrap_or(SAMPLE_RATE),
+            RatePolicy::Fixed(rate) => rate.max(1),
+        }
+    }
+}
+
 impl AudioBuffer {
     pub fn new(channels: usize, length: usize, sample_rate: u32) -> Self {
         AudioBuffer {
@@ -285,6 +683,11 @@ impl AudioBuffer {
         self.samples.first().map(|c| c.len()).unwrap_or(0)
     }
 
+    /// Length in seconds
+    pub fn duration_secs(&self) -> f32 {
+        self.length() as f32 / self.sample_rate as f32
+    }
+
     pub fn get_channel_data(&self, channel: usize) -> &[f32] {
         &self.samples[channel]
     }
@@ -301,88 +704,102 @@ impl AudioBuffer {
 
     /// Concatenate multiple audio buffers (resamples to first buffer's sample rate if needed)
     pub fn concat(buffers: &[AudioBuffer]) -> Result<AudioBuffer> {
+        AudioBuffer::concat_at(buffers, RatePolicy::First)
+    }
+
+    /// Concatenate like `concat`, converting every buffer to the sample rate
+    /// `policy` picks
+    pub fn concat_at(buffers: &[AudioBuffer], policy: RatePolicy) -> Result<AudioBuffer> {
         if buffers.is_empty() {
             return Ok(AudioBuffer::new(1, 1, SAMPLE_RATE));
         }
 
-        // Use first buffer's sample rate as target
-        let target_sample_rate = buffers[0].sample_rate;
+        let target_sample_rate = policy.resolve(buffers);
 
-        // Resample all buffers to the target sample rate
-        let resampled: Vec<AudioBuffer> = buffers
+        // Lay the result out from metadata so it is allocated exactly once
+        let num_channels = buffers.iter().map(|b| b.num_channels()).max().unwrap_or(1);
+        let lengths: Vec<usize> = buffers
             .iter()
-            .map(|b| {
-                if b.sample_rate != target_sample_rate {
-                    b.resample(target_sample_rate)
-                } else {
-                    b.clone()
-                }
-            })
+            .map(|b| b.resampled_length(target_sample_rate))
             .collect();
-
-        let num_channels = resampled
-            .iter()
-            .map(|b| b.num_channels())
-            .max()
-            .unwrap_or(1);
-        let total_length: usize = resampled.iter().map(|b| b.length()).sum();
+        let total_length: usize = lengths.iter().sum();
 
         let mut result = AudioBuffer::new(num_channels, total_length, target_sample_rate);
         let mut offset = 0;
 
-        for buffer in &resampled {
-            for ch in 0..num_channels {
-                let src_ch = ch.min(buffer.num_channels() - 1);
-                let src_data = buffer.get_channel_data(src_ch);
-                let dst_data = result.get_channel_data_mut(ch);
-                for (i, &sample) in src_data.iter().enumerate() {
-                    dst_data[offset + i] = sample;
-                }
+        for (buffer, length) in buffers.iter().zip(lengths) {
+            // Only a buffer that needs converting is copied, and the copy is
+            // dropped before the next one is made
+            let converted;
+            let source = if buffer.sample_rate == target_sample_rate
+                && buffer.num_channels() == num_channels
+            {
+                buffer
+            } else {
+                converted = buffer
+                    .resample(target_sample_rate)
+                    .conform_channels(num_channels);
+                &converted
+            };
+
+            for (dst, src) in result.samples.iter_mut().zip(&source.samples) {
+                let count = src.len().min(length);
+                dst[offset..offset + count].copy_from_slice(&src[..count]);
             }
-            offset += buffer.length();
+            offset += length;
         }
 
         Ok(result)
     }
 
-    /// Merge (mix) multiple audio buffers together (resamples to first buffer's sample rate if needed)
-    pub fn merge(buffers: &[AudioBuffer]) -> Result<AudioBuffer> {
+    /// Concatenate buffers like `concat` with `gap_secs` of silence between
+    /// each adjacent pair, none before the first or after the last
+    pub fn concat_with_gap(buffers: &[AudioBuffer], gap_secs: f32) -> Result<AudioBuffer> {
+        let Some(first) = buffers.first() else {
+            return AudioBuffer::concat(buffers);
+        };
+        let gap = AudioBuffer::silence(gap_secs.max(0.0), first.sample_rate);
+        if gap.length() == 0 {
+            return AudioBuffer::concat(buffers);
+        }
+
+        let mut spaced = Vec::with_capacity(buffers.len() * 2 - 1);
+        for (i, buffer) in buffers.iter().enumerate() {
+            if i > 0 {
+                spaced.push(gap.clone());
+            }
+            spaced.push(buffer.clone());
+        }
+        AudioBuffer::concat(&spaced)
+    }
+
+    /// Concatenate buffers like `concat`, applying a short linear fade-out and
+    /// fade-in at every internal boundary so the join passes through zero
+    /// instead of clicking. Unlike `concat_crossfade` the length is unchanged.
+    pub fn concat_declicked(buffers: &[AudioBuffer], fade_secs: f32) -> Result<AudioBuffer> {
         if buffers.is_empty() {
             return Ok(AudioBuffer::new(1, 1, SAMPLE_RATE));
         }
 
-        // Use first buffer's sample rate as target
         let target_sample_rate = buffers[0].sample_rate;
-
-        // Resample all buffers to the target sample rate
         let resampled: Vec<AudioBuffer> = buffers
             .iter()
-            .map(|b| {
-                if b.sample_rate != target_sample_rate {
-                    b.resample(target_sample_rate)
-                } else {
-                    b.clone()
-                }
-            })
+            .map(|b| b.resample(target_sample_rate))
             .collect();
-
-        let num_channels = resampled
-            .iter()
-            .map(|b| b.num_channels())
-            .max()
-            .unwrap_or(1);
-        let max_length = resampled.iter().map(|b| b.length()).max().unwrap_or(0);
-
-        let mut result = AudioBuffer::new(num_channels, max_length, target_sample_rate);
-
-        for buffer in &resampled {
-            for ch in 0..num_channels {
-                let src_ch = ch.min(buffer.num_channels() - 1);
-                let src_data = buffer.get_channel_data(src_ch);
-                let dst_data = result.get_channel_data_mut(ch);
-                for (i, &sample) in src_data.iter().enumerate() {
-                    let mixed = dst_data[i] + sample;
-                    dst_data[i] = mixed.clamp(-1.0, 1.0);
+        let mut result = AudioBuffer::concat(&resampled)?;
+
+        let fade = (fade_secs.max(0.0) * target_sample_rate as f32) as usize;
+        let mut boundary = 0;
+        for pair in resampled.windows(2) {
+            boundary += pair[0].length();
+            let n = fade.min(pair[0].length() / 2).min(pair[1].length() / 2);
+            if n == 0 {
+                continue;
+            }
+            for channel in result.samples.iter_mut() {
+                for i in 0..n {
+                    channel[boundary - n + i] *= (n - 1 - i) as f32 / n as f32;
+                    channel[boundary + i] *= i as f32 / n as f32;
                 }
             }
         }
@@ -390,198 +807,626 @@ impl AudioBuffer {
         Ok(result)
     }
 
-    /// Convert to mono by averaging channels
-    pub fn to_mono(&self) -> Vec<f32> {
-        let len = self.length();
-        let mut mono = vec![0.0; len];
-        let num_channels = self.num_channels() as f32;
-
-        for ch in 0..self.num_channels() {
-            let data = self.get_channel_data(ch);
-            for i in 0..len {
-                mono[i] += data[i] / num_channels;
-            }
-        }
+    /// Concatenate buffers, overlapping each adjacent pair by `fade_secs` with
+    /// an equal-power crossfade. The overlap shrinks for buffers shorter than
+    /// the fade, so the result is `sum(len) - (n - 1) * fade` samples long.
+    pub fn concat_crossfade(buffers: &[AudioBuffer], fade_secs: f32) -> Result<AudioBuffer> {
+        let parts: Vec<&AudioBuffer> = buffers.iter().collect();
+        Ok(AudioBuffer::crossfade_parts(&parts, fade_secs))
+    }
 
-        mono
+    /// This buffer `count` times over, crossfaded like `concat_crossfade`
+    /// without copying the buffer for each repetition
+    pub fn repeat_crossfade(&self, count: usize, fade_secs: f32) -> AudioBuffer {
+        AudioBuffer::crossfade_parts(&vec![self; count], fade_secs)
     }
 
-    /// Write to WAV file
-    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let spec = WavSpec {
-            channels: self.num_channels() as u16,
-            sample_rate: self.sample_rate,
-            bits_per_sample: 16,
-            sample_format: SampleFormat::Int,
+    /// Lay `parts` out end to end with equal-power crossfades at the seams,
+    /// allocating the result once and writing each part in place
+    fn crossfade_parts(parts: &[&AudioBuffer], fade_secs: f32) -> AudioBuffer {
+        let Some(first) = parts.first() else {
+            return AudioBuffer::new(1, 1, SAMPLE_RATE);
         };
+        let sample_rate = first.sample_rate;
+        let fade = (fade_secs.max(0.0) * sample_rate as f32) as usize;
+
+        // Each overlap is limited by the output so far and the incoming part
+        let num_channels = parts.iter().map(|b| b.num_channels()).max().unwrap_or(1);
+        let mut total_length = 0;
+        let mut overlaps = Vec::with_capacity(parts.len());
+        for part in parts {
+            let length = part.resampled_length(sample_rate);
+            let overlap = fade.min(total_length).min(length);
+            overlaps.push(overlap);
+            total_length += length - overlap;
+        }
 
-        let mut writer = hound::WavWriter::create(path, spec)?;
-        let len = self.length();
+        let mut out = AudioBuffer::new(num_channels, total_length, sample_rate);
+        let mut end = 0;
+        for (part, overlap) in parts.iter().zip(overlaps) {
+            let converted;
+            let source = if part.sample_rate == sample_rate {
+                *part
+            } else {
+                converted = part.resample(sample_rate);
+                &converted
+            };
+            let start = end - overlap;
 
-        for i in 0..len {
-            for ch in 0..self.num_channels() {
-                let sample = self.samples[ch][i].clamp(-1.0, 1.0);
-                let val = (sample * 32767.0) as i16;
-                writer.write_sample(val)?;
+            for ch in 0..num_channels {
+                let src = source.get_channel_data(ch.min(source.num_channels() - 1));
+                let dst = &mut out.get_channel_data_mut(ch)[start..];
+                for i in 0..overlap {
+                    let angle = (i as f32 + 0.5) / overlap as f32 * std::f32::consts::FRAC_PI_2;
+                    let mixed = dst[i] * angle.cos() + src[i] * angle.sin();
+                    dst[i] = mixed.clamp(-1.0, 1.0);
+                }
+                dst[overlap..src.len()].copy_from_slice(&src[overlap..]);
             }
+            end = start + source.length();
         }
 
-        writer.finalize()?;
-        Ok(())
+        out
     }
 
-    /// Read from WAV file
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let reader = WavReader::open(path)?;
-        let spec = reader.spec();
-        let num_channels = spec.channels as usize;
-        let sample_rate = spec.sample_rate;
+    /// Merge (mix) multiple audio buffers together (resamples to first buffer's sample rate if needed)
+    pub fn merge(buffers: &[AudioBuffer]) -> Result<AudioBuffer> {
+        AudioBuffer::merge_weighted(buffers, &vec![1.0; buffers.len()])
+    }
 
-        let samples: Vec<i16> = reader
-            .into_samples::<i16>()
-            .filter_map(|s| s.ok())
-            .collect();
+    /// Merge like `merge`, converting every buffer to the sample rate
+    /// `policy` picks
+    pub fn merge_at(buffers: &[AudioBuffer], policy: RatePolicy) -> Result<AudioBuffer> {
+        AudioBuffer::sum_buffers(buffers, &vec![1.0; buffers.len()], true, policy)
+    }
 
-        let num_samples = samples.len() / num_channels;
-        let mut channels = vec![vec![0.0f32; num_samples]; num_channels];
+    /// Merge (mix) multiple audio buffers, scaling each by its gain before summing
+    pub fn merge_weighted(buffers: &[AudioBuffer], gains: &[f32]) -> Result<AudioBuffer> {
+        AudioBuffer::sum_buffers(buffers, gains, true, RatePolicy::First)
+    }
 
-        for (i, sample) in samples.iter().enumerate() {
-            let ch = i % num_channels;
-            let idx = i / num_channels;
-            channels[ch][idx] = *sample as f32 / 32768.0;
+    /// Merge (mix) multiple audio buffers without clamping. If the mix peaks
+    /// above full scale, the whole mix is scaled down so its peak sits at
+    /// -0.1 dBFS, preserving the relative levels of every buffer.
+    pub fn merge_normalized(buffers: &[AudioBuffer]) -> Result<AudioBuffer> {
+        let gains = vec![1.0; buffers.len()];
+        let mut result = AudioBuffer::sum_buffers(buffers, &gains, false, RatePolicy::First)?;
+
+        let peak = result.peak();
+        if peak > 1.0 {
+            let scale = 10f32.powf(NORMALIZE_CEILING_DB / 20.0) / peak;
+            for sample in result.samples.iter_mut().flatten() {
+                *sample *= scale;
+            }
         }
 
-        Ok(AudioBuffer {
-            samples: channels,
-            sample_rate,
-        })
+        Ok(result)
     }
 
-    /// Read from WAV bytes
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let cursor = Cursor::new(bytes);
-        let reader = WavReader::new(cursor)?;
-        let spec = reader.spec();
-        let num_channels = spec.channels as usize;
-        let sample_rate = spec.sample_rate;
-        let bits_per_sample = spec.bits_per_sample;
+    /// Sum gain-scaled buffers at the sample rate `policy` picks, optionally
+    /// clamping every partial sum to [-1, 1]
+    fn sum_buffers(
+        buffers: &[AudioBuffer],
+        gains: &[f32],
+        clamp: bool,
+        policy: RatePolicy,
+    ) -> Result<AudioBuffer> {
+        if buffers.len() != gains.len() {
+            anyhow::bail!(
+                "merge_weighted got {} buffers but {} gains",
+                buffers.len(),
+                gains.len()
+            );
+        }
 
-        let num_samples_total: usize;
-        let mut channels: Vec<Vec<f32>>;
+        if buffers.is_empty() {
+            return Ok(AudioBuffer::new(1, 1, SAMPLE_RATE));
+        }
 
-        match bits_per_sample {
-            16 => {
-                let samples: Vec<i16> = reader
-                    .into_samples::<i16>()
-                    .filter_map(|s| s.ok())
-                    .collect();
+        let target_sample_rate = policy.resolve(buffers);
 
-                num_samples_total = samples.len() / num_channels;
-                channels = vec![vec![0.0f32; num_samples_total]; num_channels];
+        // Resample all buffers to the target sample rate and channel count
+        let num_channels = buffers.iter().map(|b| b.num_channels()).max().unwrap_or(1);
+        let resampled: Vec<AudioBuffer> = buffers
+            .iter()
+            .map(|b| {
+                b.resample(target_sample_rate)
+                    .conform_channels(num_channels)
+            })
+            .collect();
 
-                for (i, sample) in samples.iter().enumerate() {
-                    let ch = i % num_channels;
-                    let idx = i / num_channels;
-                    channels[ch][idx] = *sample as f32 / 32768.0;
-                }
-            }
-            24 => {
-                let samples: Vec<i32> = reader
-                    .into_samples::<i32>()
-                    .filter_map(|s| s.ok())
-                    .collect();
+        let max_length = resampled.iter().map(|b| b.length()).max().unwrap_or(0);
 
-                num_samples_total = samples.len() / num_channels;
-                channels = vec![vec![0.0f32; num_samples_total]; num_channels];
+        let mut result = AudioBuffer::new(num_channels, max_length, target_sample_rate);
 
-                for (i, sample) in samples.iter().enumerate() {
-                    let ch = i % num_channels;
-                    let idx = i / num_channels;
-                    // 24-bit audio is stored in i32, max value is 2^23
-                    channels[ch][idx] = *sample as f32 / 8388608.0;
+        for (buffer, &gain) in resampled.iter().zip(gains) {
+            for ch in 0..num_channels {
+                let src_data = buffer.get_channel_data(ch);
+                let dst_data = result.get_channel_data_mut(ch);
+                for (i, &sample) in src_data.iter().enumerate() {
+                    let mixed = dst_data[i] + sample * gain;
+                    dst_data[i] = if clamp { mixed.clamp(-1.0, 1.0) } else { mixed };
                 }
             }
-            32 => {
-                let samples: Vec<i32> = reader
-                    .into_samples::<i32>()
-                    .filter_map(|s| s.ok())
-                    .collect();
+        }
 
-                num_samples_total = samples.len() / num_channels;
-                channels = vec![vec![0.0f32; num_samples_total]; num_channels];
+        Ok(result)
+    }
 
-                for (i, sample) in samples.iter().enumerate() {
-                    let ch = i % num_channels;
-                    let idx = i / num_channels;
-                    channels[ch][idx] = *sample as f32 / 2147483648.0;
-                }
-            }
-            _ => {
-                // Fallback to 16-bit
-                let samples: Vec<i16> = reader
-                    .into_samples::<i16>()
-                    .filter_map(|s| s.ok())
-                    .collect();
+    /// Copy the samples in `start..end`, clamping both indices to the buffer.
+    /// An empty range yields a 1-sample buffer, like `trim_silence`.
+    pub fn slice(&self, start: usize, end: usize) -> AudioBuffer {
+        let end = end.min(self.length());
+        let start = start.min(end);
 
-                num_samples_total = samples.len() / num_channels;
-                channels = vec![vec![0.0f32; num_samples_total]; num_channels];
+        if start >= end {
+            return AudioBuffer::new(self.num_channels().max(1), 1, self.sample_rate);
+        }
 
-                for (i, sample) in samples.iter().enumerate() {
-                    let ch = i % num_channels;
-                    let idx = i / num_channels;
-                    channels[ch][idx] = *sample as f32 / 32768.0;
-                }
-            }
+        AudioBuffer {
+            samples: self
+                .samples
+                .iter()
+                .map(|channel| channel[start..end].to_vec())
+                .collect(),
+            sample_rate: self.sample_rate,
         }
+    }
 
-        Ok(AudioBuffer {
-            samples: channels,
-            sample_rate,
-        })
+    /// Remove `start_secs` from the beginning and `end_secs` from the end
+    pub fn trim(&self, start_secs: f32, end_secs: f32) -> AudioBuffer {
+        let start = (start_secs.max(0.0) * self.sample_rate as f32) as usize;
+        let end_trim = (end_secs.max(0.0) * self.sample_rate as f32) as usize;
+        self.slice(start, self.length().saturating_sub(end_trim))
     }
 
-    /// Resample audio buffer to a target sample rate using linear interpolation
-    pub fn resample(&self, target_sample_rate: u32) -> Self {
-        if self.sample_rate == target_sample_rate {
-            return self.clone();
-        }
+    /// Add `before_secs` of silence at the beginning and `after_secs` at the end
+    pub fn pad(&self, before_secs: f32, after_secs: f32) -> AudioBuffer {
+        let before = (before_secs.max(0.0) * self.sample_rate as f32) as usize;
+        let after = (after_secs.max(0.0) * self.sample_rate as f32) as usize;
 
-        let ratio = self.sample_rate as f64 / target_sample_rate as f64;
-        let new_length = ((self.length() as f64) / ratio).ceil() as usize;
-        let num_channels = self.num_channels();
+        AudioBuffer {
+            samples: self
+                .samples
+                .iter()
+                .map(|channel| {
+                    let mut padded = vec![0.0; before];
+                    padded.extend_from_slice(channel);
+                    padded.resize(before + channel.len() + after, 0.0);
+                    padded
+                })
+                .collect(),
+            sample_rate: self.sample_rate,
+        }
+    }
 
-        let mut new_samples = vec![vec![0.0f32; new_length]; num_channels];
+    /// Apply a gain in decibels (clamped like `apply_volume`)
+    pub fn gain_db(&self, db: f32) -> AudioBuffer {
+        apply_volume(self, 10f32.powf(db / 20.0))
+    }
 
-        for ch in 0..num_channels {
-            let src = &self.samples[ch];
-            let dst = &mut new_samples[ch];
-            let src_len = src.len();
+    /// Same layout and sample rate, with every sample within `tol` of the
+    /// other buffer's. Meant for golden-file comparisons in tests.
+    pub fn approx_eq(&self, other: &AudioBuffer, tol: f32) -> bool {
+        self.sample_rate == other.sample_rate
+            && self.num_channels() == other.num_channels()
+            && self.samples.iter().zip(&other.samples).all(|(a, b)| {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() <= tol)
+            })
+    }
 
-            for i in 0..new_length {
-                let src_pos = i as f64 * ratio;
-                let src_idx = src_pos as usize;
-                let frac = src_pos - src_idx as f64;
+    /// Maximum absolute sample value across all channels
+    pub fn peak(&self) -> f32 {
+        self.samples
+            .iter()
+            .flatten()
+            .fold(0.0f32, |m, &s| m.max(s.abs()))
+    }
 
-                if src_idx + 1 < src_len {
-                    // Linear interpolation between two samples
-                    dst[i] = (src[src_idx] as f64 * (1.0 - frac) + src[src_idx + 1] as f64 * frac)
-                        as f32;
-                } else if src_idx < src_len {
-                    dst[i] = src[src_idx];
-                }
-            }
+    /// Root-mean-square level across all samples of all channels
+    pub fn rms(&self) -> f32 {
+        let count: usize = self.samples.iter().map(|c| c.len()).sum();
+        if count == 0 {
+            return 0.0;
         }
 
+        let sum_sq: f64 = self
+            .samples
+            .iter()
+            .flatten()
+            .map(|&s| (s as f64) * (s as f64))
+            .sum();
+        (sum_sq / count as f64).sqrt() as f32
+    }
+
+    /// Subtract each channel's mean so it is centered on zero
+    pub fn remove_dc(&self) -> AudioBuffer {
         AudioBuffer {
-            samples: new_samples,
-            sample_rate: target_sample_rate,
+            samples: self
+                .samples
+                .iter()
+                .map(|channel| {
+                    if channel.is_empty() {
+                        return Vec::new();
+                    }
+                    let mean = (channel.iter().map(|&s| s as f64).sum::<f64>()
+                        / channel.len() as f64) as f32;
+                    channel.iter().map(|&s| s - mean).collect()
+                })
+                .collect(),
+            sample_rate: self.sample_rate,
         }
     }
-}
 
-// ============================================================================
-// Audio Effects
-// ============================================================================
+    /// Spectral-subtraction noise gate. The noise profile is the average
+    /// spectrum of the quietest frames; every short-time bin then loses
+    /// `strength` times that profile before the frames are overlap-added back.
+    /// 0 leaves the audio untouched and 1.5 is a solid default.
+    pub fn denoise(&self, strength: f32) -> AudioBuffer {
+        let strength = strength.clamp(0.0, 4.0);
+        if strength == 0.0 {
+            return self.clone();
+        }
+
+        // ~40 ms frames with 75% overlap
+        let size = ((self.sample_rate as f32 * 0.04) as usize)
+            .next_power_of_two()
+            .clamp(256, 4096);
+        let hop = size / 4;
+        let window: Vec<f32> = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+            .collect();
+        let mut planner = FftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(size);
+        let inverse = planner.plan_fft_inverse(size);
+
+        let samples = self
+            .samples
+            .iter()
+            .map(|channel| {
+                if channel.is_empty() {
+                    return Vec::new();
+                }
+
+                // A frame of padding on each side gives every sample full window coverage
+                let mut padded = vec![0.0; size];
+                padded.extend_from_slice(channel);
+                padded.resize(channel.len() + 2 * size, 0.0);
+                let starts: Vec<usize> = (0..=padded.len() - size).step_by(hop).collect();
+                let spectra: Vec<Vec<Complex<f32>>> = starts
+                    .iter()
+                    .map(|&start| {
+                        let mut frame: Vec<Complex<f32>> = padded[start..start + size]
+                            .iter()
+                            .zip(&window)
+                            .map(|(s, w)| Complex::new(s * w, 0.0))
+                            .collect();
+                        forward.process(&mut frame);
+                        frame
+                    })
+                    .collect();
+
+                // Profile the quietest tenth of the frames that lie inside the audio
+                let energy = |i: usize| spectra[i].iter().map(|c| c.norm_sqr()).sum::<f32>();
+                let mut ranked: Vec<(f32, usize)> = starts
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &start)| start >= size && start <= channel.len())
+                    .map(|(i, _)| (energy(i), i))
+                    .collect();
+                if ranked.is_empty() {
+                    ranked = (0..starts.len()).map(|i| (energy(i), i)).collect();
+                }
+                ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let quiet = &ranked[..(ranked.len() / 10).max(1)];
+                let mut profile = vec![0.0f32; size];
+                for &(_, i) in quiet {
+                    for (noise, bin) in profile.iter_mut().zip(&spectra[i]) {
+                        *noise += bin.norm() / quiet.len() as f32;
+                    }
+                }
+
+                let mut output = vec![0.0f32; padded.len()];
+                let mut weight = vec![0.0f32; padded.len()];
+                for (&start, mut spectrum) in starts.iter().zip(spectra) {
+                    for (bin, noise) in spectrum.iter_mut().zip(&profile) {
+                        let magnitude = bin.norm();
+                        let gain = if magnitude > 0.0 {
+                            (1.0 - strength * noise / magnitude).max(0.0)
+                        } else {
+                            0.0
+                        };
+                        *bin *= gain;
+                    }
+                    inverse.process(&mut spectrum);
+                    for (i, (bin, w)) in spectrum.iter().zip(&window).enumerate() {
+                        output[start + i] += bin.re / size as f32 * w;
+                        weight[start + i] += w * w;
+                    }
+                }
+
+                output
+                    .iter()
+                    .zip(&weight)
+                    .skip(size)
+                    .take(channel.len())
+                    .map(|(s, w)| if *w > 1e-6 { s / w } else { 0.0 })
+                    .collect()
+            })
+            .collect();
+
+        AudioBuffer {
+            samples,
+            sample_rate: self.sample_rate,
+        }
+    }
+
+    /// Shorten every channel to at most `length` samples
+    pub fn truncate(&mut self, length: usize) {
+        for channel in self.samples.iter_mut() {
+            channel.truncate(length);
+        }
+    }
+
+    /// Extend every channel with silence to at least `length` samples
+    pub fn pad_to(&mut self, length: usize) {
+        for channel in self.samples.iter_mut() {
+            if channel.len() < length {
+                channel.resize(length, 0.0);
+            }
+        }
+    }
+
+    /// Convert to mono by averaging channels
+    pub fn to_mono(&self) -> Vec<f32> {
+        let len = self.length();
+        let mut mono = vec![0.0; len];
+        let num_channels = self.num_channels() as f32;
+
+        for ch in 0..self.num_channels() {
+            let data = self.get_channel_data(ch);
+            for i in 0..len {
+                mono[i] += data[i] / num_channels;
+            }
+        }
+
+        mono
+    }
+
+    /// Convert to `target_channels` channels. Mono is duplicated into every
+    /// channel, anything is averaged down to mono, and other layouts go
+    /// through stereo (see `to_stereo`) or repeat their channels in order.
+    /// `concat` and `merge` conform every input to the widest one.
+    pub fn conform_channels(&self, target_channels: usize) -> AudioBuffer {
+        let target_channels = target_channels.max(1);
+        let current = self.num_channels();
+        if current == target_channels || current == 0 {
+            return self.clone();
+        }
+
+        let samples = match (current, target_channels) {
+            (_, 1) => vec![self.to_mono()],
+            (1, n) => vec![self.samples[0].clone(); n],
+            (_, 2) => self.to_stereo().samples,
+            (c, n) => (0..n).map(|ch| self.samples[ch % c].clone()).collect(),
+        };
+
+        AudioBuffer {
+            samples,
+            sample_rate: self.sample_rate,
+        }
+    }
+
+    /// Split into one mono buffer per channel
+    pub fn split_channels(&self) -> Vec<AudioBuffer> {
+        self.samples
+            .iter()
+            .map(|channel| AudioBuffer::from_mono(channel.clone(), self.sample_rate))
+            .collect()
+    }
+
+    /// Convert to stereo. Mono is duplicated into both channels, stereo passes
+    /// through, and more channels are averaged down (even channels into left,
+    /// odd channels into right).
+    pub fn to_stereo(&self) -> AudioBuffer {
+        match self.num_channels() {
+            0 => AudioBuffer::new(2, 0, self.sample_rate),
+            1 => AudioBuffer::from_stereo(
+                self.samples[0].clone(),
+                self.samples[0].clone(),
+                self.sample_rate,
+            ),
+            2 => self.clone(),
+            n => {
+                let len = self.length();
+                let mut left = vec![0.0; len];
+                let mut right = vec![0.0; len];
+                let left_count = n.div_ceil(2) as f32;
+                let right_count = (n / 2) as f32;
+
+                for (ch, data) in self.samples.iter().enumerate() {
+                    let (dst, count) = if ch % 2 == 0 {
+                        (&mut left, left_count)
+                    } else {
+                        (&mut right, right_count)
+                    };
+                    for (d, &s) in dst.iter_mut().zip(data) {
+                        *d += s / count;
+                    }
+                }
+
+                AudioBuffer::from_stereo(left, right, self.sample_rate)
+            }
+        }
+    }
+
+    /// Write to WAV file. Returns how many samples had to be clipped to ±1.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let file = std::io::BufWriter::new(File::create(path)?);
+        self.write_wav(file)
+    }
+
+    /// Encode as an in-memory 16-bit WAV file
+    pub fn to_wav_bytes(&self) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.write_wav(&mut cursor)?;
+        Ok(cursor.into_inner())
+    }
+
+    fn write_wav<W: std::io::Write + std::io::Seek>(&self, out: W) -> Result<usize> {
+        let spec = WavSpec {
+            channels: self.num_channels() as u16,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::new(out, spec)?;
+        let len = self.length();
+        let mut clipped = 0;
+
+        for i in 0..len {
+            for ch in 0..self.num_channels() {
+                let sample = self.samples[ch][i].clamp(-1.0, 1.0);
+                if sample.abs() >= 1.0 {
+                    clipped += 1;
+                }
+                let val = (sample * 32767.0) as i16;
+                writer.write_sample(val)?;
+            }
+        }
+
+        writer.finalize()?;
+        Ok(clipped)
+    }
+
+    /// Read from WAV file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = WavReader::open(path)?;
+        let spec = reader.spec();
+        let num_channels = spec.channels as usize;
+        let sample_rate = spec.sample_rate;
+
+        let samples: Vec<f32> = reader
+            .into_samples::<i16>()
+            .filter_map(|s| s.ok())
+            .map(|s| s as f32 / 32768.0)
+            .collect();
+
+        Ok(AudioBuffer {
+            samples: deinterleave(samples, num_channels)?,
+            sample_rate,
+        })
+    }
+
+    /// Read from WAV bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let cursor = Cursor::new(bytes);
+        let reader = WavReader::new(cursor)?;
+        let spec = reader.spec();
+        let num_channels = spec.channels as usize;
+        let sample_rate = spec.sample_rate;
+        let bits_per_sample = spec.bits_per_sample;
+
+        let samples: Vec<f32> = match bits_per_sample {
+            // 24-bit audio is stored in i32, max value is 2^23
+            24 => reader
+                .into_samples::<i32>()
+                .filter_map(|s| s.ok())
+                .map(|s| s as f32 / 8388608.0)
+                .collect(),
+            32 => reader
+                .into_samples::<i32>()
+                .filter_map(|s| s.ok())
+                .map(|s| s as f32 / 2147483648.0)
+                .collect(),
+            // 16-bit, and the fallback for anything else
+            _ => reader
+                .into_samples::<i16>()
+                .filter_map(|s| s.ok())
+                .map(|s| s as f32 / 32768.0)
+                .collect(),
+        };
+
+        Ok(AudioBuffer {
+            samples: deinterleave(samples, num_channels)?,
+            sample_rate,
+        })
+    }
+
+    /// Length `resample(target_sample_rate)` would produce, without resampling
+    pub fn resampled_length(&self, target_sample_rate: u32) -> usize {
+        if self.sample_rate == target_sample_rate {
+            return self.length();
+        }
+        let ratio = self.sample_rate as f64 / target_sample_rate as f64;
+        ((self.length() as f64) / ratio).ceil() as usize
+    }
+
+    /// Resample audio buffer to a target sample rate using linear interpolation
+    pub fn resample(&self, target_sample_rate: u32) -> Self {
+        if self.sample_rate == target_sample_rate {
+            return self.clone();
+        }
+
+        let ratio = self.sample_rate as f64 / target_sample_rate as f64;
+        let new_length = self.resampled_length(target_sample_rate);
+        let num_channels = self.num_channels();
+
+        let mut new_samples = vec![vec![0.0f32; new_length]; num_channels];
+
+        for ch in 0..num_channels {
+            let src = &self.samples[ch];
+            let dst = &mut new_samples[ch];
+
+            for (i, sample) in dst.iter_mut().enumerate() {
+                *sample = interpolate_linear(src, i as f64 * ratio);
+            }
+        }
+
+        AudioBuffer {
+            samples: new_samples,
+            sample_rate: target_sample_rate,
+        }
+    }
+}
+
+/// Split interleaved WAV samples into channels. A final frame that is missing
+/// some of its channels (a truncated file) is completed with silence.
+fn deinterleave(samples: Vec<f32>, num_channels: usize) -> Result<Vec<Vec<f32>>> {
+    if num_channels == 0 {
+        anyhow::bail!("WAV declares zero channels");
+    }
+
+    let frames = samples.len().div_ceil(num_channels);
+    let mut channels = vec![Vec::with_capacity(frames); num_channels];
+    for (i, sample) in samples.into_iter().enumerate() {
+        channels[i % num_channels].push(sample);
+    }
+    for channel in channels.iter_mut() {
+        channel.resize(frames, 0.0);
+    }
+    Ok(channels)
+}
+
+/// Sample at fractional position `pos`, linearly interpolated between its
+/// neighbours. The last sample is held and anything past it is silence.
+fn interpolate_linear(src: &[f32], pos: f64) -> f32 {
+    let idx = pos as usize;
+    let frac = pos - idx as f64;
+
+    if idx + 1 < src.len() {
+        (src[idx] as f64 * (1.0 - frac) + src[idx + 1] as f64 * frac) as f32
+    } else if idx < src.len() {
+        src[idx]
+    } else {
+        0.0
+    }
+}
+
+// ============================================================================
+// Audio Effects
+// ============================================================================
 
 /// Apply echo effect to audio buffer
 pub fn apply_echo(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
@@ -624,6 +1469,56 @@ pub fn apply_echo(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer
     out
 }
 
+/// Stereo delay whose echoes alternate between channels: the first repeat of
+/// the left channel lands on the right, the second back on the left, and so on
+pub fn apply_ping_pong(
+    buffer: &AudioBuffer,
+    delay_secs: f32,
+    decay: f32,
+    repeats: usize,
+) -> AudioBuffer {
+    let input = buffer.to_stereo();
+    let delay_samples = (delay_secs.max(0.0) * input.sample_rate as f32) as usize;
+    let new_length = input.length() + delay_samples * repeats;
+    let mut out = AudioBuffer::new(2, new_length, input.sample_rate);
+
+    for ch in 0..2 {
+        let out_data = out.get_channel_data_mut(ch);
+        out_data[..input.length()].copy_from_slice(input.get_channel_data(ch));
+
+        for r in 1..=repeats {
+            let attenuation = decay.powi(r as i32);
+            let offset = r * delay_samples;
+            // Odd taps come from the opposite channel
+            let source = input.get_channel_data((ch + r) % 2);
+            for (i, &sample) in source.iter().enumerate() {
+                out_data[i + offset] += sample * attenuation;
+            }
+        }
+
+        for sample in out_data.iter_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Widen by delaying the right channel a few milliseconds behind the left
+/// (Haas effect). Longer delays than 40ms are heard as echo, so they clamp.
+pub fn apply_haas(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let input = buffer.to_stereo();
+    let delay_ms = options.delay_ms.unwrap_or(15.0).clamp(0.0, 40.0);
+    let delay_samples = (delay_ms / 1000.0 * input.sample_rate as f32).round() as usize;
+    let len = input.length();
+    let mut out = AudioBuffer::new(2, len + delay_samples, input.sample_rate);
+
+    out.samples[0][..len].copy_from_slice(input.get_channel_data(0));
+    out.samples[1][delay_samples..].copy_from_slice(input.get_channel_data(1));
+
+    out
+}
+
 /// Apply binaural beats effect to audio buffer
 pub fn apply_binaural(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
     let sample_rate = buffer.sample_rate;
@@ -730,63 +1625,663 @@ pub fn apply_pan(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
     out
 }
 
-/// Apply volume scaling to audio buffer
-pub fn apply_volume(buffer: &AudioBuffer, volume: f32) -> AudioBuffer {
+/// Reduce bit depth and sample rate: quantize to `2^bits` levels and hold each
+/// value for `rate_reduction` samples
+pub fn apply_bitcrush(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let bits = options.bits.unwrap_or(8).clamp(1, 16);
+    let hold = options.rate_reduction.unwrap_or(1).max(1) as usize;
+    let half_levels = (1u32 << (bits - 1)) as f32;
     let mut out = buffer.clone();
 
-    for ch in 0..out.num_channels() {
-        let data = out.get_channel_data_mut(ch);
-        for sample in data.iter_mut() {
-            *sample = (*sample * volume).clamp(-1.0, 1.0);
+    for channel in out.samples.iter_mut() {
+        let mut held = 0.0f32;
+        for (i, sample) in channel.iter_mut().enumerate() {
+            if i % hold == 0 {
+                held = ((*sample * half_levels).round() / half_levels).clamp(-1.0, 1.0);
+            }
+            *sample = held;
         }
     }
 
     out
 }
 
-/// Trim silence from beginning and end of audio buffer
-pub fn trim_silence(buffer: &AudioBuffer, threshold: f32, min_silence_ms: f32) -> AudioBuffer {
-    let sample_rate = buffer.sample_rate;
-    let min_samples = ((min_silence_ms / 1000.0) * sample_rate as f32).max(1.0) as usize;
-    let channels = buffer.num_channels();
-    let len = buffer.length();
+/// Multiply each channel by a sine carrier for robotic/metallic textures
+pub fn apply_ring_mod(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let carrier = options.carrier.unwrap_or(50.0).max(0.0);
+    let two_pi = std::f32::consts::PI * 2.0;
+    let phase_inc = two_pi * carrier / buffer.sample_rate as f32;
+    let mut out = buffer.clone();
 
-    // Build per-sample max across channels
-    let mut abs_max = vec![0.0f32; len];
-    for ch in 0..channels {
-        let data = buffer.get_channel_data(ch);
-        for i in 0..len {
-            let v = data[i].abs();
-            if v > abs_max[i] {
-                abs_max[i] = v;
+    for channel in out.samples.iter_mut() {
+        let mut phase = 0.0f32;
+        for sample in channel.iter_mut() {
+            *sample = (*sample * phase.sin()).clamp(-1.0, 1.0);
+            phase += phase_inc;
+            if phase > two_pi {
+                phase -= two_pi;
             }
         }
     }
 
-    // Find start position
-    let find_start = || -> usize {
-        for i in 0..=len.saturating_sub(min_samples) {
-            let mut m = 0.0f32;
-            for j in 0..min_samples {
-                if i + j < len {
-                    let v = abs_max[i + j];
-                    if v > m {
-                        m = v;
-                    }
-                }
-            }
-            if m > threshold {
-                return i;
-            }
+    out
+}
+
+/// Speed of sound in air, m/s
+const SPEED_OF_SOUND: f64 = 343.0;
+
+/// Simulate a source passing by in a straight line, `closest_distance` metres
+/// away at the midpoint of the clip and moving at `speed` m/s. A moving read
+/// pointer follows the changing travel time of the sound, so pitch is raised
+/// while it approaches and lowered as it recedes; level falls off with
+/// distance and the source pans across. The travel time differs at the start
+/// and the end, so the output is slightly longer than the input.
+pub fn apply_doppler(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let sample_rate = buffer.sample_rate as f64;
+    let speed = (options.speed.unwrap_or(20.0) as f64).clamp(0.0, SPEED_OF_SOUND * 0.9);
+    let closest = (options.closest_distance.unwrap_or(5.0) as f64).max(0.1);
+    let direction = if options.direction.unwrap_or(1.0) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let src = buffer.to_mono();
+    let midpoint = src.len() as f64 / sample_rate / 2.0;
+
+    // Position along the path and distance to the listener at time t
+    let along = |t: f64| speed * (t - midpoint);
+    let distance = |t: f64| along(t).hypot(closest);
+    let start_delay = distance(0.0) / SPEED_OF_SOUND;
+
+    let mut left = Vec::with_capacity(src.len());
+    let mut right = Vec::with_capacity(src.len());
+    for i in 0usize.. {
+        let t = i as f64 / sample_rate;
+        let r = distance(t);
+        let read = (t - (r / SPEED_OF_SOUND - start_delay)) * sample_rate;
+        if read >= src.len() as f64 {
+            break;
         }
-        len
+
+        let sample = interpolate_linear(&src, read) * (closest / r) as f32;
+        let pan = (direction * along(t) / r) as f32;
+        let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+        left.push(sample * angle.cos());
+        right.push(sample * angle.sin());
+    }
+
+    AudioBuffer::from_stereo(left, right, buffer.sample_rate)
+}
+
+/// Taps per head-related impulse response
+const HRIR_TAPS: usize = 64;
+/// Spacing of the built-in HRIR grid in azimuth and elevation, in degrees
+const HRIR_GRID_STEP: f32 = 15.0;
+/// Radius of the model head, in metres
+const HEAD_RADIUS: f64 = 0.0875;
+
+/// Impulse responses of the left and right ear for one source direction
+struct Hrir {
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+/// Response of one ear to a source `incidence` radians off the ear's axis,
+/// from the rigid spherical head model (Brown & Duda): the sound arrives later
+/// the further it travels around the head, and the head shadow dulls it.
+/// `None` if the delay does not fit in `HRIR_TAPS` at this sample rate.
+fn spherical_head_ear(incidence: f64, sample_rate: u32) -> Option<Vec<f32>> {
+    let fs = sample_rate as f64;
+    let head_delay = HEAD_RADIUS / SPEED_OF_SOUND;
+    let travel = if incidence < std::f64::consts::FRAC_PI_2 {
+        head_delay * (1.0 - incidence.cos())
+    } else {
+        head_delay * (1.0 + incidence - std::f64::consts::FRAC_PI_2)
     };
+    let delay = travel * fs;
+    let start = delay as usize;
+    if start + 1 >= HRIR_TAPS {
+        return None;
+    }
 
-    // Find end position
-    let find_end = || -> usize {
-        for i in (0..=len.saturating_sub(min_samples)).rev() {
-            let mut m = 0.0f32;
-            for j in 0..min_samples {
+    let mut taps = vec![0.0f32; HRIR_TAPS];
+    taps[start] = (1.0 - delay.fract()) as f32;
+    taps[start + 1] = delay.fract() as f32;
+
+    // Head shadow: a shelf that lifts highs facing the ear and cuts them
+    // behind the head, discretised with the bilinear transform
+    let alpha = 1.05 + 0.95 * (incidence.to_degrees() / 150.0 * 180.0).to_radians().cos();
+    let k = fs * HEAD_RADIUS / SPEED_OF_SOUND;
+    let b0 = ((1.0 + alpha * k) / (1.0 + k)) as f32;
+    let b1 = ((1.0 - alpha * k) / (1.0 + k)) as f32;
+    let a1 = ((1.0 - k) / (1.0 + k)) as f32;
+    let (mut x1, mut y1) = (0.0f32, 0.0f32);
+    for tap in taps.iter_mut() {
+        let y0 = b0 * *tap + b1 * x1 - a1 * y1;
+        x1 = *tap;
+        y1 = y0;
+        *tap = y0;
+    }
+
+    Some(taps)
+}
+
+/// Entry of the built-in HRIR set at a grid direction
+fn grid_hrir(azimuth: f32, elevation: f32, sample_rate: u32) -> Option<Hrir> {
+    let (azimuth, elevation) = (azimuth.to_radians() as f64, elevation.to_radians() as f64);
+    // x points to the right ear, y to the front, z up
+    let right_component = elevation.cos() * azimuth.sin();
+    Some(Hrir {
+        left: spherical_head_ear((-right_component).clamp(-1.0, 1.0).acos(), sample_rate)?,
+        right: spherical_head_ear(right_component.clamp(-1.0, 1.0).acos(), sample_rate)?,
+    })
+}
+
+/// HRIR for any direction, interpolated between the four nearest entries
+/// of the built-in set
+fn hrir_for(azimuth: f32, elevation: f32, sample_rate: u32) -> Option<Hrir> {
+    let azimuth = azimuth.rem_euclid(360.0);
+    let elevation = elevation.clamp(-90.0, 90.0);
+    let az0 = (azimuth / HRIR_GRID_STEP).floor() * HRIR_GRID_STEP;
+    let el0 = ((elevation / HRIR_GRID_STEP).floor() * HRIR_GRID_STEP).min(90.0 - HRIR_GRID_STEP);
+    let az_weight = (azimuth - az0) / HRIR_GRID_STEP;
+    let el_weight = (elevation - el0) / HRIR_GRID_STEP;
+
+    let mut hrir = Hrir {
+        left: vec![0.0; HRIR_TAPS],
+        right: vec![0.0; HRIR_TAPS],
+    };
+    for (az, az_share) in [(az0, 1.0 - az_weight), (az0 + HRIR_GRID_STEP, az_weight)] {
+        for (el, el_share) in [(el0, 1.0 - el_weight), (el0 + HRIR_GRID_STEP, el_weight)] {
+            let weight = az_share * el_share;
+            if weight == 0.0 {
+                continue;
+            }
+            let entry = grid_hrir(az, el, sample_rate)?;
+            for (out, tap) in hrir.left.iter_mut().zip(&entry.left) {
+                *out += weight * tap;
+            }
+            for (out, tap) in hrir.right.iter_mut().zip(&entry.right) {
+                *out += weight * tap;
+            }
+        }
+    }
+
+    Some(hrir)
+}
+
+/// Place the source in 3D for headphone listening by convolving it with the
+/// HRIR for `azimuth`/`elevation`. Falls back to `apply_pan` when there is no
+/// HRIR for the sample rate. Length is preserved.
+pub fn apply_spatial(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let azimuth = options.azimuth.unwrap_or(0.0);
+    let elevation = options.elevation.unwrap_or(0.0);
+    let Some(hrir) = hrir_for(azimuth, elevation, buffer.sample_rate) else {
+        let pan = EffectOptions {
+            pan: Some(azimuth.to_radians().sin()),
+            ..Default::default()
+        };
+        return apply_pan(buffer, &pan);
+    };
+
+    let mono = buffer.to_mono();
+    let convolve = |taps: &[f32]| -> Vec<f32> {
+        (0..mono.len())
+            .map(|i| {
+                let sum: f32 = taps
+                    .iter()
+                    .take(i + 1)
+                    .enumerate()
+                    .map(|(k, tap)| tap * mono[i - k])
+                    .sum();
+                sum.clamp(-1.0, 1.0)
+            })
+            .collect()
+    };
+
+    AudioBuffer::from_stereo(
+        convolve(&hrir.left),
+        convolve(&hrir.right),
+        buffer.sample_rate,
+    )
+}
+
+/// Bands in the `robot` channel vocoder, spread evenly in pitch
+const VOCODER_BANDS: usize = 12;
+
+/// Channel vocoder: the loudness of the speech in each band shapes the same
+/// band of a sawtooth carrier, giving the classic robot voice. Length is
+/// preserved and the result is scaled to the input peak.
+pub fn apply_robot(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let sample_rate = buffer.sample_rate;
+    let carrier_hz = options.carrier.unwrap_or(110.0).clamp(20.0, 2000.0);
+    let len = buffer.length();
+    let carrier: Vec<f32> = (0..len)
+        .map(|i| 2.0 * (i as f32 * carrier_hz / sample_rate as f32).fract() - 1.0)
+        .collect();
+    // Envelope follower with a ~10ms time constant
+    let smoothing = (-1.0 / (0.01 * sample_rate as f32)).exp();
+    let (lowest, highest) = (150.0f32, 6000.0f32);
+    let band_ratio = (highest / lowest).powf(1.0 / VOCODER_BANDS as f32);
+
+    let mut out = AudioBuffer::new(buffer.num_channels(), len, sample_rate);
+    for (input, output) in buffer.samples.iter().zip(out.samples.iter_mut()) {
+        for band in 0..VOCODER_BANDS {
+            let low = lowest * band_ratio.powi(band as i32);
+            let highpass = Biquad::highpass(low, sample_rate);
+            let lowpass = Biquad::lowpass(low * band_ratio, sample_rate);
+            let mut modulator = input.clone();
+            let mut excitation = carrier.clone();
+            for data in [&mut modulator, &mut excitation] {
+                highpass.process(data);
+                lowpass.process(data);
+            }
+
+            let mut envelope = 0.0f32;
+            for ((sample, &m), &c) in output.iter_mut().zip(&modulator).zip(&excitation) {
+                envelope = m.abs() + smoothing * (envelope - m.abs());
+                *sample += c * envelope;
+            }
+        }
+    }
+
+    let output_peak = out.peak();
+    if output_peak == 0.0 {
+        return out;
+    }
+    apply_volume(&out, buffer.peak() / output_peak)
+}
+
+/// Second-order filter section (RBJ audio EQ cookbook, Butterworth Q)
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn new(b: [f32; 3], a: [f32; 3]) -> Self {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+        }
+    }
+
+    /// (cos w0, alpha) for a cutoff, kept below Nyquist
+    fn coefficients(cutoff_hz: f32, sample_rate: u32) -> (f32, f32) {
+        let cutoff = cutoff_hz.clamp(1.0, sample_rate as f32 * 0.45);
+        let w0 = 2.0 * std::f32::consts::PI * cutoff / sample_rate as f32;
+        (w0.cos(), w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2))
+    }
+
+    fn lowpass(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::coefficients(cutoff_hz, sample_rate);
+        Self::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn highpass(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::coefficients(cutoff_hz, sample_rate);
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Band-pass peaking at 0 dB on `center_hz`
+    fn bandpass(center_hz: f32, q: f32, sample_rate: u32) -> Self {
+        let center = center_hz.clamp(1.0, sample_rate as f32 * 0.45);
+        let w0 = 2.0 * std::f32::consts::PI * center / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * q.max(0.1));
+        Self::new(
+            [alpha, 0.0, -alpha],
+            [1.0 + alpha, -2.0 * w0.cos(), 1.0 - alpha],
+        )
+    }
+
+    /// Filter one sample; `state` holds `[x1, x2, y1, y2]` between calls so
+    /// coefficients can change from one sample to the next
+    fn tick(&self, x0: f32, state: &mut [f32; 4]) -> f32 {
+        let [x1, x2, y1, y2] = *state;
+        let y0 = self.b0 * x0 + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+        *state = [x0, x1, y0, y1];
+        y0
+    }
+
+    fn process(&self, data: &mut [f32]) {
+        let mut state = [0.0f32; 4];
+        for sample in data.iter_mut() {
+            *sample = self.tick(*sample, &mut state);
+        }
+    }
+}
+
+/// Keep only `low_hz..high_hz`, with two filter sections (24 dB/octave) per edge
+pub fn apply_bandpass(buffer: &AudioBuffer, low_hz: f32, high_hz: f32) -> AudioBuffer {
+    let highpass = Biquad::highpass(low_hz, buffer.sample_rate);
+    let lowpass = Biquad::lowpass(high_hz, buffer.sample_rate);
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        highpass.process(channel);
+        highpass.process(channel);
+        lowpass.process(channel);
+        lowpass.process(channel);
+    }
+
+    out
+}
+
+/// Cut everything below `cutoff_hz` (24 dB/octave)
+pub fn apply_highpass(buffer: &AudioBuffer, cutoff_hz: f32) -> AudioBuffer {
+    let highpass = Biquad::highpass(cutoff_hz, buffer.sample_rate);
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        highpass.process(channel);
+        highpass.process(channel);
+    }
+
+    out
+}
+
+/// Envelope-following band-pass ("auto-wah"): the louder the input, the higher
+/// the filter centre sweeps between `min_freq` and `max_freq`. Coefficients
+/// are recomputed every sample from a ~20ms envelope. Length is preserved.
+pub fn apply_autowah(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let sample_rate = buffer.sample_rate;
+    let sensitivity = options.sensitivity.unwrap_or(2.0).max(0.0);
+    let min_freq = options.min_freq.unwrap_or(300.0).max(20.0);
+    let max_freq = options.max_freq.unwrap_or(2500.0).max(min_freq);
+    let q = options.q.unwrap_or(2.0);
+    let smoothing = (-1.0 / (0.02 * sample_rate as f32)).exp();
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        let mut envelope = 0.0f32;
+        let mut state = [0.0f32; 4];
+        for sample in channel.iter_mut() {
+            envelope = sample.abs() + smoothing * (envelope - sample.abs());
+            let sweep = (envelope * sensitivity).clamp(0.0, 1.0);
+            let center = min_freq * (max_freq / min_freq).powf(sweep);
+            let filter = Biquad::bandpass(center, q, sample_rate);
+            *sample = filter.tick(*sample, &mut state).clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Overdrive: boost by `drive`, soft-clip with tanh, dull the result with a
+/// low-pass at `tone` Hz and blend it with the dry signal by `mix`. tanh never
+/// exceeds 1, so the output stays bounded however hard it is driven.
+pub fn apply_distortion(buffer: &AudioBuffer, options: &EffectOptions) -> AudioBuffer {
+    let drive = options.drive.unwrap_or(4.0).max(0.0);
+    let tone = options.tone.unwrap_or(5000.0);
+    let mix = options.mix.unwrap_or(1.0).clamp(0.0, 1.0);
+    let lowpass = Biquad::lowpass(tone, buffer.sample_rate);
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        let mut wet: Vec<f32> = channel.iter().map(|s| (s * drive).tanh()).collect();
+        lowpass.process(&mut wet);
+        for (sample, wet) in channel.iter_mut().zip(wet) {
+            *sample = (*sample * (1.0 - mix) + wet * mix).clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Soft-clip with a tanh curve; higher `drive` saturates more
+pub fn apply_saturation(buffer: &AudioBuffer, drive: f32) -> AudioBuffer {
+    let drive = drive.max(0.01);
+    let norm = drive.tanh();
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        for sample in channel.iter_mut() {
+            *sample = (*sample * drive).tanh() / norm;
+        }
+    }
+
+    out
+}
+
+/// Add uniform white noise at `level`, seeded so renders stay reproducible
+pub fn add_noise(buffer: &AudioBuffer, level: f32, seed: u64) -> AudioBuffer {
+    let level = level.abs();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        for sample in channel.iter_mut() {
+            *sample = (*sample + rng.gen_range(-level..=level)).clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Key envelope level at which `<duck>` reaches its full gain reduction
+const DUCK_THRESHOLD: f32 = 0.05;
+
+/// Lower `target` by up to `amount_db` while `key` is sounding. The key level
+/// is followed with separate attack and release times (in milliseconds) so the
+/// gain eases down and back up instead of pumping sample by sample.
+pub fn apply_sidechain_duck(
+    target: &AudioBuffer,
+    key: &AudioBuffer,
+    amount_db: f32,
+    attack_ms: f32,
+    release_ms: f32,
+) -> AudioBuffer {
+    let sample_rate = target.sample_rate as f32;
+    let coefficient = |ms: f32| (-1.0 / (ms.max(0.1) / 1000.0 * sample_rate)).exp();
+    let attack = coefficient(attack_ms);
+    let release = coefficient(release_ms);
+    let amount_db = amount_db.min(0.0);
+
+    // Peak of the key across its channels, silent past its end
+    let key_level = |i: usize| {
+        key.samples
+            .iter()
+            .filter_map(|channel| channel.get(i))
+            .fold(0.0f32, |m, s| m.max(s.abs()))
+    };
+
+    let mut envelope = 0.0f32;
+    let gains: Vec<f32> = (0..target.length())
+        .map(|i| {
+            let level = key_level(i);
+            let coefficient = if level > envelope { attack } else { release };
+            envelope = coefficient * envelope + (1.0 - coefficient) * level;
+            let depth = (envelope / DUCK_THRESHOLD).min(1.0);
+            10f32.powf(amount_db * depth / 20.0)
+        })
+        .collect();
+
+    let mut out = target.clone();
+    for channel in out.samples.iter_mut() {
+        for (sample, gain) in channel.iter_mut().zip(&gains) {
+            *sample *= gain;
+        }
+    }
+
+    out
+}
+
+/// Default `limiter_ceiling_db`
+const LIMITER_CEILING_DB: f32 = -0.3;
+/// How far ahead of a peak the limiter starts turning the gain down
+const LIMITER_ATTACK_MS: f32 = 5.0;
+const LIMITER_RELEASE_MS: f32 = 100.0;
+
+/// Keep every peak at or below `ceiling_db` dBFS by turning the gain down
+/// smoothly around it, instead of clamping samples flat at full scale. The
+/// gain ramps down over `LIMITER_ATTACK_MS` before each peak and recovers
+/// over `LIMITER_RELEASE_MS` afterwards, the same way on every channel.
+pub fn apply_limiter(buffer: &AudioBuffer, ceiling_db: f32) -> AudioBuffer {
+    let ceiling = 10f32.powf(ceiling_db.min(0.0) / 20.0);
+    let sample_rate = buffer.sample_rate as f32;
+    let length = buffer.length();
+
+    // Gain each frame needs on its own to stay under the ceiling
+    let mut gains: Vec<f32> = (0..length)
+        .map(|i| {
+            let peak = buffer
+                .samples
+                .iter()
+                .filter_map(|channel| channel.get(i))
+                .fold(0.0f32, |m, s| m.max(s.abs()));
+            if peak > ceiling {
+                ceiling / peak
+            } else {
+                1.0
+            }
+        })
+        .collect();
+
+    // Backwards: reach each reduction gradually ahead of its peak
+    let attack_step = 1.0 / (LIMITER_ATTACK_MS / 1000.0 * sample_rate).max(1.0);
+    for i in (0..length.saturating_sub(1)).rev() {
+        gains[i] = gains[i].min(gains[i + 1] + attack_step);
+    }
+    // Forwards: ease back up after it, never above what a frame needs
+    let release = (-1.0 / (LIMITER_RELEASE_MS / 1000.0 * sample_rate)).exp();
+    let mut envelope = 1.0f32;
+    for gain in gains.iter_mut() {
+        envelope = gain.min(1.0 - release * (1.0 - envelope));
+        *gain = envelope;
+    }
+
+    let mut out = buffer.clone();
+    for channel in out.samples.iter_mut() {
+        for (sample, gain) in channel.iter_mut().zip(&gains) {
+            *sample *= gain;
+        }
+    }
+
+    out
+}
+
+/// Interpolation shape for `<automation>` envelopes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutomationCurve {
+    Linear,
+    /// Constant ratio per sample, which sounds even for fades
+    Exp,
+}
+
+impl AutomationCurve {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "linear" => Some(AutomationCurve::Linear),
+            "exp" => Some(AutomationCurve::Exp),
+            _ => None,
+        }
+    }
+}
+
+/// Lowest gain an exponential ramp starts from or ends at (-60 dB)
+const EXP_CURVE_FLOOR: f32 = 0.001;
+
+/// Apply a per-sample gain ramping from `from` to `to` across the buffer
+pub fn apply_gain_envelope(
+    buffer: &AudioBuffer,
+    from: f32,
+    to: f32,
+    curve: AutomationCurve,
+) -> AudioBuffer {
+    let from = from.max(0.0);
+    let to = to.max(0.0);
+    let last = buffer.length().saturating_sub(1).max(1) as f32;
+    let gain_at = |i: usize| {
+        let t = i as f32 / last;
+        match curve {
+            AutomationCurve::Linear => from + (to - from) * t,
+            AutomationCurve::Exp => {
+                let start = from.max(EXP_CURVE_FLOOR);
+                let end = to.max(EXP_CURVE_FLOOR);
+                start * (end / start).powf(t)
+            }
+        }
+    };
+    let mut out = buffer.clone();
+
+    for channel in out.samples.iter_mut() {
+        for (i, sample) in channel.iter_mut().enumerate() {
+            *sample = (*sample * gain_at(i)).clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Apply volume scaling to audio buffer
+pub fn apply_volume(buffer: &AudioBuffer, volume: f32) -> AudioBuffer {
+    let mut out = buffer.clone();
+
+    for ch in 0..out.num_channels() {
+        let data = out.get_channel_data_mut(ch);
+        for sample in data.iter_mut() {
+            *sample = (*sample * volume).clamp(-1.0, 1.0);
+        }
+    }
+
+    out
+}
+
+/// Trim silence from beginning and end of audio buffer
+pub fn trim_silence(buffer: &AudioBuffer, threshold: f32, min_silence_ms: f32) -> AudioBuffer {
+    let sample_rate = buffer.sample_rate;
+    let min_samples = ((min_silence_ms / 1000.0) * sample_rate as f32).max(1.0) as usize;
+    let channels = buffer.num_channels();
+    let len = buffer.length();
+
+    // Build per-sample max across channels
+    let mut abs_max = vec![0.0f32; len];
+    for ch in 0..channels {
+        let data = buffer.get_channel_data(ch);
+        for i in 0..len {
+            let v = data[i].abs();
+            if v > abs_max[i] {
+                abs_max[i] = v;
+            }
+        }
+    }
+
+    // Find start position
+    let find_start = || -> usize {
+        for i in 0..=len.saturating_sub(min_samples) {
+            let mut m = 0.0f32;
+            for j in 0..min_samples {
+                if i + j < len {
+                    let v = abs_max[i + j];
+                    if v > m {
+                        m = v;
+                    }
+                }
+            }
+            if m > threshold {
+                return i;
+            }
+        }
+        len
+    };
+
+    // Find end position
+    let find_end = || -> usize {
+        for i in (0..=len.saturating_sub(min_samples)).rev() {
+            let mut m = 0.0f32;
+            for j in 0..min_samples {
                 if i + j < len {
                     let v = abs_max[i + j];
                     if v > m {
@@ -822,6 +2317,104 @@ pub fn trim_silence(buffer: &AudioBuffer, threshold: f32, min_silence_ms: f32) -
     out
 }
 
+// ============================================================================
+// WAV Metadata
+// ============================================================================
+
+/// A named position in the rendered audio, in sample frames
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Marker {
+    pub name: String,
+    pub position: usize,
+}
+
+/// Build the `cue ` chunk and its `LIST/adtl` label chunk for `markers`
+fn cue_chunks(markers: &[Marker]) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut cue = Vec::with_capacity(4 + markers.len() * 24);
+    cue.extend_from_slice(&(markers.len() as u32).to_le_bytes());
+
+    let mut adtl = b"adtl".to_vec();
+    for (i, marker) in markers.iter().enumerate() {
+        let id = i as u32 + 1;
+        let position = marker.position as u32;
+        cue.extend_from_slice(&id.to_le_bytes());
+        cue.extend_from_slice(&position.to_le_bytes());
+        cue.extend_from_slice(b"data");
+        cue.extend_from_slice(&0u32.to_le_bytes()); // chunk start
+        cue.extend_from_slice(&0u32.to_le_bytes()); // block start
+        cue.extend_from_slice(&position.to_le_bytes());
+
+        let mut label = id.to_le_bytes().to_vec();
+        label.extend_from_slice(marker.name.as_bytes());
+        label.push(0);
+        adtl.extend_from_slice(b"labl");
+        adtl.extend_from_slice(&(label.len() as u32).to_le_bytes());
+        adtl.extend_from_slice(&label);
+        if label.len() % 2 == 1 {
+            adtl.push(0);
+        }
+    }
+
+    vec![(*b"cue ", cue), (*b"LIST", adtl)]
+}
+
+/// Text fields written to a WAV `LIST/INFO` chunk
+#[derive(Clone, Debug, Default)]
+pub struct WavInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+/// Build a `LIST/INFO` chunk with `INAM`, `IART` and `ISFT` fields
+fn info_chunk(info: &WavInfo) -> ([u8; 4], Vec<u8>) {
+    let fields = [
+        (b"INAM", info.title.as_deref()),
+        (b"IART", info.artist.as_deref()),
+        (b"ISFT", Some("domgpt")),
+    ];
+
+    let mut list = b"INFO".to_vec();
+    for (id, value) in fields {
+        let Some(value) = value else {
+            continue;
+        };
+        let mut text = value.as_bytes().to_vec();
+        text.push(0);
+        list.extend_from_slice(id);
+        list.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        list.extend_from_slice(&text);
+        if text.len() % 2 == 1 {
+            list.push(0);
+        }
+    }
+
+    (*b"LIST", list)
+}
+
+/// Append RIFF chunks to an existing WAV file and fix up the RIFF size.
+/// hound has no API for extra chunks, so this edits the file after writing.
+fn append_riff_chunks<P: AsRef<Path>>(path: P, chunks: &[([u8; 4], Vec<u8>)]) -> Result<()> {
+    let path = path.as_ref();
+    let mut bytes = fs::read(path)?;
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        anyhow::bail!("{} is not a WAV file", path.display());
+    }
+
+    for (id, data) in chunks {
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    let riff_size = (bytes.len() - 8) as u32;
+    bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
 // ============================================================================
 // Model and Voice Download
 // ============================================================================
@@ -831,7 +2424,7 @@ async fn download_file(
     client: &reqwest::Client,
     url: &str,
     path: &Path,
-    app_handle: Option<&AppHandle>,
+    progress: Option<&dyn ProgressSink>,
     job_id: &str,
     file_name: &str,
 ) -> Result<()> {
@@ -857,817 +2450,7972 @@ async fn download_file(
     downloaded += stream.len() as u64;
     file.write_all(&stream)?;
 
-    if let Some(handle) = app_handle {
+    if let Some(sink) = progress {
         let progress = if total_size > 0 {
             downloaded as f32 / total_size as f32
         } else {
             1.0
         };
-        let _ = handle.emit(
-            "tts-progress",
-            TtsProgressEvent {
-                job_id: job_id.to_string(),
-                message: format!("Downloaded {}", file_name),
-                progress,
-                stage: "download".to_string(),
-            },
-        );
+        sink.report(TtsProgressEvent {
+            job_id: job_id.to_string(),
+            message: format!("Downloaded {}", file_name),
+            progress,
+            stage: "download".to_string(),
+            index: None,
+        });
     }
 
     Ok(())
 }
 
-/// Ensure model files are downloaded
+/// Ensure model files are downloaded. Returns whether
+/// anything had to be fetched.
 pub async fn ensure_model_files(
     onnx_dir: &Path,
-    app_handle: Option<&AppHandle>,
+    progress: Option<&dyn ProgressSink>,
     job_id: &str,
-) -> Result<()> {
-    let model_files = [
-        "duration_predictor.onnx",
-        "text_encoder.onnx",
-        "vector_estimator.onnx",
-        "vocoder.onnx",
-        "tts.json",
-        "unicode_indexer.json",
-    ];
+) -> Result<bool> {
+    let model_files = MODEL_FILES;
 
     let client = reqwest::Client::new();
+    let mut downloaded = false;
 
     for (i, file) in model_files.iter().enumerate() {
         let path = onnx_dir.join(file);
         if !path.exists() {
             let url = format!("{}/onnx/{}", MODEL_REPO, file);
 
-            if let Some(handle) = app_handle {
-                let _ = handle.emit(
-                    "tts-progress",
-                    TtsProgressEvent {
-                        job_id: job_id.to_string(),
-                        message: format!("Downloading model: {}", file),
-                        progress: i as f32 / model_files.len() as f32,
-                        stage: "download".to_string(),
-                    },
-                );
+            if let Some(sink) = progress {
+                sink.report(TtsProgressEvent {
+                    job_id: job_id.to_string(),
+                    message: format!("Downloading model: {}", file),
+                    progress: i as f32 / model_files.len() as f32,
+                    stage: "download".to_string(),
+                    index: None,
+                });
             }
 
-            download_file(&client, &url, &path, app_handle, job_id, file).await?;
+            download_file(&client, &url, &path, progress, job_id, file).await?;
+            downloaded = true;
         }
     }
 
-    Ok(())
+    Ok(downloaded)
 }
 
-/// Ensure voice style files are downloaded
+/// Ensure voice style files are downloaded. Returns whether
+/// anything had to be fetched.
 pub async fn ensure_voice_files(
     voice_dir: &Path,
-    app_handle: Option<&AppHandle>,
+    progress: Option<&dyn ProgressSink>,
     job_id: &str,
-) -> Result<()> {
-    let voice_files = ["F1.json", "F2.json", "M1.json", "M2.json"];
+) -> Result<bool> {
+    let voice_files = VOICE_FILES;
 
     let client = reqwest::Client::new();
+    let mut downloaded = false;
 
     for (i, file) in voice_files.iter().enumerate() {
         let path = voice_dir.join(file);
         if !path.exists() {
             let url = format!("{}/voice_styles/{}", MODEL_REPO, file);
 
-            if let Some(handle) = app_handle {
-                let _ = handle.emit(
-                    "tts-progress",
-                    TtsProgressEvent {
-                        job_id: job_id.to_string(),
-                        message: format!("Downloading voice: {}", file),
-                        progress: i as f32 / voice_files.len() as f32,
-                        stage: "download".to_string(),
-                    },
-                );
+            if let Some(sink) = progress {
+                sink.report(TtsProgressEvent {
+                    job_id: job_id.to_string(),
+                    message: format!("Downloading voice: {}", file),
+                    progress: i as f32 / voice_files.len() as f32,
+                    stage: "download".to_string(),
+                    index: None,
+                });
             }
 
-            download_file(&client, &url, &path, app_handle, job_id, file).await?;
+            download_file(&client, &url, &path, progress, job_id, file).await?;
+            downloaded = true;
         }
     }
 
-    Ok(())
+    Ok(downloaded)
 }
 
-// ============================================================================
-// Script Parser and Audio Generator
-// ============================================================================
+/// Health of one model or voice file as reported by `verify_models`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFileState {
+    Ok,
+    Missing,
+    /// Zero bytes long, typically an interrupted download
+    Empty,
+    /// A JSON file that does not parse
+    Corrupt,
+    /// Was broken and has been downloaded again
+    Repaired,
+}
 
-pub struct ScriptToAudioContext {
-    pub tts: TextToSpeech,
-    pub current_speed: f32,
-    pub current_voice: String,
-    pub sample_rate: u32,
-    pub onnx_dir: PathBuf,
-    pub voice_dir: PathBuf,
-    pub sound_effects_dir: PathBuf,
-    pub resource_dir: Option<PathBuf>,
-    pub app_handle: Option<AppHandle>,
-    pub job_id: String,
-    pub total_nodes: usize,
-    pub current_node: usize,
+#[derive(Clone, Debug, Serialize)]
+pub struct ModelFileStatus {
+    /// Path relative to the model repository, e.g. `onnx/vocoder.onnx`
+    pub file: String,
+    pub state: ModelFileState,
+    /// Why a repair attempt failed
+    pub error: Option<String>,
 }
 
-impl ScriptToAudioContext {
-    pub async fn new(
-        onnx_dir: PathBuf,
-        voice_dir: PathBuf,
-        sound_effects_dir: PathBuf,
-        resource_dir: Option<PathBuf>,
-        app_handle: Option<AppHandle>,
-        job_id: String,
-    ) -> Result<Self> {
-        // Ensure model and voice files exist
-        ensure_model_files(&onnx_dir, app_handle.as_ref(), &job_id).await?;
-        ensure_voice_files(&voice_dir, app_handle.as_ref(), &job_id).await?;
+/// Check that a cached file exists, is non-empty and, for JSON, parses
+fn check_model_file(path: &Path) -> ModelFileState {
+    let Ok(metadata) = fs::metadata(path) else {
+        return ModelFileState::Missing;
+    };
+    if metadata.len() == 0 {
+        return ModelFileState::Empty;
+    }
+    if path.extension().is_some_and(|ext| ext == "json") {
+        let parses = fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+            .is_some();
+        if !parses {
+            return ModelFileState::Corrupt;
+        }
+    }
+    ModelFileState::Ok
+}
 
-        // Load TTS
-        let tts = load_text_to_speech_internal(&onnx_dir)?;
+/// Check every model and voice file and, with `repair`, download the broken
+/// ones again from `base_url`. Returns one status per file.
+pub async fn verify_model_files(
+    onnx_dir: &Path,
+    voice_dir: &Path,
+    base_url: &str,
+    repair: bool,
+    progress: Option<&dyn ProgressSink>,
+    job_id: &str,
+) -> Vec<ModelFileStatus> {
+    let files: Vec<(&Path, &str, &str)> = MODEL_FILES
+        .iter()
+        .map(|file| (onnx_dir, "onnx", *file))
+        .chain(
+            VOICE_FILES
+                .iter()
+                .map(|file| (voice_dir, "voice_styles", *file)),
+        )
+        .collect();
 
-        // Use the actual sample rate from the TTS model config
-        let sample_rate = tts.sample_rate as u32;
+    let client = reqwest::Client::new();
+    let mut statuses = Vec::new();
 
-        Ok(ScriptToAudioContext {
-            tts,
-            current_speed: 1.0,
-            current_voice: "female".to_string(),
-            sample_rate,
-            onnx_dir,
-            voice_dir,
-            sound_effects_dir,
-            resource_dir,
-            app_handle,
-            job_id,
-            total_nodes: 0,
-            current_node: 0,
-        })
-    }
+    for (i, (dir, remote_dir, file)) in files.iter().enumerate() {
+        let name = format!("{}/{}", remote_dir, file);
+        if let Some(sink) = progress {
+            sink.report(TtsProgressEvent {
+                job_id: job_id.to_string(),
+                message: format!("Verifying {}", name),
+                progress: i as f32 / files.len() as f32,
+                stage: "verify".to_string(),
+                index: None,
+            });
+        }
 
-    fn emit_progress(&self, message: &str, stage: &str) {
-        if let Some(ref handle) = self.app_handle {
-            let progress = if self.total_nodes > 0 {
-                0.1 + (self.current_node as f32 / self.total_nodes as f32) * 0.9
-            } else {
-                0.0
-            };
-            let _ = handle.emit(
-                "tts-progress",
-                TtsProgressEvent {
-                    job_id: self.job_id.clone(),
-                    message: message.to_string(),
-                    progress,
-                    stage: stage.to_string(),
-                },
-            );
+        let path = dir.join(file);
+        let mut state = check_model_file(&path);
+        let mut error = None;
+        if repair && state != ModelFileState::Ok {
+            let url = format!("{}/{}", base_url, name);
+            let result = download_file(&client, &url, &path, progress, job_id, file).await;
+            match result.map(|_| check_model_file(&path)) {
+                Ok(ModelFileState::Ok) => state = ModelFileState::Repaired,
+                Ok(after) => error = Some(format!("Downloaded file is still {:?}", after)),
+                Err(e) => error = Some(e.to_string()),
+            }
         }
+
+        statuses.push(ModelFileStatus {
+            file: name,
+            state,
+            error,
+        });
     }
 
-    fn get_voice_style(&self, voice_key: &str) -> Result<Style> {
-        let voices = get_voices();
-        let voice_file = voices.get(voice_key).unwrap_or(&"F1.json");
-        let voice_path = self.voice_dir.join(voice_file);
-        load_voice_style(&[voice_path.to_string_lossy().to_string()], false)
+    if let Some(sink) = progress {
+        sink.report(TtsProgressEvent {
+            job_id: job_id.to_string(),
+            message: "Verification complete".to_string(),
+            progress: 1.0,
+            stage: "verify".to_string(),
+            index: None,
+        });
     }
 
-    fn fetch_sound_effect(&self, effect_key: &str) -> Result<AudioBuffer> {
-        // First try embedded sounds
-        if let Some(bytes) = get_embedded_sound(effect_key) {
-            let buffer = AudioBuffer::from_bytes(bytes)?;
-            // Resample to match TTS sample rate if needed
-            if buffer.sample_rate != self.sample_rate {
-                return Ok(buffer.resample(self.sample_rate));
-            }
-            return Ok(buffer);
-        }
+    statuses
+}
 
-        // Fallback to file-based loading for custom sounds
-        let effects = get_sound_effects();
-        let filename = effects
-            .get(effect_key)
-            .ok_or_else(|| anyhow::anyhow!("Sound effect '{}' not found", effect_key))?;
+/// Cache path for a sound downloaded from `url`
+fn remote_sound_path(sound_effects_dir: &Path, url: &str) -> PathBuf {
+    // FNV-1a, which unlike `DefaultHasher` is stable across Rust releases
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    sound_effects_dir.join(format!("url-{:016x}.wav", hash))
+}
 
-        // Try sound_effects_dir first
-        let path = self.sound_effects_dir.join(filename);
-        if path.exists() {
-            let buffer = AudioBuffer::from_file(&path)?;
-            // Resample to match TTS sample rate if needed
-            if buffer.sample_rate != self.sample_rate {
-                return Ok(buffer.resample(self.sample_rate));
-            }
-            return Ok(buffer);
-        }
+/// Whether an `<audio src>` names something to download rather than a
+/// registered key or local file
+fn is_url(src: &str) -> bool {
+    src.starts_with("http://") || src.starts_with("https://")
+}
 
-        // Try resource_dir as fallback (for bundled assets)
-        if let Some(ref resource_dir) = self.resource_dir {
-            let resource_path = resource_dir.join(filename);
-            if resource_path.exists() {
-                let buffer = AudioBuffer::from_file(&resource_path)?;
-                // Resample to match TTS sample rate if needed
-                if buffer.sample_rate != self.sample_rate {
-                    return Ok(buffer.resample(self.sample_rate));
+/// URLs referenced by `<sound src="...">` or `<audio src="http...">` in a script
+fn sound_urls(script: &str) -> Vec<String> {
+    let document = kuchiki::parse_html().one(preprocess_script(script));
+    let mut urls: Vec<String> = Vec::new();
+    if let Ok(sounds) = document.select("sound, audio") {
+        for sound in sounds {
+            let is_audio = get_tag_name(sound.as_node()).as_deref() == Some("audio");
+            if let Some(src) = get_attr(sound.as_node(), "src") {
+                if (!is_audio || is_url(&src)) && !urls.contains(&src) {
+                    urls.push(src);
                 }
-                return Ok(buffer);
             }
         }
-
-        // If still not found, provide a helpful error message
-        Err(anyhow::anyhow!(
-            "Sound effect file '{}' not found. Checked embedded sounds and: {:?}{}",
-            filename,
-            path,
-            self.resource_dir
-                .as_ref()
-                .map(|r| format!(", {:?}", r.join(filename)))
-                .unwrap_or_default()
-        ))
     }
+    urls
+}
 
-    fn apply_effect(
-        &self,
-        effect_name: &str,
-        buffer: &AudioBuffer,
-        options: &EffectOptions,
-    ) -> AudioBuffer {
-        match effect_name {
-            "echo" => apply_echo(buffer, options),
-            "binaural" => apply_binaural(buffer, options),
-            "pan" => apply_pan(buffer, options),
-            _ => {
-                eprintln!("Unknown effect: {}", effect_name);
-                buffer.clone()
-            }
+/// Download every `<sound src="...">` in the script into the sound cache,
+/// skipping URLs that are already cached. Returns `(url, error)` for each
+/// sound that could not be fetched.
+pub async fn fetch_remote_sounds(
+    script: &str,
+    sound_effects_dir: &Path,
+    progress: Option<&dyn ProgressSink>,
+    job_id: &str,
+) -> Vec<(String, String)> {
+    let client = reqwest::Client::new();
+    let mut failures = Vec::new();
+
+    for url in sound_urls(script) {
+        let path = remote_sound_path(sound_effects_dir, &url);
+        if path.exists() {
+            continue;
         }
-    }
 
-    fn get_preset(&self, effect_name: &str, preset_name: &str) -> Option<EffectOptions> {
-        match effect_name {
-            "echo" => get_echo_presets().get(preset_name).cloned(),
-            "binaural" => get_binaural_presets().get(preset_name).cloned(),
-            "pan" => get_pan_presets().get(preset_name).cloned(),
-            _ => None,
+        let result = download_file(&client, &url, &path, progress, job_id, &url).await;
+        // Never leave an undecodable file behind in the cache
+        let result = result.and_then(|_| AudioBuffer::from_bytes(&fs::read(&path)?).map(|_| ()));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&path);
+            failures.push((url, e.to_string()));
         }
     }
 
-    fn generate_tts(&mut self, text: &str) -> Result<AudioBuffer> {
-        let style = self.get_voice_style(&self.current_voice)?;
-        let speed = (self.current_speed.clamp(0.5, 2.0) - 0.5) / 1.5;
-        let speed = 0.75 + speed * 0.5;
-        let (wav, _duration) =
-            self.tts
-                .call(format!(". {}", text).as_str(), &style, 50, speed, 0.3)?;
+    failures
+}
 
-        let buffer = AudioBuffer::from_mono(wav, self.sample_rate);
+// ============================================================================
+// Shared Model State
+// ============================================================================
 
-        // Trim silence
-        let trimmed = trim_silence(&buffer, 0.002, 20.0);
+/// Lazily-loaded model shared across commands. The model is loaded once per
+/// model directory and requested provider; users serialize on the inner
+/// mutex while running inference. Clones share the same slot.
+pub struct SharedModel<T> {
+    slot: Arc<Mutex<Option<LoadedModel<T>>>>,
+    loads: Arc<AtomicUsize>,
+}
 
-        // Reduce loudness
-        Ok(apply_volume(&trimmed, 0.85))
+/// The model in a `SharedModel` slot and what it was loaded for
+struct LoadedModel<T> {
+    onnx_dir: PathBuf,
+    requested: ExecutionProvider,
+    model: Arc<Mutex<T>>,
+    /// Provider actually in use, which may differ from `requested` after a
+    /// fallback to the CPU
+    provider: ExecutionProvider,
+}
+
+impl<T> Default for SharedModel<T> {
+    fn default() -> Self {
+        SharedModel {
+            slot: Arc::new(Mutex::new(None)),
+            loads: Arc::new(AtomicUsize::new(0)),
+        }
     }
 }
 
-/// Load TTS without GPU option (internal helper)
-fn load_text_to_speech_internal(onnx_dir: &Path) -> Result<TextToSpeech> {
-    use ort::session::Session;
+impl<T> Clone for SharedModel<T> {
+    fn clone(&self) -> Self {
+        SharedModel {
+            slot: self.slot.clone(),
+            loads: self.loads.clone(),
+        }
+    }
+}
 
-    let cfgs = load_cfgs(onnx_dir)?;
+impl<T> SharedModel<T> {
+    /// Return the loaded model, running `load` only if nothing is loaded yet
+    /// or the loaded model came from another `onnx_dir` or `provider`.
+    /// Concurrent callers wait for an in-flight load instead of loading twice.
+    pub fn get_or_load<F>(
+        &self,
+        onnx_dir: &Path,
+        provider: ExecutionProvider,
+        load: F,
+    ) -> Result<(Arc<Mutex<T>>, ExecutionProvider)>
+    where
+        F: FnOnce() -> Result<(T, ExecutionProvider)>,
+    {
+        let mut slot = self
+            .slot
+            .lock()
+            .map_err(|_| anyhow::anyhow!("TTS model lock poisoned"))?;
+
+        if let Some(loaded) = slot.as_ref() {
+            if loaded.onnx_dir == onnx_dir && loaded.requested == provider {
+                return Ok((loaded.model.clone(), loaded.provider));
+            }
+        }
 
-    let dp_path = onnx_dir.join("duration_predictor.onnx");
-    let text_enc_path = onnx_dir.join("text_encoder.onnx");
-    let vector_est_path = onnx_dir.join("vector_estimator.onnx");
-    let vocoder_path = onnx_dir.join("vocoder.onnx");
-    let unicode_indexer_path = onnx_dir.join("unicode_indexer.json");
+        // Jobs still running on a replaced model keep their own handle to it
+        let (model, used_provider) = load()?;
+        self.loads.fetch_add(1, Ordering::SeqCst);
+
+        let model = Arc::new(Mutex::new(model));
+        *slot = Some(LoadedModel {
+            onnx_dir: onnx_dir.to_path_buf(),
+            requested: provider,
+            model: model.clone(),
+            provider: used_provider,
+        });
+        Ok((model, used_provider))
+    }
 
-    let dp_ort = Session::builder()?.commit_from_file(&dp_path)?;
-    let text_enc_ort = Session::builder()?.commit_from_file(&text_enc_path)?;
-    let vector_est_ort = Session::builder()?.commit_from_file(&vector_est_path)?;
-    let vocoder_ort = Session::builder()?.commit_from_file(&vocoder_path)?;
+    /// Number of times the model has actually been loaded
+    pub fn load_count(&self) -> usize {
+        self.loads.load(Ordering::SeqCst)
+    }
+}
 
-    let text_processor = UnicodeProcessor::new(&unicode_indexer_path)?;
+/// Tauri managed state holding the TTS model shared by all jobs
+pub type TtsState = SharedModel<TextToSpeech>;
 
-    Ok(TextToSpeech::new(
-        cfgs,
-        text_processor,
-        dp_ort,
-        text_enc_ort,
-        vector_est_ort,
-        vocoder_ort,
-    ))
+/// Audio for a sound effect registered at runtime: a file path (JSON string)
+/// or raw WAV bytes (JSON array)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SoundSource {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
 }
 
-/// Count nodes in the DOM tree
-fn count_nodes(node: &NodeRef) -> usize {
-    1 + node
-        .children()
-        .map(|child| count_nodes(&child))
-        .sum::<usize>()
+impl SoundSource {
+    fn load(&self) -> Result<AudioBuffer> {
+        match self {
+            SoundSource::Path(path) => AudioBuffer::from_file(path),
+            SoundSource::Bytes(bytes) => AudioBuffer::from_bytes(bytes),
+        }
+    }
 }
 
-/// Get element attribute value
-fn get_attr(node: &NodeRef, name: &str) -> Option<String> {
-    node.as_element()
-        .and_then(|el| el.attributes.borrow().get(name).map(|s| s.to_string()))
+/// Tauri managed state holding sound effects registered by the frontend.
+/// Jobs take a snapshot when they start.
+#[derive(Default)]
+pub struct SoundRegistry {
+    sounds: Mutex<HashMap<String, SoundSource>>,
 }
 
-/// Get element tag name (lowercase)
-fn get_tag_name(node: &NodeRef) -> Option<String> {
-    node.as_element()
-        .map(|el| el.name.local.to_string().to_lowercase())
+impl SoundRegistry {
+    /// Add a sound effect, replacing any previous one with the same key
+    pub fn register(&self, key: String, source: SoundSource) {
+        if let Ok(mut sounds) = self.sounds.lock() {
+            sounds.insert(key, source);
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, SoundSource> {
+        self.sounds.lock().map(|s| s.clone()).unwrap_or_default()
+    }
 }
 
-/// Helper to make a tag self-closing if it has no content
-fn make_tag_self_closing(input: &str, tag_name: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let mut chars = input.chars().peekable();
+/// Tauri managed state holding effect presets saved from the frontend.
+/// Jobs take a snapshot when they start.
+#[derive(Default)]
+pub struct PresetRegistry {
+    presets: Mutex<EffectPresets>,
+}
 
-    while let Some(c) = chars.next() {
-        if c == '<' {
-            // Check if this is our target tag
-            let mut tag_content = String::from("<");
-            let mut found_tag = false;
+impl PresetRegistry {
+    /// Add a preset, replacing any previous one with the same effect and name
+    pub fn register(&self, effect: String, name: String, options: EffectOptions) {
+        if let Ok(mut presets) = self.presets.lock() {
+            presets.entry(effect).or_default().insert(name, options);
+        }
+    }
 
-            // Collect the tag name
-            while let Some(&next_c) = chars.peek() {
-                if next_c.is_whitespace() || next_c == '>' || next_c == '/' {
-                    break;
-                }
-                tag_content.push(chars.next().unwrap());
-            }
+    pub fn snapshot(&self) -> EffectPresets {
+        self.presets.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+}
 
-            if tag_content == format!("<{}", tag_name) {
-                found_tag = true;
-                // Collect rest of opening tag
-                while let Some(&next_c) = chars.peek() {
-                    tag_content.push(chars.next().unwrap());
-                    if next_c == '>' {
-                        break;
-                    }
-                }
+/// Voice style and sound effect files found in the data directories, keyed
+/// by lowercase file stem and mapped to the file name
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AssetScan {
+    pub voices: HashMap<String, String>,
+    pub sounds: HashMap<String, String>,
+}
 
-                // Check if there's an immediate closing tag
-                let mut lookahead = String::new();
-                let closing_tag = format!("</{}>", tag_name);
+impl AssetScan {
+    /// List the `.json` voice styles in `voice_dir` and the `.wav` files in
+    /// `sound_effects_dir`, leaving out downloaded `url-*` sounds
+    pub fn read(voice_dir: &Path, sound_effects_dir: &Path) -> Self {
+        AssetScan {
+            voices: files_by_stem(voice_dir, "json"),
+            sounds: files_by_stem(sound_effects_dir, "wav")
+                .into_iter()
+                .filter(|(key, _)| !key.starts_with("url-"))
+                .collect(),
+        }
+    }
+}
 
-                // Collect potential whitespace and closing tag
-                while let Some(&next_c) = chars.peek() {
-                    if lookahead.len() >= closing_tag.len() + 10 {
-                        break; // Don't look too far ahead
-                    }
-                    if lookahead.ends_with(&closing_tag) {
-                        break;
-                    }
-                    lookahead.push(chars.next().unwrap());
+fn files_by_stem(dir: &Path, extension: &str) -> HashMap<String, String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+        })
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?.to_lowercase();
+            let name = path.file_name()?.to_str()?.to_string();
+            Some((stem, name))
+        })
+        .collect()
+}
 
-                    // If we find non-whitespace that isn't part of closing tag, stop
-                    if !next_c.is_whitespace() && !lookahead.trim_start().starts_with("</") {
-                        break;
-                    }
-                }
+/// Tauri managed state caching what the data directories held when they
+/// were first scanned. `refresh_assets` scans again so files dropped in
+/// while the app runs are found without a restart; jobs take a snapshot
+/// when they start, so a refresh never changes a running render.
+#[derive(Default)]
+pub struct AssetCatalog {
+    scans: Mutex<HashMap<(PathBuf, PathBuf), AssetScan>>,
+}
 
-                if lookahead.trim().is_empty() || lookahead.trim() == format!("</{}>", tag_name) {
-                    // It's an empty tag, make sure it has closing
-                    result.push_str(&tag_content);
-                    if !tag_content.ends_with("/>") {
-                        if !lookahead.contains(&closing_tag) {
-                            result.push_str(&format!("</{}>", tag_name));
-                        } else {
-                            result.push_str(&lookahead);
-                        }
-                    }
-                } else {
-                    // Has content
-                    result.push_str(&tag_content);
-                    result.push_str(&lookahead);
-                }
-            } else {
-                result.push_str(&tag_content);
-            }
+impl AssetCatalog {
+    /// The cached scan of these directories, scanning them on first use
+    pub fn snapshot(&self, voice_dir: &Path, sound_effects_dir: &Path) -> AssetScan {
+        let key = (voice_dir.to_path_buf(), sound_effects_dir.to_path_buf());
+        match self.scans.lock() {
+            Ok(mut scans) => scans
+                .entry(key)
+                .or_insert_with(|| AssetScan::read(voice_dir, sound_effects_dir))
+                .clone(),
+            Err(_) => AssetScan::read(voice_dir, sound_effects_dir),
+        }
+    }
 
-            if !found_tag {
-                continue;
-            }
-        } else {
-            result.push(c);
+    /// Drop every cached scan and scan these directories again
+    pub fn refresh(&self, voice_dir: &Path, sound_effects_dir: &Path) -> AssetScan {
+        let scan = AssetScan::read(voice_dir, sound_effects_dir);
+        if let Ok(mut scans) = self.scans.lock() {
+            scans.clear();
+            scans.insert(
+                (voice_dir.to_path_buf(), sound_effects_dir.to_path_buf()),
+                scan.clone(),
+            );
         }
+        scan
     }
+}
 
-    result
+/// Render jobs allowed to run at once unless changed with
+/// `set_max_concurrent_jobs`
+pub const DEFAULT_MAX_CONCURRENT_JOBS: usize = 2;
+
+/// Tauri managed state bounding how many render jobs run at once. Jobs over
+/// the limit wait in FIFO order (tokio's semaphore is fair).
+pub struct JobQueue {
+    semaphore: tokio::sync::Semaphore,
+    limit: Mutex<usize>,
+    /// Permits to retire as running jobs finish after the limit was lowered
+    excess: AtomicUsize,
+    waiting: AtomicUsize,
 }
 
-/// Preprocess script - replace ellipsis with pause tags and unescape HTML entities
-fn preprocess_script(script: &str) -> String {
-    let mut result = script.to_string();
+impl Default for JobQueue {
+    fn default() -> Self {
+        JobQueue::new(DEFAULT_MAX_CONCURRENT_JOBS)
+    }
+}
 
-    result = make_tag_self_closing(&result, "pause");
-    result = make_tag_self_closing(&result, "sound");
+/// A running slot in the `JobQueue`, released when dropped
+pub struct JobPermit<'a> {
+    queue: &'a JobQueue,
+    permit: Option<tokio::sync::SemaphorePermit<'a>>,
+}
 
-    // Replace ellipsis with .
-    result = result.replace("...", r#"."#);
-    result = result.replace("(pause)", r#"<pause value="0.5"></pause>"#);
+impl Drop for JobPermit<'_> {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        let retire = self
+            .queue
+            .excess
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if retire {
+            permit.forget();
+        }
+    }
+}
 
-    // Unescape HTML entities (kuchiki handles most, but we do some manually for safety)
-    result = result.replace("&quot;", "\"");
-    result = result.replace("&amp;", "&");
-    result = result.replace("&lt;", "<");
-    result = result.replace("&gt;", ">");
+impl JobQueue {
+    pub fn new(max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        JobQueue {
+            semaphore: tokio::sync::Semaphore::new(max_concurrent),
+            limit: Mutex::new(max_concurrent),
+            excess: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+        }
+    }
 
-    result
+    /// Wait for a free slot. If the job has to wait, `on_queued` is called
+    /// first with its 1-based position in the queue.
+    pub async fn acquire<F>(&self, on_queued: F) -> Result<JobPermit<'_>>
+    where
+        F: FnOnce(usize),
+    {
+        let permit = match self.semaphore.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let position = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+                on_queued(position);
+                let permit = self.semaphore.acquire().await;
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                permit.map_err(|_| anyhow::anyhow!("Job queue closed"))?
+            }
+        };
+        Ok(JobPermit {
+            queue: self,
+            permit: Some(permit),
+        })
+    }
+
+    /// Change how many jobs may run at once. Lowering the limit never stops
+    /// running jobs; it takes effect as they finish.
+    pub fn set_limit(&self, max_concurrent: usize) -> Result<()> {
+        let max_concurrent = max_concurrent.max(1);
+        let mut limit = self
+            .limit
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Job queue lock poisoned"))?;
+
+        if max_concurrent > *limit {
+            let added = max_concurrent - *limit;
+            // Cancel pending retirements before handing out new permits
+            let cancelled = self
+                .excess
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    Some(n - n.min(added))
+                })
+                .unwrap_or(0)
+                .min(added);
+            self.semaphore.add_permits(added - cancelled);
+        } else {
+            let removed = *limit - max_concurrent;
+            let forgotten = self.semaphore.forget_permits(removed);
+            self.excess.fetch_add(removed - forgotten, Ordering::SeqCst);
+        }
+
+        *limit = max_concurrent;
+        Ok(())
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.lock().map(|l| *l).unwrap_or(1)
+    }
 }
 
-/// Process a single DOM node and return audio segments
-fn process_node(ctx: &mut ScriptToAudioContext, node: &NodeRef) -> Result<Vec<AudioBuffer>> {
-    ctx.current_node += 1;
-    ctx.emit_progress("Processing script", "generate");
+/// Inference parameters for a single TTS call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SynthesisParams {
+    /// Number of diffusion denoising steps
+    pub total_step: usize,
+    pub speed: f32,
+    /// Scale of the initial diffusion noise (1.0 = model default)
+    pub temperature: f32,
+    /// Silence inserted between chunks the model splits internally
+    pub silence_duration: f32,
+    /// Seed for the initial diffusion noise, drawn from the render's seeded
+    /// generator so a seeded render speaks the same way every time
+    pub noise_seed: u64,
+}
 
-    let mut segments: Vec<AudioBuffer> = Vec::new();
+/// Speech synthesis boundary used by `generate_tts`
+pub trait SpeechEngine: Send {
+    fn sample_rate(&self) -> u32;
 
-    // Handle text nodes
-    if let Some(text_node) = node.as_text() {
-        let text = text_node.borrow().trim().to_string();
-        println!("Text: {}", text);
-        if !text.is_empty() {
-            let audio = ctx.generate_tts(&text)?;
-            segments.push(audio);
+    fn synthesize(
+        &mut self,
+        text: &str,
+        style: &Style,
+        params: &SynthesisParams,
+    ) -> Result<Vec<f32>>;
+}
+
+impl SpeechEngine for TextToSpeech {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    fn synthesize(
+        &mut self,
+        text: &str,
+        style: &Style,
+        params: &SynthesisParams,
+    ) -> Result<Vec<f32>> {
+        self.temperature = params.temperature;
+        self.noise_seed = Some(params.noise_seed);
+        let (wav, _duration) = self.call(
+            text,
+            style,
+            params.total_step,
+            params.speed,
+            params.silence_duration,
+        )?;
+        Ok(wav)
+    }
+}
+
+/// Builds an engine the first time it is needed
+type EngineLoader = Box<dyn FnMut() -> Result<Arc<Mutex<dyn SpeechEngine>>> + Send>;
+
+/// Speech engine that defers loading the real one until the first synthesis,
+/// so scripts without speech never build the ONNX sessions
+pub struct LazyEngine {
+    sample_rate: u32,
+    load: EngineLoader,
+    engine: Option<Arc<Mutex<dyn SpeechEngine>>>,
+}
+
+impl LazyEngine {
+    pub fn new<F>(sample_rate: u32, load: F) -> Self
+    where
+        F: FnMut() -> Result<Arc<Mutex<dyn SpeechEngine>>> + Send + 'static,
+    {
+        LazyEngine {
+            sample_rate,
+            load: Box::new(load),
+            engine: None,
         }
-        return Ok(segments);
     }
 
-    // Handle element nodes
-    if let Some(tag) = get_tag_name(node) {
-        match tag.as_str() {
-            "speed" => {
-                let prev_speed = ctx.current_speed;
-                if let Some(value) = get_attr(node, "value") {
-                    ctx.current_speed = value.parse().unwrap_or(1.0);
-                }
-                for child in node.children() {
-                    segments.extend(process_node(ctx, &child)?);
-                }
-                ctx.current_speed = prev_speed;
+    /// Whether the real engine has been loaded yet
+    pub fn is_loaded(&self) -> bool {
+        self.engine.is_some()
+    }
+}
+
+impl SpeechEngine for LazyEngine {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn synthesize(
+        &mut self,
+        text: &str,
+        style: &Style,
+        params: &SynthesisParams,
+    ) -> Result<Vec<f32>> {
+        let engine = match &self.engine {
+            Some(engine) => engine.clone(),
+            None => {
+                let engine = (self.load)()?;
+                self.engine = Some(engine.clone());
+                engine
+            }
+        };
+        let mut engine = engine
+            .lock()
+            .map_err(|_| anyhow::anyhow!("TTS model lock poisoned"))?;
+        engine.synthesize(text, style, params)
+    }
+}
+
+// ============================================================================
+// Text Segmentation
+// ============================================================================
+
+/// Split text into sentence chunks on `.`, `?`, `!` and newlines.
+/// Decimals and known abbreviations don't end a sentence, and chunks longer
+/// than `max_chars` are split further on word boundaries.
+pub fn split_sentences(text: &str, max_chars: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut sentences: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        if c == '\n' {
+            push_sentence(&mut sentences, &mut current);
+            continue;
+        }
+
+        current.push(c);
+
+        if matches!(c, '.' | '?' | '!') {
+            // Keep trailing punctuation and closing quotes with the sentence
+            while i < chars.len() && matches!(chars[i], '.' | '?' | '!' | '"' | '\'' | ')') {
+                current.push(chars[i]);
+                i += 1;
             }
 
-            "voice" => {
-                let prev_voice = ctx.current_voice.clone();
-                if let Some(value) = get_attr(node, "value") {
-                    let voices = get_voices();
-                    ctx.current_voice = if voices.contains_key(value.as_str()) {
-                        value
+            // Decimals like "3.5" have no whitespace after the period
+            let at_boundary = i >= chars.len() || chars[i].is_whitespace();
+            let last_word = current.split_whitespace().last().unwrap_or("");
+            let is_abbrev = c == '.' && ABBREVIATIONS.contains(&last_word);
+
+            if at_boundary && !is_abbrev {
+                push_sentence(&mut sentences, &mut current);
+            }
+        }
+    }
+    push_sentence(&mut sentences, &mut current);
+
+    let max_chars = max_chars.max(1);
+    let mut chunks = Vec::new();
+    for sentence in sentences {
+        if sentence.chars().count() <= max_chars {
+            chunks.push(sentence);
+            continue;
+        }
+
+        let mut chunk = String::new();
+        for word in sentence.split_whitespace() {
+            if !chunk.is_empty() && chunk.chars().count() + 1 + word.chars().count() > max_chars {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            if !chunk.is_empty() {
+                chunk.push(' ');
+            }
+            chunk.push_str(word);
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+    }
+
+    chunks
+}
+
+/// Whether a chunk ends with sentence-final punctuation (ignoring closing quotes)
+fn ends_sentence(chunk: &str) -> bool {
+    chunk
+        .trim_end_matches(|c: char| matches!(c, '"' | '\'' | ')'))
+        .ends_with(|c: char| matches!(c, '.' | '?' | '!'))
+}
+
+fn push_sentence(sentences: &mut Vec<String>, current: &mut String) {
+    let sentence = current.trim();
+    if !sentence.is_empty() {
+        sentences.push(sentence.to_string());
+    }
+    current.clear();
+}
+
+// ============================================================================
+// Script Parser and Audio Generator
+// ============================================================================
+
+/// Speed of a `<speed from to>` block, stepped once per sentence chunk from
+/// `from` on the first chunk to `to` on the last
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedRamp {
+    pub from: f32,
+    pub to: f32,
+    /// Sentence chunks the block is expected to produce
+    pub chunks: usize,
+    /// Index of the next chunk
+    pub next: usize,
+}
+
+impl SpeedRamp {
+    /// Speed for the next chunk, holding at `to` past the expected count
+    fn next_speed(&mut self) -> f32 {
+        let position = if self.chunks > 1 {
+            (self.next as f32 / (self.chunks - 1) as f32).min(1.0)
+        } else {
+            0.0
+        };
+        self.next += 1;
+        self.from + (self.to - self.from) * position
+    }
+}
+
+pub struct ScriptToAudioContext {
+    pub tts: Arc<Mutex<dyn SpeechEngine>>,
+    pub current_speed: f32,
+    pub current_voice: String,
+    pub sample_rate: u32,
+    pub onnx_dir: PathBuf,
+    pub voice_dir: PathBuf,
+    pub sound_effects_dir: PathBuf,
+    pub resource_dir: Option<PathBuf>,
+    /// Where progress events and warnings are reported, if anywhere
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    pub job_id: String,
+    /// Position of the script being rendered in a batch, tagged onto its
+    /// progress events
+    pub batch_index: Option<usize>,
+    pub total_nodes: usize,
+    /// Nodes processed so far, shared with the contexts forked for overlay parts
+    pub current_node: Arc<AtomicUsize>,
+    /// Share of the progress bar given to the download stage
+    pub download_weight: f32,
+    /// Whether this job had to download files; otherwise the download band
+    /// is skipped and generation starts at 0
+    pub files_downloaded: bool,
+    pub max_chunk_chars: usize,
+    pub chunk_pause_secs: f32,
+    pub current_steps: usize,
+    pub current_temperature: f32,
+    pub sentence_pause_secs: f32,
+    pub paragraph_pause_secs: f32,
+    pub trim_enabled: bool,
+    pub trim_threshold: f32,
+    pub trim_min_silence_ms: f32,
+    pub tts_gain: f32,
+    pub voice_crossfade_ms: f32,
+    /// Fail on unknown tags/attributes instead of warning about them
+    pub strict: bool,
+    /// Position of the audio currently being generated, in sample frames
+    pub elapsed_samples: usize,
+    pub markers: Vec<Marker>,
+    /// Runtime-registered sound effects, consulted before the built-ins
+    pub custom_sounds: HashMap<String, SoundSource>,
+    /// User effect presets from `presets.json`, consulted before the
+    /// built-ins
+    pub effect_presets: EffectPresets,
+    /// Runtime-registered effect presets, consulted before `effect_presets`
+    pub registered_presets: EffectPresets,
+    /// Voice and sound files found in the data directories, beyond the
+    /// built-in keys
+    pub assets: AssetScan,
+    /// Decoded sound effects at `sound_cache_rate`, keyed by effect key
+    pub sound_cache: HashMap<String, AudioBuffer>,
+    pub sound_cache_rate: u32,
+    /// Number of sound effects decoded from their source so far
+    pub sound_decodes: usize,
+    /// Loaded voice styles keyed by file path, with the modification time
+    /// they were read at
+    pub style_cache: HashMap<PathBuf, (Option<std::time::SystemTime>, Style)>,
+    /// Number of voice style files read from disk so far
+    pub style_loads: usize,
+    /// Remove DC offset from sound effects as they are loaded
+    pub remove_dc_on_import: bool,
+    /// Fold sound effects with more than two channels down to stereo as
+    /// they are loaded
+    pub downmix_on_import: bool,
+    /// Expands abbreviations and units before synthesis (None = off)
+    pub text_normalizer: Option<TextNormalizer>,
+    /// Micro-fade the boundaries between top-level segments
+    pub declick: bool,
+    /// Amount (0..1) of seeded random speed variation per text chunk
+    pub humanize: f32,
+    /// Relative speed variation at `humanize = 1.0`
+    pub humanize_range: f32,
+    /// Voice every line is spoken in regardless of `<voice>`, `<speaker>`
+    /// and `<role>` tags
+    pub voice_override: Option<String>,
+    /// Active `<speed from to>` ramp, scaling `current_speed` per chunk
+    pub speed_ramp: Option<SpeedRamp>,
+    /// Part of the signal processing tags are restricted to by `<channel>`
+    pub channel_side: Option<ChannelSide>,
+    /// Voice mixed into `current_voice` and how much of it (0..1)
+    pub current_blend: Option<(String, f32)>,
+    /// Dialogue speaker name (lowercase) -> voice key, from options or `<cast>`
+    pub speakers: HashMap<String, String>,
+    /// Rendering fails once this instant passes
+    pub deadline: Option<Instant>,
+    /// Loops that would take the output past this length fail up front
+    pub max_output_secs: f32,
+    /// Render the parts of an `<overlay>` on separate threads
+    pub parallel_overlay: bool,
+    /// Silence rendered for a script with nothing in it; such scripts fail
+    /// when unset
+    pub empty_silence_secs: Option<f32>,
+    /// Channel layout the finished audio is conformed to
+    pub output_channels: Option<OutputChannels>,
+    /// Sample rate the finished audio is resampled to; rendering itself
+    /// always runs at `sample_rate`
+    pub output_sample_rate: Option<u32>,
+    /// Ceiling in dBFS of the limiter run on the finished audio, if enabled
+    pub limiter_ceiling_db: Option<f32>,
+    /// Keep every rendered `<overlay>` part in `stems`
+    pub capture_stems: bool,
+    pub stems: Vec<Stem>,
+    /// Receives each top-level segment as soon as it is final
+    pub chunk_sink: Option<Box<dyn FnMut(AudioChunk) + Send>>,
+    /// Chunks handed to `chunk_sink` so far in this render
+    pub chunks_streamed: usize,
+    pub rng_seed: u64,
+    pub rng: StdRng,
+    pub last_emit: Option<Instant>,
+    pub last_emitted_progress: f32,
+    pub emitted_progress_events: usize,
+    /// Time spent per operation, reported by `profile`
+    pub timings: BTreeMap<String, Duration>,
+    pub tts_calls: usize,
+    /// Raw model output so far, in sample frames
+    pub tts_samples: usize,
+}
+
+impl ScriptToAudioContext {
+    pub async fn new(
+        paths: &RenderPaths,
+        progress: Option<Arc<dyn ProgressSink>>,
+        job_id: String,
+        provider: ExecutionProvider,
+        tts_state: &TtsState,
+        needs_speech: bool,
+    ) -> Result<Self> {
+        let onnx_dir = paths.onnx_dir.clone();
+        let voice_dir = paths.voice_dir.clone();
+
+        // Only scripts with something to say need the model and voice files
+        let mut files_downloaded = false;
+        if needs_speech {
+            files_downloaded |= ensure_model_files(&onnx_dir, progress.as_deref(), &job_id).await?;
+            files_downloaded |=
+                ensure_voice_files(&voice_dir, progress.as_deref(), &job_id).await?;
+        }
+
+        // The sessions are built (or reused from a previous job) on the first
+        // synthesis; until then the config alone provides the sample rate
+        let sample_rate = load_cfgs(&onnx_dir)
+            .map(|cfgs| cfgs.ae.sample_rate as u32)
+            .unwrap_or(SAMPLE_RATE);
+        let tts = {
+            let tts_state = tts_state.clone();
+            let onnx_dir = onnx_dir.clone();
+            let sink = progress.clone();
+            let job_id = job_id.clone();
+            LazyEngine::new(sample_rate, move || {
+                let (tts, used_provider) = tts_state.get_or_load(&onnx_dir, provider, || {
+                    load_text_to_speech_internal(&onnx_dir, provider)
+                })?;
+                if let Some(ref sink) = sink {
+                    let progress = if files_downloaded {
+                        DEFAULT_DOWNLOAD_WEIGHT
                     } else {
-                        value
+                        0.0
                     };
+                    sink.report(TtsProgressEvent {
+                        job_id: job_id.clone(),
+                        message: format!("Using {} for inference", used_provider.name()),
+                        progress,
+                        stage: "load".to_string(),
+                        index: None,
+                    });
                 }
-                for child in node.children() {
-                    segments.extend(process_node(ctx, &child)?);
-                }
-                ctx.current_voice = prev_voice;
+                Ok(tts as Arc<Mutex<dyn SpeechEngine>>)
+            })
+        };
+
+        let mut ctx = Self::with_engine(
+            Arc::new(Mutex::new(tts)),
+            voice_dir,
+            paths.sound_effects_dir.clone(),
+        )?;
+        ctx.onnx_dir = onnx_dir;
+        ctx.resource_dir = paths.resource_dir.clone();
+        ctx.progress = progress;
+        ctx.job_id = job_id;
+        ctx.files_downloaded = files_downloaded;
+
+        let presets_file = ctx.sound_effects_dir.with_file_name(PRESETS_FILE);
+        let (effect_presets, warnings) = load_effect_presets(&presets_file);
+        ctx.effect_presets = effect_presets;
+        for warning in warnings {
+            ctx.emit_warning(&warning);
+        }
+        Ok(ctx)
+    }
+
+    /// Create a context around an already-loaded speech engine without
+    /// downloading anything
+    pub fn with_engine(
+        tts: Arc<Mutex<dyn SpeechEngine>>,
+        voice_dir: PathBuf,
+        sound_effects_dir: PathBuf,
+    ) -> Result<Self> {
+        // Use the actual sample rate from the TTS model config
+        let sample_rate = tts
+            .lock()
+            .map_err(|_| anyhow::anyhow!("TTS model lock poisoned"))?
+            .sample_rate();
+
+        // Without an explicit seed, randomness is seeded from the job timestamp
+        let rng_seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Ok(ScriptToAudioContext {
+            tts,
+            current_speed: 1.0,
+            current_voice: "female".to_string(),
+            sample_rate,
+            onnx_dir: PathBuf::new(),
+            voice_dir,
+            sound_effects_dir,
+            resource_dir: None,
+            progress: None,
+            job_id: String::new(),
+            batch_index: None,
+            total_nodes: 0,
+            current_node: Arc::new(AtomicUsize::new(0)),
+            download_weight: DEFAULT_DOWNLOAD_WEIGHT,
+            files_downloaded: false,
+            max_chunk_chars: DEFAULT_MAX_CHUNK_CHARS,
+            chunk_pause_secs: DEFAULT_CHUNK_PAUSE_SECS,
+            current_steps: DEFAULT_TTS_STEPS,
+            current_temperature: DEFAULT_TTS_TEMPERATURE,
+            sentence_pause_secs: 0.0,
+            paragraph_pause_secs: 0.0,
+            trim_enabled: true,
+            trim_threshold: DEFAULT_TRIM_THRESHOLD,
+            trim_min_silence_ms: DEFAULT_TRIM_MIN_SILENCE_MS,
+            tts_gain: DEFAULT_TTS_GAIN,
+            voice_crossfade_ms: 0.0,
+            strict: false,
+            elapsed_samples: 0,
+            markers: Vec::new(),
+            custom_sounds: HashMap::new(),
+            effect_presets: EffectPresets::new(),
+            registered_presets: EffectPresets::new(),
+            assets: AssetScan::default(),
+            sound_cache: HashMap::new(),
+            sound_cache_rate: sample_rate,
+            sound_decodes: 0,
+            style_cache: HashMap::new(),
+            style_loads: 0,
+            remove_dc_on_import: false,
+            downmix_on_import: false,
+            text_normalizer: Some(TextNormalizer::default()),
+            declick: false,
+            humanize: 0.0,
+            humanize_range: DEFAULT_HUMANIZE_RANGE,
+            voice_override: None,
+            speed_ramp: None,
+            channel_side: None,
+            current_blend: None,
+            speakers: HashMap::new(),
+            deadline: None,
+            max_output_secs: DEFAULT_MAX_OUTPUT_SECS,
+            parallel_overlay: true,
+            empty_silence_secs: None,
+            output_channels: None,
+            output_sample_rate: None,
+            limiter_ceiling_db: None,
+            capture_stems: false,
+            stems: Vec::new(),
+            chunk_sink: None,
+            chunks_streamed: 0,
+            rng_seed,
+            rng: StdRng::seed_from_u64(rng_seed),
+            last_emit: None,
+            last_emitted_progress: 0.0,
+            emitted_progress_events: 0,
+            timings: BTreeMap::new(),
+            tts_calls: 0,
+            tts_samples: 0,
+        })
+    }
+
+    /// Copy of this context for rendering one overlay part on its own. It
+    /// shares the engine and node counter and gets its own random generator,
+    /// seeded from this one so results do not depend on thread scheduling.
+    fn fork(&mut self) -> ScriptToAudioContext {
+        let rng_seed = self.rng.gen();
+        ScriptToAudioContext {
+            tts: self.tts.clone(),
+            current_speed: self.current_speed,
+            current_voice: self.current_voice.clone(),
+            sample_rate: self.sample_rate,
+            onnx_dir: self.onnx_dir.clone(),
+            voice_dir: self.voice_dir.clone(),
+            sound_effects_dir: self.sound_effects_dir.clone(),
+            resource_dir: self.resource_dir.clone(),
+            progress: self.progress.clone(),
+            job_id: self.job_id.clone(),
+            batch_index: self.batch_index,
+            total_nodes: self.total_nodes,
+            current_node: self.current_node.clone(),
+            download_weight: self.download_weight,
+            files_downloaded: self.files_downloaded,
+            max_chunk_chars: self.max_chunk_chars,
+            chunk_pause_secs: self.chunk_pause_secs,
+            current_steps: self.current_steps,
+            current_temperature: self.current_temperature,
+            sentence_pause_secs: self.sentence_pause_secs,
+            paragraph_pause_secs: self.paragraph_pause_secs,
+            trim_enabled: self.trim_enabled,
+            trim_threshold: self.trim_threshold,
+            trim_min_silence_ms: self.trim_min_silence_ms,
+            tts_gain: self.tts_gain,
+            voice_crossfade_ms: self.voice_crossfade_ms,
+            strict: self.strict,
+            elapsed_samples: self.elapsed_samples,
+            markers: Vec::new(),
+            custom_sounds: self.custom_sounds.clone(),
+            effect_presets: self.effect_presets.clone(),
+            registered_presets: self.registered_presets.clone(),
+            assets: self.assets.clone(),
+            sound_cache: self.sound_cache.clone(),
+            sound_cache_rate: self.sound_cache_rate,
+            sound_decodes: 0,
+            style_cache: self.style_cache.clone(),
+            style_loads: 0,
+            remove_dc_on_import: self.remove_dc_on_import,
+            downmix_on_import: self.downmix_on_import,
+            text_normalizer: self.text_normalizer.clone(),
+            declick: self.declick,
+            humanize: self.humanize,
+            humanize_range: self.humanize_range,
+            voice_override: self.voice_override.clone(),
+            speed_ramp: self.speed_ramp,
+            channel_side: self.channel_side,
+            current_blend: self.current_blend.clone(),
+            speakers: self.speakers.clone(),
+            deadline: self.deadline,
+            max_output_secs: self.max_output_secs,
+            parallel_overlay: self.parallel_overlay,
+            empty_silence_secs: self.empty_silence_secs,
+            output_channels: self.output_channels,
+            output_sample_rate: self.output_sample_rate,
+            limiter_ceiling_db: self.limiter_ceiling_db,
+            capture_stems: self.capture_stems,
+            stems: Vec::new(),
+            // Overlay parts are streamed as one chunk by the parent
+            chunk_sink: None,
+            chunks_streamed: 0,
+            rng_seed,
+            rng: StdRng::seed_from_u64(rng_seed),
+            last_emit: self.last_emit,
+            last_emitted_progress: self.last_emitted_progress,
+            emitted_progress_events: 0,
+            timings: BTreeMap::new(),
+            tts_calls: 0,
+            tts_samples: 0,
+        }
+    }
+
+    /// Take back what a forked context produced: markers, newly decoded
+    /// sounds and progress bookkeeping
+    fn absorb(&mut self, fork: ScriptToAudioContext) {
+        self.markers.extend(fork.markers);
+        self.stems.extend(fork.stems);
+        for (key, buffer) in fork.sound_cache {
+            self.sound_cache.entry(key).or_insert(buffer);
+        }
+        self.sound_decodes += fork.sound_decodes;
+        for (path, entry) in fork.style_cache {
+            self.style_cache.entry(path).or_insert(entry);
+        }
+        self.style_loads += fork.style_loads;
+        for (operation, time) in fork.timings {
+            *self.timings.entry(operation).or_default() += time;
+        }
+        self.tts_calls += fork.tts_calls;
+        self.tts_samples += fork.tts_samples;
+        self.emitted_progress_events += fork.emitted_progress_events;
+        self.last_emitted_progress = self.last_emitted_progress.max(fork.last_emitted_progress);
+    }
+
+    /// Hand segments to the chunk sink, flagging the last one as final if
+    /// `ends_stream` is set
+    fn stream_segments(&mut self, segments: &[AudioBuffer], ends_stream: bool) {
+        let Some(sink) = self.chunk_sink.as_mut() else {
+            return;
+        };
+        for (i, segment) in segments.iter().enumerate() {
+            sink(AudioChunk {
+                index: self.chunks_streamed,
+                is_final: ends_stream && i + 1 == segments.len(),
+                audio: segment.clone(),
+            });
+            self.chunks_streamed += 1;
+        }
+    }
+
+    /// Conform finished audio to the requested output format
+    fn conform_output(&mut self, audio: &AudioBuffer) -> AudioBuffer {
+        let audio = match self.output_channels {
+            Some(channels) => audio.conform_channels(channels.count()),
+            None => audio.clone(),
+        };
+        match self.output_sample_rate {
+            Some(rate) => {
+                let started = Instant::now();
+                let resampled = audio.resample(rate);
+                self.record_time("resample", started);
+                resampled
             }
+            None => audio,
+        }
+    }
 
-            "pause" => {
-                let duration: f32 = get_attr(node, "value")
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(1.0);
-                let silence = AudioBuffer::silence(duration, ctx.sample_rate);
-                segments.push(silence);
-                for child in node.children() {
-                    segments.extend(process_node(ctx, &child)?);
-                }
+    /// Add the time since `started` to `operation`
+    fn record_time(&mut self, operation: &str, started: Instant) {
+        *self.timings.entry(operation.to_string()).or_default() += started.elapsed();
+    }
+
+    /// Timings and counters collected so far. `total_secs` is left for the
+    /// caller, which knows what the whole run covered.
+    pub fn profile(&self) -> RenderProfile {
+        RenderProfile {
+            stages: self
+                .timings
+                .iter()
+                .map(|(operation, time)| (operation.clone(), time.as_secs_f64()))
+                .collect(),
+            tts_calls: self.tts_calls,
+            tts_audio_secs: self.tts_samples as f64 / self.sample_rate as f64,
+            total_secs: 0.0,
+        }
+    }
+
+    /// Reseed the random generator used by `<random>` and friends
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng_seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Emit a generation progress event, throttled to at most one every
+    /// `PROGRESS_INTERVAL` unless progress moved by `PROGRESS_MIN_DELTA`.
+    /// Completion (progress 1.0) is always emitted.
+    fn emit_progress(&mut self, message: &str, stage: &str) {
+        let progress = if self.total_nodes > 0 {
+            let offset = if self.files_downloaded {
+                self.download_weight
+            } else {
+                0.0
+            };
+            let done = self.current_node.load(Ordering::SeqCst);
+            offset + (done as f32 / self.total_nodes as f32) * (1.0 - offset)
+        } else {
+            0.0
+        };
+
+        let now = Instant::now();
+        let delta = (progress - self.last_emitted_progress).abs();
+        let should_emit = match self.last_emit {
+            None => true,
+            Some(last) => {
+                progress >= 1.0
+                    || delta >= PROGRESS_MIN_DELTA
+                    || (delta > 0.0 && now.duration_since(last) >= PROGRESS_INTERVAL)
             }
+        };
+        if !should_emit {
+            return;
+        }
 
-            "overlay" => {
-                let mut parts: Vec<AudioBuffer> = Vec::new();
-                for child in node.children() {
-                    if let Some(child_tag) = get_tag_name(&child) {
-                        if child_tag == "part" {
-                            ctx.current_node += 1;
-                            ctx.emit_progress("Processing overlay part", "generate");
-
-                            let mut part_segments: Vec<AudioBuffer> = Vec::new();
-                            for part_child in child.children() {
-                                part_segments.extend(process_node(ctx, &part_child)?);
-                            }
-                            if !part_segments.is_empty() {
-                                let concatenated = AudioBuffer::concat(&part_segments)?;
-                                parts.push(concatenated);
-                            }
-                        }
-                    }
-                }
-                if !parts.is_empty() {
-                    let merged = AudioBuffer::merge(&parts)?;
-                    segments.push(merged);
-                }
+        self.last_emit = Some(now);
+        self.last_emitted_progress = progress;
+        self.emitted_progress_events += 1;
+
+        if let Some(ref sink) = self.progress {
+            sink.report(TtsProgressEvent {
+                job_id: self.job_id.clone(),
+                message: message.to_string(),
+                progress,
+                stage: stage.to_string(),
+                index: self.batch_index,
+            });
+        }
+    }
+
+    /// Log a warning and emit it as an event at the current progress,
+    /// bypassing throttling
+    fn emit_warning(&self, message: &str) {
+        eprintln!("Warning: {}", message);
+        if let Some(ref sink) = self.progress {
+            sink.report(TtsProgressEvent {
+                job_id: self.job_id.clone(),
+                message: message.to_string(),
+                progress: self.last_emitted_progress,
+                stage: "warning".to_string(),
+                index: self.batch_index,
+            });
+        }
+    }
+
+    /// Fail with a timeout error once the job has run past its deadline
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => anyhow::bail!(
+                "Render timed out after processing {} of {} nodes ({:.0}%)",
+                self.current_node.load(Ordering::SeqCst),
+                self.total_nodes,
+                self.last_emitted_progress * 100.0
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Voice key a `<speaker name>` renders with, if the speaker is known
+    fn speaker_voice(&self, name: &str) -> Option<String> {
+        self.speakers.get(&name.to_lowercase()).cloned()
+    }
+
+    /// Style for the current voice, blended with `current_blend` if set.
+    /// `voice_override` replaces both.
+    fn current_style(&mut self) -> Result<Style> {
+        if let Some(voice) = self.voice_override.clone() {
+            return self.get_voice_style(&voice);
+        }
+        let voice = self.current_voice.clone();
+        let style = self.get_voice_style(&voice)?;
+        match self.current_blend.clone() {
+            Some((blend, amount)) if amount > 0.0 => {
+                Style::blend(&style, &self.get_voice_style(&blend)?, amount)
             }
+            _ => Ok(style),
+        }
+    }
 
-            "sound" => {
-                if let Some(value) = get_attr(node, "value") {
-                    if let Ok(buffer) = ctx.fetch_sound_effect(&value) {
-                        segments.push(buffer);
-                    }
-                }
-                for child in node.children() {
-                    segments.extend(process_node(ctx, &child)?);
-                }
+    /// Style for a voice key, read from disk only the first time it is used
+    /// or after the file changes. Outside strict mode a voice file that fails
+    /// to load falls back to the default voice with a warning, so one bad
+    /// custom voice does not end a long render.
+    fn get_voice_style(&mut self, voice_key: &str) -> Result<Style> {
+        let voices = get_voices();
+        let voice_file = voices
+            .get(voice_key)
+            .map(|file| file.to_string())
+            .or_else(|| self.assets.voices.get(&voice_key.to_lowercase()).cloned())
+            .unwrap_or_else(|| DEFAULT_VOICE_FILE.to_string());
+        let voice_path = self.voice_dir.join(&voice_file);
+
+        match self.load_style_file(&voice_path) {
+            Err(e) if !self.strict && voice_file != DEFAULT_VOICE_FILE => {
+                let message = format!(
+                    "Could not load voice \"{}\", using the default voice: {}",
+                    voice_key, e
+                );
+                self.emit_warning(&message);
+                let default_path = self.voice_dir.join(DEFAULT_VOICE_FILE);
+                let style = self.load_style_file(&default_path)?;
+                // Warn once, until the file changes
+                let modified = fs::metadata(&voice_path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                self.style_cache
+                    .insert(voice_path, (modified, style.clone()));
+                Ok(style)
             }
+            result => result,
+        }
+    }
 
-            "effect" => {
-                let effect_name = get_attr(node, "value").unwrap_or_default();
-                let preset_name = get_attr(node, "preset");
-                let options_attr = get_attr(node, "options").unwrap_or_else(|| "{}".to_string());
+    /// Load a voice style file through the cache
+    fn load_style_file(&mut self, voice_path: &Path) -> Result<Style> {
+        let modified = fs::metadata(&voice_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let Some((cached_at, style)) = self.style_cache.get(voice_path) {
+            if *cached_at == modified {
+                return Ok(style.clone());
+            }
+        }
 
-                let mut options = EffectOptions::default();
+        let style = load_voice_style(&[voice_path.to_string_lossy().to_string()], false)?;
+        self.style_loads += 1;
+        self.style_cache
+            .insert(voice_path.to_path_buf(), (modified, style.clone()));
+        Ok(style)
+    }
 
-                // Load preset if available
-                if let Some(ref preset) = preset_name {
-                    if let Some(preset_opts) = ctx.get_preset(&effect_name, preset) {
-                        options = preset_opts;
-                    }
-                }
+    /// Clean up a freshly decoded sound effect as the import options ask
+    fn prepare_import(&self, mut buffer: AudioBuffer) -> AudioBuffer {
+        if self.remove_dc_on_import {
+            buffer = buffer.remove_dc();
+        }
+        if self.downmix_on_import && buffer.num_channels() > 2 {
+            buffer = buffer.conform_channels(2);
+        }
+        buffer
+    }
+
+    /// Load a `<sound src>` from the download cache filled by `fetch_remote_sounds`
+    fn fetch_remote_sound(&self, url: &str) -> Result<AudioBuffer> {
+        let path = remote_sound_path(&self.sound_effects_dir, url);
+        if !path.exists() {
+            anyhow::bail!("Sound '{}' was not downloaded", url);
+        }
+        let buffer = AudioBuffer::from_bytes(&fs::read(&path)?)?;
+        let buffer = self.prepare_import(buffer);
+        if buffer.sample_rate != self.sample_rate {
+            return Ok(buffer.resample(self.sample_rate));
+        }
+        Ok(buffer)
+    }
+
+    /// Load an SSML `<audio src>`: a downloaded URL, a registered or built-in
+    /// sound key, or a local WAV file, at the context sample rate
+    fn fetch_audio_src(&mut self, src: &str) -> Result<AudioBuffer> {
+        if is_url(src) {
+            return self.fetch_remote_sound(src);
+        }
+        if self.custom_sounds.contains_key(src)
+            || get_embedded_sound(src).is_some()
+            || get_sound_effects().contains_key(src)
+        {
+            return self.fetch_sound_effect(src);
+        }
+
+        let bytes =
+            fs::read(src).with_context(|| format!("Audio file '{}' could not be read", src))?;
+        let buffer = self.prepare_import(AudioBuffer::from_bytes(&bytes)?);
+        Ok(buffer.resample(self.sample_rate))
+    }
+
+    /// Fetch a sound effect at the context sample rate, decoding it only the
+    /// first time it is used in a job
+    fn fetch_sound_effect(&mut self, effect_key: &str) -> Result<AudioBuffer> {
+        if self.sound_cache_rate != self.sample_rate {
+            self.sound_cache.clear();
+            self.sound_cache_rate = self.sample_rate;
+        }
+        if let Some(buffer) = self.sound_cache.get(effect_key) {
+            return Ok(buffer.clone());
+        }
+
+        let started = Instant::now();
+        let buffer = self.load_sound_effect(effect_key)?;
+        let buffer = self.prepare_import(buffer);
+        self.record_time("sound_load", started);
+        self.sound_decodes += 1;
+        self.sound_cache
+            .insert(effect_key.to_string(), buffer.clone());
+        Ok(buffer)
+    }
+
+    fn load_sound_effect(&self, effect_key: &str) -> Result<AudioBuffer> {
+        // Sounds registered by the frontend take precedence
+        if let Some(source) = self.custom_sounds.get(effect_key) {
+            let buffer = source.load()?;
+            if buffer.sample_rate != self.sample_rate {
+                return Ok(buffer.resample(self.sample_rate));
+            }
+            return Ok(buffer);
+        }
+
+        // Then try embedded sounds
+        if let Some(bytes) = get_embedded_sound(effect_key) {
+            let buffer = AudioBuffer::from_bytes(bytes)?;
+            // Resample to match TTS sample rate if needed
+            if buffer.sample_rate != self.sample_rate {
+                return Ok(buffer.resample(self.sample_rate));
+            }
+            return Ok(buffer);
+        }
+
+        // Fallback to file-based loading for custom sounds
+        let effects = get_sound_effects();
+        let filename = effects
+            .get(effect_key)
+            .map(|file| file.to_string())
+            .or_else(|| self.assets.sounds.get(&effect_key.to_lowercase()).cloned())
+            .ok_or_else(|| anyhow::anyhow!("Sound effect '{}' not found", effect_key))?;
+
+        // Try sound_effects_dir first
+        let path = self.sound_effects_dir.join(&filename);
+        if path.exists() {
+            let buffer = AudioBuffer::from_file(&path)?;
+            // Resample to match TTS sample rate if needed
+            if buffer.sample_rate != self.sample_rate {
+                return Ok(buffer.resample(self.sample_rate));
+            }
+            return Ok(buffer);
+        }
+
+        // Try resource_dir as fallback (for bundled assets)
+        if let Some(ref resource_dir) = self.resource_dir {
+            let resource_path = resource_dir.join(&filename);
+            if resource_path.exists() {
+                let buffer = AudioBuffer::from_file(&resource_path)?;
+                // Resample to match TTS sample rate if needed
+                if buffer.sample_rate != self.sample_rate {
+                    return Ok(buffer.resample(self.sample_rate));
+                }
+                return Ok(buffer);
+            }
+        }
+
+        // If still not found, provide a helpful error message
+        Err(anyhow::anyhow!(
+            "Sound effect file '{}' not found. Checked embedded sounds and: {:?}{}",
+            filename,
+            path,
+            self.resource_dir
+                .as_ref()
+                .map(|r| format!(", {:?}", r.join(&filename)))
+                .unwrap_or_default()
+        ))
+    }
+
+    fn apply_effect(
+        &self,
+        effect_name: &str,
+        buffer: &AudioBuffer,
+        options: &EffectOptions,
+    ) -> AudioBuffer {
+        match effect_name {
+            "echo" => apply_echo(buffer, options),
+            "binaural" => apply_binaural(buffer, options),
+            "pan" => apply_pan(buffer, options),
+            "bitcrush" => apply_bitcrush(buffer, options),
+            "ringmod" => apply_ring_mod(buffer, options),
+            "haas" => apply_haas(buffer, options),
+            "robot" => apply_robot(buffer, options),
+            "doppler" => apply_doppler(buffer, options),
+            "spatial" => apply_spatial(buffer, options),
+            "autowah" => apply_autowah(buffer, options),
+            "distortion" => apply_distortion(buffer, options),
+            "denoise" => buffer.denoise(options.strength.unwrap_or(1.5)),
+            "pingpong" => apply_ping_pong(
+                buffer,
+                options.delay.unwrap_or(0.25),
+                options.decay.unwrap_or(0.6),
+                options.repeats.unwrap_or(3) as usize,
+            ),
+            // Composite presets: narrow band-pass, saturation and a little hiss
+            "telephone" => {
+                let filtered = apply_bandpass(buffer, 300.0, 3400.0);
+                add_noise(&apply_saturation(&filtered, 2.0), 0.002, 0)
+            }
+            "radio" => {
+                let filtered = apply_bandpass(buffer, 200.0, 5000.0);
+                add_noise(&apply_saturation(&filtered, 3.0), 0.004, 0)
+            }
+            _ => {
+                eprintln!("Unknown effect: {}", effect_name);
+                buffer.clone()
+            }
+        }
+    }
+
+    /// Effects of `<effect chain="a,b">` in the order they apply, each with
+    /// its entry in the `options` JSON object keyed by effect name. Unknown
+    /// effects fail a strict job and are skipped with a warning otherwise.
+    fn effect_chain(
+        &self,
+        chain: &str,
+        options: Option<&str>,
+    ) -> Result<Vec<(String, EffectOptions)>> {
+        let options: serde_json::Map<String, serde_json::Value> = options
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+
+        let mut steps = Vec::new();
+        for name in chain.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if !KNOWN_EFFECTS.contains(&name) {
+                let message = format!("Unknown effect \"{}\" in chain", name);
+                if self.strict {
+                    anyhow::bail!(message);
+                }
+                self.emit_warning(&message);
+                continue;
+            }
+            let effect_options = options
+                .get(name)
+                .map(|value| EffectOptions::from_json(&value.to_string()))
+                .unwrap_or_default();
+            steps.push((name.to_string(), effect_options));
+        }
+        Ok(steps)
+    }
+
+    fn get_preset(&self, effect_name: &str, preset_name: &str) -> Option<EffectOptions> {
+        [&self.registered_presets, &self.effect_presets]
+            .into_iter()
+            .find_map(|presets| presets.get(effect_name)?.get(preset_name))
+            .cloned()
+            .or_else(|| get_effect_preset(effect_name, preset_name))
+    }
+
+    /// Generate speech for a text node after expanding abbreviations and
+    /// units, e.g. "Dr." to "Doctor" and "5 km" to "5 kilometers"
+    fn generate_tts(&mut self, text: &str) -> Result<AudioBuffer> {
+        match &self.text_normalizer {
+            Some(normalizer) => {
+                let text = normalizer.normalize(text);
+                self.generate_tts_verbatim(&text)
+            }
+            None => self.generate_tts_verbatim(text),
+        }
+    }
+
+    /// Generate speech one sentence chunk at a time. Sentence pauses follow
+    /// sentence-final punctuation; paragraph pauses replace them at blank
+    /// lines.
+    fn generate_tts_verbatim(&mut self, text: &str) -> Result<AudioBuffer> {
+        let style = self.current_style()?;
+
+        let mut segments: Vec<AudioBuffer> = Vec::new();
+        for paragraph in paragraph_break_regex().split(text) {
+            let chunks = split_sentences(paragraph, self.max_chunk_chars);
+            if chunks.is_empty() {
+                continue;
+            }
+
+            if !segments.is_empty() {
+                self.push_silence(&mut segments, self.chunk_pause_secs);
+                self.push_silence(&mut segments, self.paragraph_pause_secs);
+            }
+
+            for (i, chunk) in chunks.iter().enumerate() {
+                if i > 0 {
+                    self.push_silence(&mut segments, self.chunk_pause_secs);
+                    if ends_sentence(&chunks[i - 1]) {
+                        self.push_silence(&mut segments, self.sentence_pause_secs);
+                    }
+                }
+                segments.push(self.generate_tts_chunk(chunk, &style)?);
+            }
+        }
+
+        AudioBuffer::concat(&segments)
+    }
+
+    /// Number of sentence chunks `generate_tts` splits the text under `node`
+    /// into, skipping text that is never spoken: comments, the fallback of
+    /// `<audio>` and all but one `<random>` item
+    fn count_speech_chunks(&self, node: &NodeRef) -> usize {
+        if is_comment(node) {
+            return 0;
+        }
+        match get_tag_name(node).as_deref() {
+            Some("audio") => return 0,
+            // Only one item is spoken; count the longest
+            Some("random") => {
+                return node
+                    .children()
+                    .filter(|child| get_tag_name(child).as_deref() == Some("item"))
+                    .map(|item| self.count_speech_chunks(&item))
+                    .max()
+                    .unwrap_or(0)
+            }
+            _ => {}
+        }
+        if let Some(text) = node.as_text() {
+            let text = text.borrow().trim().to_string();
+            let text = match &self.text_normalizer {
+                Some(normalizer) => normalizer.normalize(&text),
+                None => text,
+            };
+            return paragraph_break_regex()
+                .split(&text)
+                .map(|paragraph| split_sentences(paragraph, self.max_chunk_chars).len())
+                .sum();
+        }
+        node.children()
+            .map(|child| self.count_speech_chunks(&child))
+            .sum()
+    }
+
+    fn push_silence(&self, segments: &mut Vec<AudioBuffer>, duration_secs: f32) {
+        if duration_secs > 0.0 {
+            segments.push(AudioBuffer::silence(duration_secs, self.sample_rate));
+        }
+    }
+
+    fn generate_tts_chunk(&mut self, text: &str, style: &Style) -> Result<AudioBuffer> {
+        let mut current_speed = self.current_speed;
+        if let Some(ramp) = self.speed_ramp.as_mut() {
+            // Relative to the ramp's start, so a nested <emphasis> still applies
+            current_speed *= ramp.next_speed() / ramp.from;
+        }
+        if self.humanize > 0.0 {
+            let range = self.humanize.clamp(0.0, 1.0) * self.humanize_range.abs();
+            current_speed *= 1.0 + self.rng.gen_range(-range..=range);
+        }
+        let speed = (current_speed.clamp(0.5, 2.0) - 0.5) / 1.5;
+        let speed = 0.75 + speed * 0.5;
+        let params = SynthesisParams {
+            total_step: self.current_steps,
+            speed,
+            temperature: self.current_temperature,
+            silence_duration: 0.3,
+            noise_seed: self.rng.gen(),
+        };
+        let wav = self
+            .tts
+            .lock()
+            .map_err(|_| anyhow::anyhow!("TTS model lock poisoned"))?
+            .synthesize(format!(". {}", text).as_str(), style, &params)?;
+
+        let buffer = AudioBuffer::from_mono(wav, self.sample_rate);
+        self.tts_calls += 1;
+        self.tts_samples += buffer.length();
+
+        // Trim silence
+        let trimmed = if self.trim_enabled {
+            trim_silence(&buffer, self.trim_threshold, self.trim_min_silence_ms)
+        } else {
+            buffer
+        };
+
+        // Reduce loudness
+        Ok(apply_volume(&trimmed, self.tts_gain))
+    }
+}
+
+/// Load TTS on the requested execution provider (internal helper).
+/// Returns the provider that was actually used after any fallback.
+fn load_text_to_speech_internal(
+    onnx_dir: &Path,
+    provider: ExecutionProvider,
+) -> Result<(TextToSpeech, ExecutionProvider)> {
+    let cfgs = load_cfgs(onnx_dir)?;
+
+    let dp_path = onnx_dir.join("duration_predictor.onnx");
+    let text_enc_path = onnx_dir.join("text_encoder.onnx");
+    let vector_est_path = onnx_dir.join("vector_estimator.onnx");
+    let vocoder_path = onnx_dir.join("vocoder.onnx");
+    let unicode_indexer_path = onnx_dir.join("unicode_indexer.json");
+
+    // Once a provider falls back to CPU, keep the remaining sessions on CPU too
+    let (builder, provider) = session_builder(provider)?;
+    let dp_ort = builder.commit_from_file(&dp_path)?;
+    let (builder, provider) = session_builder(provider)?;
+    let text_enc_ort = builder.commit_from_file(&text_enc_path)?;
+    let (builder, provider) = session_builder(provider)?;
+    let vector_est_ort = builder.commit_from_file(&vector_est_path)?;
+    let (builder, provider) = session_builder(provider)?;
+    let vocoder_ort = builder.commit_from_file(&vocoder_path)?;
+
+    let text_processor = UnicodeProcessor::new(&unicode_indexer_path)?;
+
+    let tts = TextToSpeech::new(
+        cfgs,
+        text_processor,
+        dp_ort,
+        text_enc_ort,
+        vector_est_ort,
+        vocoder_ort,
+    );
+
+    Ok((tts, provider))
+}
+
+/// Whether a node is an HTML comment or a `<comment>` element, which are
+/// skipped entirely along with everything inside them
+fn is_comment(node: &NodeRef) -> bool {
+    node.as_comment().is_some() || get_tag_name(node).as_deref() == Some("comment")
+}
+
+/// Count nodes in the DOM tree, not counting comments
+fn count_nodes(node: &NodeRef) -> usize {
+    if is_comment(node) {
+        return 0;
+    }
+    1 + node
+        .children()
+        .map(|child| count_nodes(&child))
+        .sum::<usize>()
+}
+
+/// Get element attribute value
+fn get_attr(node: &NodeRef, name: &str) -> Option<String> {
+    node.as_element()
+        .and_then(|el| el.attributes.borrow().get(name).map(|s| s.to_string()))
+}
+
+/// Get element tag name (lowercase)
+fn get_tag_name(node: &NodeRef) -> Option<String> {
+    node.as_element()
+        .map(|el| el.name.local.to_string().to_lowercase())
+}
+
+/// Attributes understood by each script tag, or `None` for unknown tags.
+/// `root`, `html`, `head` and `body` come from parsing and take no attributes.
+fn known_attributes(tag: &str) -> Option<&'static [&'static str]> {
+    let attrs: &'static [&'static str] = match tag {
+        "speed" => &["value", "from", "to"],
+        "pause" | "silence" | "volume" | "quality" => &["value"],
+        "voice" => &["value", "blend", "amount"],
+        "sound" => &["value", "src", "volume", "pan", "repeat"],
+        "audio" => &["src"],
+        "marker" => &["name"],
+        "emphasis" => &["level"],
+        "sequence" => &["shuffle"],
+        "speaker" => &["name"],
+        "role" => &["name", "voice"],
+        "tts" => &[
+            "steps",
+            "temperature",
+            "trim",
+            "trim_threshold",
+            "trim_min_silence_ms",
+            "gain",
+        ],
+        "pauses" => &["sentence", "paragraph"],
+        "overlay" => &["gain"],
+        "part" => &["name", "offset", "gain"],
+        "duck" => &["key", "target", "amount", "attack", "release"],
+        "effect" => &["value", "preset", "options", "chain", "mono"],
+        "channel" => &["side"],
+        "loop" => &["value", "duration", "gap", "crossfade"],
+        "automation" => &["param", "from", "to", "curve"],
+        "random" | "item" | "cast" | "comment" | "raw" | "root" | "html" | "head" | "body"
+        | "whisper" | "shout" | "p" | "s" => &[],
+        _ => return None,
+    };
+    Some(attrs)
+}
+
+/// Render the markup of one overlay part in its own context, returning the
+/// context so the caller can take back its markers and caches
+fn render_part(
+    mut ctx: ScriptToAudioContext,
+    markup: &str,
+) -> Result<(Vec<AudioBuffer>, ScriptToAudioContext)> {
+    let document = kuchiki::parse_html().one(format!("<root>{}</root>", markup));
+    let root = document
+        .select_first("root")
+        .map(|n| n.as_node().clone())
+        .unwrap_or_else(|_| document.clone());
+    let segments = process_children(&mut ctx, &root)?;
+    Ok((segments, ctx))
+}
+
+/// Gather the direct children of an `<overlay>`, splitting out `<duck>`
+/// elements. A self-closing `<duck/>` swallows the parts after it as
+/// children, so those are lifted back up to the overlay level.
+fn collect_overlay_children(node: &NodeRef, children: &mut Vec<NodeRef>, ducks: &mut Vec<NodeRef>) {
+    for child in node.children() {
+        if get_tag_name(&child).as_deref() == Some("duck") {
+            collect_overlay_children(&child, children, ducks);
+            ducks.push(child);
+        } else {
+            children.push(child);
+        }
+    }
+}
+
+/// Collect a description of every unknown tag and unknown attribute in the tree
+fn collect_unknown_markup(node: &NodeRef, issues: &mut Vec<String>) {
+    if is_comment(node) {
+        return;
+    }
+    if let Some(tag) = get_tag_name(node) {
+        match known_attributes(&tag) {
+            Some(known) => {
+                if let Some(el) = node.as_element() {
+                    for (name, _) in el.attributes.borrow().map.iter() {
+                        let attr = name.local.to_string();
+                        if !known.contains(&attr.as_str()) {
+                            issues.push(format!("Unknown attribute \"{}\" on <{}>", attr, tag));
+                        }
+                    }
+                }
+            }
+            None => issues.push(format!("Unknown tag <{}>", tag)),
+        }
+    }
+    for child in node.children() {
+        collect_unknown_markup(&child, issues);
+    }
+}
+
+/// Rewrite `<tag .../>` as `<tag ...></tag>` so the parser does not swallow
+/// the content after it as children
+fn close_void_tag(input: &str, tag_name: &str) -> String {
+    let re = Regex::new(&format!(r"(?i)<{}(\s[^<>]*?)?\s*/>", tag_name)).unwrap();
+    re.replace_all(input, |caps: &regex::Captures| {
+        let attrs = caps.get(1).map_or("", |m| m.as_str());
+        format!("<{}{}></{}>", tag_name, attrs, tag_name)
+    })
+    .into_owned()
+}
+
+/// Helper to make a tag self-closing if it has no content
+fn make_tag_self_closing(input: &str, tag_name: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '<' {
+            // Check if this is our target tag
+            let mut tag_content = String::from("<");
+            let mut found_tag = false;
+
+            // Collect the tag name
+            while let Some(&next_c) = chars.peek() {
+                if next_c.is_whitespace() || next_c == '>' || next_c == '/' {
+                    break;
+                }
+                tag_content.push(chars.next().unwrap());
+            }
+
+            if tag_content == format!("<{}", tag_name) {
+                found_tag = true;
+                // Collect rest of opening tag
+                while let Some(&next_c) = chars.peek() {
+                    tag_content.push(chars.next().unwrap());
+                    if next_c == '>' {
+                        break;
+                    }
+                }
+
+                // Check if there's an immediate closing tag
+                let mut lookahead = String::new();
+                let closing_tag = format!("</{}>", tag_name);
+
+                // Collect potential whitespace and closing tag
+                while let Some(&next_c) = chars.peek() {
+                    if lookahead.len() >= closing_tag.len() + 10 {
+                        break; // Don't look too far ahead
+                    }
+                    if lookahead.ends_with(&closing_tag) {
+                        break;
+                    }
+                    lookahead.push(chars.next().unwrap());
+
+                    // If we find non-whitespace that isn't part of closing tag, stop
+                    if !next_c.is_whitespace() && !lookahead.trim_start().starts_with("</") {
+                        break;
+                    }
+                }
+
+                if lookahead.trim().is_empty() || lookahead.trim() == format!("</{}>", tag_name) {
+                    // It's an empty tag, make sure it has closing
+                    result.push_str(&tag_content);
+                    if !tag_content.ends_with("/>") {
+                        if !lookahead.contains(&closing_tag) {
+                            result.push_str(&format!("</{}>", tag_name));
+                        } else {
+                            result.push_str(&lookahead);
+                        }
+                    }
+                } else {
+                    // Has content
+                    result.push_str(&tag_content);
+                    result.push_str(&lookahead);
+                }
+            } else {
+                result.push_str(&tag_content);
+            }
+
+            if !found_tag {
+                continue;
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Expand inline bracket directives in plain text into tags:
+///
+/// - `[pause 0.5]` -> `<pause value="0.5"></pause>`
+/// - `[sound beep]` -> `<sound value="beep"></sound>`
+/// - `[marker intro]` -> `<marker name="intro"></marker>`
+///
+/// Brackets inside tag markup are left alone, `\[` produces a literal `[`,
+/// and anything that is not a recognised directive is kept as written.
+fn expand_bracket_directives(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut in_tag = false;
+    let mut rest = input;
+
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            '\\' if !in_tag && rest.starts_with('[') => {
+                result.push('[');
+                rest = &rest[1..];
+                continue;
+            }
+            '[' if !in_tag => {
+                let directive = rest
+                    .find(|c| matches!(c, ']' | '[' | '<' | '\n'))
+                    .filter(|&end| rest[end..].starts_with(']'))
+                    .and_then(|end| bracket_directive(&rest[..end]).map(|tag| (end, tag)));
+                if let Some((end, tag)) = directive {
+                    result.push_str(&tag);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+
+    result
+}
+
+/// Tag for the contents of a `[...]` directive, if it is one
+fn bracket_directive(contents: &str) -> Option<String> {
+    let mut parts = contents.split_whitespace();
+    let name = parts.next()?;
+    let arg = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    match name {
+        "pause" => {
+            let secs: f32 = arg.parse().ok()?;
+            Some(format!(r#"<pause value="{}"></pause>"#, secs))
+        }
+        "sound" => Some(format!(r#"<sound value="{}"></sound>"#, arg)),
+        "marker" => Some(format!(r#"<marker name="{}"></marker>"#, arg)),
+        _ => None,
+    }
+}
+
+/// Preprocess script - replace ellipsis with pause tags. HTML entities are
+/// left for the parser so they are decoded exactly once.
+fn preprocess_script(script: &str) -> String {
+    // Swap <raw> regions for placeholders so nothing below touches them
+    let mut raw_regions: Vec<String> = Vec::new();
+    let protected = raw_regex().replace_all(script, |caps: &regex::Captures| {
+        raw_regions.push(caps[1].to_string());
+        format!("\u{E000}{}\u{E000}", raw_regions.len() - 1)
+    });
+
+    let mut result = expand_bracket_directives(&protected);
+    result = escape_stray_markup(&result);
+
+    result = make_tag_self_closing(&result, "pause");
+    result = make_tag_self_closing(&result, "sound");
+    result = close_void_tag(&result, "silence");
+    result = close_void_tag(&result, "audio");
+
+    // Replace ellipsis with .
+    result = result.replace("...", r#"."#);
+    result = result.replace("(pause)", r#"<pause value="0.5"></pause>"#);
+
+    // Restore raw regions, escaped so the HTML parser yields them verbatim
+    for (i, raw) in raw_regions.iter().enumerate() {
+        let escaped = raw
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        result = result.replace(
+            &format!("\u{E000}{}\u{E000}", i),
+            &format!("<raw>{}</raw>", escaped),
+        );
+    }
+
+    result
+}
+
+/// Matches a tag (`<name ...>`, `</name>`, `<name/>`) or an HTML comment at
+/// the start of the input, capturing the tag name
+fn tag_shape_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(concat!(
+            r#"^(?:</?([A-Za-z][\w-]*)(?:\s(?:[^<>"']|"[^"]*"|'[^']*')*)?/?>"#,
+            r"|(?s:<!--.*?-->))"
+        ))
+        .unwrap()
+    })
+}
+
+/// Matches an entity reference such as `&amp;` or `&#39;` at the start of
+/// the input
+fn entity_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^&(?:#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z][A-Za-z0-9]*);").unwrap())
+}
+
+/// Length of the script tag or HTML comment at the start of `input`, if any.
+/// Tag-shaped text naming anything but a script tag is not markup.
+fn markup_len(input: &str) -> Option<usize> {
+    let caps = tag_shape_regex().captures(input)?;
+    match caps.get(1) {
+        Some(name) if known_attributes(&name.as_str().to_lowercase()).is_none() => None,
+        _ => caps.get(0).map(|m| m.end()),
+    }
+}
+
+/// Escape `<`, `>` and `&` in plain text so the HTML parser reads them as
+/// text. Only script tags and HTML comments are kept as markup, so `x<y`,
+/// `vector<int>` or `a<b>c` are spoken as written; entity references such as
+/// `&amp;` are kept for the parser to decode.
+fn escape_stray_markup(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        let keep = match c {
+            '<' => markup_len(rest),
+            '&' => entity_regex().find(rest).map(|m| m.end()),
+            _ => None,
+        };
+        if let Some(end) = keep {
+            result.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        match c {
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '&' => result.push_str("&amp;"),
+            c => result.push(c),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+/// Report tags outside `<raw>` that `escape_stray_markup` turns into text
+/// because they name no script tag
+fn collect_stray_tags(script: &str, issues: &mut Vec<String>) {
+    let masked = raw_regex().replace_all(script, "");
+    for (offset, _) in masked.match_indices('<') {
+        let Some(caps) = tag_shape_regex().captures(&masked[offset..]) else {
+            continue;
+        };
+        let Some(name) = caps.get(1) else {
+            continue;
+        };
+        let tag = name.as_str().to_lowercase();
+        if !caps[0].starts_with("</") && known_attributes(&tag).is_none() {
+            issues.push(format!("Unknown tag <{}> is read as text", tag));
+        }
+    }
+}
+
+/// Matches `<raw>...</raw>` regions, capturing their contents
+fn raw_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?is)<raw>(.*?)</raw>").unwrap())
+}
+
+/// Matches a blank line, where a paragraph ends
+fn paragraph_break_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\n\s*\n").unwrap())
+}
+
+/// Process the children of a node in document order. When voice crossfading
+/// is enabled, adjacent children rendered under different voices are joined
+/// with an equal-power crossfade instead of a hard cut.
+fn process_children(ctx: &mut ScriptToAudioContext, node: &NodeRef) -> Result<Vec<AudioBuffer>> {
+    process_children_streaming(ctx, node, false)
+}
+
+/// `process_children`, optionally passing each segment to the context's chunk
+/// sink once no later crossfade can change it
+fn process_children_streaming(
+    ctx: &mut ScriptToAudioContext,
+    node: &NodeRef,
+    stream: bool,
+) -> Result<Vec<AudioBuffer>> {
+    let mut segments: Vec<AudioBuffer> = Vec::new();
+    let mut prev_voice: Option<String> = None;
+    let mut streamed = 0;
+
+    for child in node.children() {
+        let child_voice = match get_tag_name(&child).as_deref() {
+            Some("voice") => get_attr(&child, "value"),
+            Some("speaker") => get_attr(&child, "name").and_then(|n| ctx.speaker_voice(&n)),
+            _ => None,
+        }
+        .filter(|_| ctx.voice_override.is_none())
+        .unwrap_or_else(|| ctx.current_voice.clone());
+
+        let mut child_segments = process_node(ctx, &child)?;
+        if child_segments.is_empty() {
+            continue;
+        }
+
+        let voice_changed = prev_voice.as_ref().is_some_and(|v| *v != child_voice);
+        if voice_changed && ctx.voice_crossfade_ms > 0.0 {
+            if let Some(last) = segments.pop() {
+                let first = child_segments.remove(0);
+                let fade_secs = ctx.voice_crossfade_ms / 1000.0;
+                let separate_length = last.length() + first.length();
+                let faded = AudioBuffer::concat_crossfade(&[last, first], fade_secs)?;
+                // Later markers move back by the overlap
+                let overlap = separate_length.saturating_sub(faded.length());
+                ctx.elapsed_samples = ctx.elapsed_samples.saturating_sub(overlap);
+                segments.push(faded);
+            }
+        }
+
+        segments.extend(child_segments);
+        prev_voice = Some(child_voice);
+
+        if stream {
+            // The last segment may still be crossfaded into the next child
+            let ready = segments.len() - 1;
+            if ready > streamed {
+                ctx.stream_segments(&segments[streamed..ready], false);
+                streamed = ready;
+            }
+        }
+    }
+
+    if stream {
+        ctx.stream_segments(&segments[streamed..], true);
+    }
+
+    Ok(segments)
+}
+
+/// Render `node`'s children and hand their audio, joined into one buffer,
+/// to `process`. Returns nothing when the children produce no audio.
+///
+/// Every tag that processes audio goes through here, which fixes the order
+/// of nested tags: the innermost tag processes the speech first and each
+/// enclosing tag works on the result, so
+/// `<volume value="0.5"><effect value="echo">...</effect></volume>` echoes and
+/// then halves.
+///
+/// Inside `<channel side>` only that part of the audio is processed and the
+/// rest passes through dry.
+fn process_wrapped<F>(
+    ctx: &mut ScriptToAudioContext,
+    node: &NodeRef,
+    process: F,
+) -> Result<Option<AudioBuffer>>
+where
+    F: FnOnce(&mut ScriptToAudioContext, AudioBuffer) -> Result<AudioBuffer>,
+{
+    let child_segments = process_children(ctx, node)?;
+    if child_segments.is_empty() {
+        return Ok(None);
+    }
+    let target = AudioBuffer::concat(&child_segments)?;
+    match ctx.channel_side {
+        Some(side) => side.apply(&target, |part| process(ctx, part)).map(Some),
+        None => process(ctx, target).map(Some),
+    }
+}
+
+/// Fail if audio ending at `end_samples` would run past the job's output
+/// cap, naming `node` with whichever of `attrs` it sets
+fn check_output_cap(
+    ctx: &ScriptToAudioContext,
+    node: &NodeRef,
+    attrs: &[&str],
+    end_samples: f64,
+) -> Result<()> {
+    let projected_secs = end_samples / ctx.sample_rate as f64;
+    if projected_secs <= ctx.max_output_secs as f64 {
+        return Ok(());
+    }
+    let tag = get_tag_name(node).unwrap_or_default();
+    let attrs = attrs
+        .iter()
+        .filter_map(|name| get_attr(node, name).map(|v| format!(" {}=\"{}\"", name, v)))
+        .collect::<String>();
+    anyhow::bail!(
+        "<{}{}> would make the audio {:.0}s long, over the {:.0}s limit",
+        tag,
+        attrs,
+        projected_secs,
+        ctx.max_output_secs
+    );
+}
+
+/// Part of a stereo signal selected by `<channel side="...">`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelSide {
+    Left,
+    Right,
+    /// The sum of both channels, (L + R) / 2
+    Mid,
+    /// The difference between the channels, (L - R) / 2
+    Side,
+}
+
+impl ChannelSide {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "left" => Some(ChannelSide::Left),
+            "right" => Some(ChannelSide::Right),
+            "mid" => Some(ChannelSide::Mid),
+            "side" => Some(ChannelSide::Side),
+            _ => None,
+        }
+    }
+
+    /// Run `process` on this part of `buffer` alone and recombine it with the
+    /// untouched rest. Mono input is promoted to stereo first, so the result
+    /// is always stereo; a part that grows (an echo tail) pads the rest.
+    fn apply<F>(self, buffer: &AudioBuffer, process: F) -> Result<AudioBuffer>
+    where
+        F: FnOnce(AudioBuffer) -> Result<AudioBuffer>,
+    {
+        let rate = buffer.sample_rate;
+        let channels = buffer.conform_channels(2).split_channels();
+        let (left, right) = (&channels[0].samples[0], &channels[1].samples[0]);
+        let (first, second): (Vec<f32>, Vec<f32>) = match self {
+            ChannelSide::Left | ChannelSide::Right => (left.clone(), right.clone()),
+            ChannelSide::Mid | ChannelSide::Side => (
+                left.iter().zip(right).map(|(l, r)| (l + r) / 2.0).collect(),
+                left.iter().zip(right).map(|(l, r)| (l - r) / 2.0).collect(),
+            ),
+        };
+        let selects_first = matches!(self, ChannelSide::Left | ChannelSide::Mid);
+        let (part, mut rest) = if selects_first {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let mut processed = process(AudioBuffer::from_mono(part, rate))?;
+        if processed.sample_rate != rate {
+            processed = processed.resample(rate);
+        }
+        let mut part = processed.conform_channels(1).samples.swap_remove(0);
+        let length = part.len().max(rest.len());
+        part.resize(length, 0.0);
+        rest.resize(length, 0.0);
+
+        let (first, second) = if selects_first {
+            (part, rest)
+        } else {
+            (rest, part)
+        };
+        let (left, right) = match self {
+            ChannelSide::Left | ChannelSide::Right => (first, second),
+            ChannelSide::Mid | ChannelSide::Side => (
+                first.iter().zip(&second).map(|(m, s)| m + s).collect(),
+                first.iter().zip(&second).map(|(m, s)| m - s).collect(),
+            ),
+        };
+        Ok(AudioBuffer::from_stereo(left, right, rate))
+    }
+}
+
+/// Process a single DOM node and return audio segments.
+///
+/// Tags that change how text is spoken (`<speed>`, `<voice>`, `<tts>`, ...)
+/// only set context for their children and so act at synthesis time, before
+/// any processing. Tags that process audio (`<volume>`, `<effect>`,
+/// `<emphasis>`, ...) use `process_wrapped` and apply from the inside out.
+fn process_node(ctx: &mut ScriptToAudioContext, node: &NodeRef) -> Result<Vec<AudioBuffer>> {
+    // Author annotations never render and don't count towards progress
+    if is_comment(node) {
+        return Ok(Vec::new());
+    }
+
+    ctx.current_node.fetch_add(1, Ordering::SeqCst);
+    ctx.check_deadline()?;
+    ctx.emit_progress("Processing script", "generate");
+
+    let start_samples = ctx.elapsed_samples;
+    let mut segments: Vec<AudioBuffer> = Vec::new();
+
+    // Handle text nodes
+    if let Some(text_node) = node.as_text() {
+        let text = text_node.borrow().trim().to_string();
+        println!("Text: {}", text);
+        if !text.is_empty() {
+            let started = Instant::now();
+            let audio = ctx.generate_tts(&text)?;
+            // Attributed to the tag the text sits in
+            let tag = node.parent().and_then(|parent| get_tag_name(&parent));
+            ctx.record_time(&format!("tts:{}", tag.unwrap_or_default()), started);
+            ctx.elapsed_samples += audio.length();
+            segments.push(audio);
+        }
+        return Ok(segments);
+    }
+
+    // Handle element nodes
+    if let Some(tag) = get_tag_name(node) {
+        match tag.as_str() {
+            "speed" => {
+                let prev_speed = ctx.current_speed;
+                let prev_ramp = ctx.speed_ramp;
+                let from = get_attr(node, "from").and_then(|v| v.parse::<f32>().ok());
+                let to = get_attr(node, "to").and_then(|v| v.parse::<f32>().ok());
+                if let Some(value) = get_attr(node, "value") {
+                    ctx.current_speed = value.parse().unwrap_or(1.0);
+                    ctx.speed_ramp = None;
+                } else if let (Some(from), Some(to)) = (from, to) {
+                    if from > 0.0 && to > 0.0 {
+                        ctx.current_speed = from;
+                        ctx.speed_ramp = Some(SpeedRamp {
+                            from,
+                            to,
+                            chunks: ctx.count_speech_chunks(node),
+                            next: 0,
+                        });
+                    }
+                }
+                segments.extend(process_children(ctx, node)?);
+                ctx.current_speed = prev_speed;
+                ctx.speed_ramp = prev_ramp;
+            }
+
+            "emphasis" => {
+                // (speed factor, gain in dB) per SSML level
+                let (speed_factor, gain_db) = match get_attr(node, "level").as_deref() {
+                    Some("reduced") => (1.05, -2.0),
+                    Some("strong") => (0.9, 2.0),
+                    _ => (0.95, 1.0),
+                };
+                let prev_speed = ctx.current_speed;
+                ctx.current_speed *= speed_factor;
+                segments.extend(process_wrapped(ctx, node, |_, target| {
+                    Ok(target.gain_db(gain_db))
+                })?);
+                ctx.current_speed = prev_speed;
+            }
+
+            "whisper" => {
+                segments.extend(process_wrapped(ctx, node, |ctx, target| {
+                    // Breathy: no low end, quiet, a little air and a touch of width
+                    let thin = apply_highpass(&target, 600.0).gain_db(-8.0);
+                    let airy = add_noise(&thin, 0.002, ctx.rng_seed);
+                    let options = EffectOptions {
+                        delay_ms: Some(8.0),
+                        ..Default::default()
+                    };
+                    Ok(apply_haas(&airy, &options))
+                })?);
+            }
+
+            "shout" => {
+                let prev_speed = ctx.current_speed;
+                ctx.current_speed *= 1.08;
+                segments.extend(process_wrapped(ctx, node, |_, target| {
+                    Ok(apply_saturation(&target, 1.5).gain_db(4.0))
+                })?);
+                ctx.current_speed = prev_speed;
+            }
+
+            "tts" => {
+                let prev_steps = ctx.current_steps;
+                let prev_temperature = ctx.current_temperature;
+                let prev_trim_enabled = ctx.trim_enabled;
+                let prev_trim_threshold = ctx.trim_threshold;
+                let prev_trim_min_silence_ms = ctx.trim_min_silence_ms;
+                let prev_tts_gain = ctx.tts_gain;
+                if let Some(steps) = get_attr(node, "steps").and_then(|v| v.parse::<usize>().ok()) {
+                    ctx.current_steps = steps.clamp(MIN_TTS_STEPS, MAX_TTS_STEPS);
+                }
+                if let Some(temperature) =
+                    get_attr(node, "temperature").and_then(|v| v.parse::<f32>().ok())
+                {
+                    ctx.current_temperature = temperature.clamp(0.0, 2.0);
+                }
+                if let Some(trim) = get_attr(node, "trim").and_then(|v| v.parse::<bool>().ok()) {
+                    ctx.trim_enabled = trim;
+                }
+                if let Some(threshold) =
+                    get_attr(node, "trim_threshold").and_then(|v| v.parse::<f32>().ok())
+                {
+                    ctx.trim_threshold = threshold.max(0.0);
+                }
+                if let Some(min_silence_ms) =
+                    get_attr(node, "trim_min_silence_ms").and_then(|v| v.parse::<f32>().ok())
+                {
+                    ctx.trim_min_silence_ms = min_silence_ms.max(0.0);
+                }
+                if let Some(gain) = get_attr(node, "gain").and_then(|v| v.parse::<f32>().ok()) {
+                    ctx.tts_gain = gain.max(0.0);
+                }
+                segments.extend(process_children(ctx, node)?);
+                ctx.current_steps = prev_steps;
+                ctx.current_temperature = prev_temperature;
+                ctx.trim_enabled = prev_trim_enabled;
+                ctx.trim_threshold = prev_trim_threshold;
+                ctx.trim_min_silence_ms = prev_trim_min_silence_ms;
+                ctx.tts_gain = prev_tts_gain;
+            }
+
+            "quality" => {
+                let prev_steps = ctx.current_steps;
+                let value = get_attr(node, "value").unwrap_or_default();
+                match QUALITY_PRESETS.iter().find(|(name, _)| *name == value) {
+                    Some(&(_, steps)) => ctx.current_steps = steps,
+                    None => {
+                        let names: Vec<&str> =
+                            QUALITY_PRESETS.iter().map(|(name, _)| *name).collect();
+                        let message = format!(
+                            "Unknown quality \"{}\"; expected {}",
+                            value,
+                            names.join(", ")
+                        );
+                        if ctx.strict {
+                            anyhow::bail!(message);
+                        }
+                        ctx.emit_warning(&message);
+                    }
+                }
+                segments.extend(process_children(ctx, node)?);
+                ctx.current_steps = prev_steps;
+            }
+
+            "voice" => {
+                let prev_voice = ctx.current_voice.clone();
+                let prev_blend = ctx.current_blend.clone();
+                if let Some(value) = get_attr(node, "value") {
+                    let voices = get_voices();
+                    ctx.current_voice = if voices.contains_key(value.as_str()) {
+                        value
+                    } else {
+                        value
+                    };
+                    // A new voice starts unblended unless it asks for a blend
+                    ctx.current_blend = get_attr(node, "blend").map(|blend| {
+                        let amount: f32 = get_attr(node, "amount")
+                            .and_then(|v| v.parse().ok())
+                            .unwrap_or(0.5);
+                        (blend, amount.clamp(0.0, 1.0))
+                    });
+                }
+                segments.extend(process_children(ctx, node)?);
+                ctx.current_voice = prev_voice;
+                ctx.current_blend = prev_blend;
+            }
+
+            "speaker" => {
+                // Unknown speakers keep the surrounding voice
+                let prev_voice = ctx.current_voice.clone();
+                let name = get_attr(node, "name").unwrap_or_default();
+                match ctx.speaker_voice(&name) {
+                    Some(voice) => ctx.current_voice = voice,
+                    None => {
+                        let message = format!("Unknown speaker \"{}\"", name);
+                        ctx.emit_warning(&message);
+                    }
+                }
+                segments.extend(process_children(ctx, node)?);
+                ctx.current_voice = prev_voice;
+            }
+
+            "cast" => {
+                // <cast><role name="alice" voice="female"/>...</cast> maps
+                // speaker names to voices; the block itself renders nothing
+                for role in node.descendants() {
+                    if get_tag_name(&role).as_deref() != Some("role") {
+                        continue;
+                    }
+                    if let (Some(name), Some(voice)) =
+                        (get_attr(&role, "name"), get_attr(&role, "voice"))
+                    {
+                        ctx.speakers.insert(name.to_lowercase(), voice);
+                    }
+                }
+                ctx.current_node
+                    .fetch_add(count_nodes(node) - 1, Ordering::SeqCst);
+            }
+
+            "raw" => {
+                // Spoken exactly as written; preprocessing left it untouched
+                let text = node.text_contents();
+                let text = text.trim();
+                if !text.is_empty() {
+                    let audio = ctx.generate_tts_verbatim(text)?;
+                    ctx.elapsed_samples += audio.length();
+                    segments.push(audio);
+                }
+                ctx.current_node
+                    .fetch_add(count_nodes(node) - 1, Ordering::SeqCst);
+            }
+
+            "pauses" => {
+                // Applies to everything after the tag, like a setting
+                if let Some(sentence) =
+                    get_attr(node, "sentence").and_then(|v| v.parse::<f32>().ok())
+                {
+                    ctx.sentence_pause_secs = sentence.max(0.0);
+                }
+                if let Some(paragraph) =
+                    get_attr(node, "paragraph").and_then(|v| v.parse::<f32>().ok())
+                {
+                    ctx.paragraph_pause_secs = paragraph.max(0.0);
+                }
+                segments.extend(process_children(ctx, node)?);
+            }
+
+            // SSML structure: a sentence pause after each <s> and a paragraph
+            // pause after each <p>. The paragraph pause replaces the sentence
+            // pause of the last <s> in a <p>.
+            "p" => {
+                segments.extend(process_children(ctx, node)?);
+                if ctx.paragraph_pause_secs > 0.0 {
+                    let silence = AudioBuffer::silence(ctx.paragraph_pause_secs, ctx.sample_rate);
+                    ctx.elapsed_samples += silence.length();
+                    segments.push(silence);
+                }
+            }
+
+            "s" => {
+                segments.extend(process_children(ctx, node)?);
+                let ends_paragraph = node
+                    .parent()
+                    .is_some_and(|parent| get_tag_name(&parent).as_deref() == Some("p"))
+                    && node.following_siblings().all(|sibling| is_blank(&sibling));
+                if !ends_paragraph && ctx.sentence_pause_secs > 0.0 {
+                    let silence = AudioBuffer::silence(ctx.sentence_pause_secs, ctx.sample_rate);
+                    ctx.elapsed_samples += silence.length();
+                    segments.push(silence);
+                }
+            }
+
+            "pause" => {
+                let duration: f32 = get_attr(node, "value")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1.0);
+                let silence = AudioBuffer::silence(duration, ctx.sample_rate);
+                ctx.elapsed_samples += silence.length();
+                segments.push(silence);
+                segments.extend(process_children(ctx, node)?);
+            }
+
+            "silence" => {
+                // Exactly `value` seconds; unlike <pause>, content inside is ignored
+                let duration: f32 = get_attr(node, "value")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1.0);
+                let silence = AudioBuffer::silence(duration.max(0.0), ctx.sample_rate);
+                ctx.elapsed_samples += silence.length();
+                segments.push(silence);
+            }
+
+            "marker" => {
+                let name = get_attr(node, "name").unwrap_or_default();
+                ctx.markers.push(Marker {
+                    name,
+                    position: ctx.elapsed_samples,
+                });
+                segments.extend(process_children(ctx, node)?);
+            }
+
+            "overlay" => {
+                // Overlay gain scales every part, part gain scales just that part
+                let overlay_gain: f32 = get_attr(node, "gain")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1.0)
+                    .max(0.0);
+
+                let mut children = Vec::new();
+                let mut ducks = Vec::new();
+                collect_overlay_children(node, &mut children, &mut ducks);
+                ctx.current_node.fetch_add(ducks.len(), Ordering::SeqCst);
+
+                // Parts are independent until the merge, so each renders in a
+                // forked context. Nodes are not Send; workers re-parse markup.
+                let mut forks = Vec::new();
+                let mut markups = Vec::new();
+                let mut layouts = Vec::new();
+                for child in children {
+                    if get_tag_name(&child).as_deref() != Some("part") {
+                        continue;
+                    }
+                    ctx.current_node.fetch_add(1, Ordering::SeqCst);
+                    ctx.emit_progress("Processing overlay part", "generate");
+
+                    // Every part starts at the overlay start plus its offset
+                    let offset: f32 = get_attr(&child, "offset")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0.0)
+                        .max(0.0);
+                    let part_gain: f32 = get_attr(&child, "gain")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(1.0)
+                        .max(0.0);
+
+                    let mut fork = ctx.fork();
+                    fork.elapsed_samples =
+                        start_samples + (offset * ctx.sample_rate as f32) as usize;
+                    forks.push(fork);
+                    markups.push(child.children().map(|c| c.to_string()).collect::<String>());
+                    layouts.push((offset, part_gain, get_attr(&child, "name")));
+                }
+
+                let rendered: Vec<Result<(Vec<AudioBuffer>, ScriptToAudioContext)>> = if ctx
+                    .parallel_overlay
+                    && forks.len() > 1
+                {
+                    std::thread::scope(|scope| {
+                        let handles: Vec<_> = forks
+                            .into_iter()
+                            .zip(&markups)
+                            .map(|(fork, markup)| scope.spawn(move || render_part(fork, markup)))
+                            .collect();
+                        handles
+                            .into_iter()
+                            .map(|handle| {
+                                handle.join().unwrap_or_else(|_| {
+                                    Err(anyhow::anyhow!("Overlay part panicked"))
+                                })
+                            })
+                            .collect()
+                    })
+                } else {
+                    forks
+                        .into_iter()
+                        .zip(&markups)
+                        .map(|(fork, markup)| render_part(fork, markup))
+                        .collect()
+                };
+
+                let mut parts: Vec<AudioBuffer> = Vec::new();
+                let mut gains: Vec<f32> = Vec::new();
+                let mut names: Vec<Option<String>> = Vec::new();
+                for (result, (offset, part_gain, name)) in rendered.into_iter().zip(layouts) {
+                    let (part_segments, fork) = result?;
+                    ctx.absorb(fork);
+                    if part_segments.is_empty() {
+                        continue;
+                    }
+                    let concatenated = AudioBuffer::concat(&part_segments)?;
+
+                    // Delay the part by left-padding it with silence
+                    if offset > 0.0 {
+                        let padding = AudioBuffer::silence(offset, ctx.sample_rate);
+                        parts.push(AudioBuffer::concat(&[padding, concatenated])?);
+                    } else {
+                        parts.push(concatenated);
+                    }
+                    gains.push(part_gain * overlay_gain);
+                    names.push(name);
+                }
+
+                // Sidechain ducking runs on the raw parts, before part gains
+                for duck in &ducks {
+                    let find = |attr: &str| {
+                        let name = get_attr(duck, attr)?;
+                        names
+                            .iter()
+                            .position(|n| n.as_deref() == Some(name.as_str()))
+                    };
+                    let number = |attr: &str, default: f32| {
+                        get_attr(duck, attr)
+                            .and_then(|v| v.parse::<f32>().ok())
+                            .unwrap_or(default)
+                    };
+                    match (find("key"), find("target")) {
+                        (Some(key), Some(target)) => {
+                            parts[target] = apply_sidechain_duck(
+                                &parts[target],
+                                &parts[key],
+                                number("amount", -10.0),
+                                number("attack", 20.0),
+                                number("release", 200.0),
+                            );
+                        }
+                        _ => {
+                            let message = "<duck> key or target does not name a part";
+                            ctx.emit_warning(message);
+                        }
+                    }
+                }
+
+                if !parts.is_empty() {
+                    let merged = AudioBuffer::merge_weighted(&parts, &gains)?;
+                    if ctx.capture_stems {
+                        // Stems carry their mix gain and share the merged length
+                        for (part, &gain) in parts.iter().zip(&gains) {
+                            let mut audio = apply_volume(part, gain);
+                            audio.pad_to(merged.length());
+                            ctx.stems.push(Stem {
+                                position: start_samples,
+                                audio,
+                            });
+                        }
+                    }
+                    segments.push(merged);
+                }
+            }
+
+            "sound" => {
+                let sound = match (get_attr(node, "src"), get_attr(node, "value")) {
+                    (Some(src), _) => match ctx.fetch_audio_src(&src) {
+                        // Failed downloads were reported when URLs were fetched
+                        Err(e) if !is_url(&src) => {
+                            let message =
+                                format!("Sound src \"{}\" could not be loaded: {}", src, e);
+                            if ctx.strict {
+                                anyhow::bail!(message);
+                            }
+                            ctx.emit_warning(&message);
+                            None
+                        }
+                        sound => sound.ok(),
+                    },
+                    (None, Some(value)) => ctx.fetch_sound_effect(&value).ok(),
+                    (None, None) => None,
+                };
+                if let Some(mut buffer) = sound {
+                    if let Some(volume) =
+                        get_attr(node, "volume").and_then(|v| v.parse::<f32>().ok())
+                    {
+                        buffer = apply_volume(&buffer, volume.max(0.0));
+                    }
+                    if let Some(pan) = get_attr(node, "pan").and_then(|v| v.parse::<f32>().ok()) {
+                        let options = EffectOptions {
+                            pan: Some(pan),
+                            ..Default::default()
+                        };
+                        buffer = apply_pan(&buffer, &options);
+                    }
+                    let repeat: usize = get_attr(node, "repeat")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(1);
+                    // Refuse repeats past the output cap before cloning any
+                    check_output_cap(
+                        ctx,
+                        node,
+                        &["value", "src", "repeat"],
+                        start_samples as f64 + buffer.length() as f64 * repeat as f64,
+                    )?;
+                    for _ in 0..repeat {
+                        ctx.elapsed_samples += buffer.length();
+                        segments.push(buffer.clone());
+                    }
+                }
+                segments.extend(process_children(ctx, node)?);
+            }
+
+            "audio" => {
+                // SSML clip; the children are fallback content, spoken only
+                // when the clip cannot be loaded
+                let src = get_attr(node, "src").unwrap_or_default();
+                match ctx.fetch_audio_src(&src) {
+                    Ok(buffer) => {
+                        ctx.elapsed_samples += buffer.length();
+                        segments.push(buffer);
+                        ctx.current_node
+                            .fetch_add(count_nodes(node) - 1, Ordering::SeqCst);
+                    }
+                    Err(e) => {
+                        ctx.emit_warning(&format!(
+                            "Audio \"{}\" unavailable, speaking the fallback text: {}",
+                            src, e
+                        ));
+                        segments.extend(process_children(ctx, node)?);
+                    }
+                }
+            }
+
+            "effect" => {
+                let steps = match get_attr(node, "chain") {
+                    Some(chain) => {
+                        ctx.effect_chain(&chain, get_attr(node, "options").as_deref())?
+                    }
+                    None => {
+                        let effect_name = get_attr(node, "value").unwrap_or_default();
+                        let preset_name = get_attr(node, "preset");
+                        let options_attr =
+                            get_attr(node, "options").unwrap_or_else(|| "{}".to_string());
+
+                        let mut options = EffectOptions::default();
+
+                        // Load preset if available
+                        if let Some(ref preset) = preset_name {
+                            if let Some(preset_opts) = ctx.get_preset(&effect_name, preset) {
+                                options = preset_opts;
+                            }
+                        }
+
+                        // Merge with parsed options
+                        let parsed_options = EffectOptions::from_json(&options_attr);
+                        options = options.merge(&parsed_options);
+                        vec![(effect_name, options)]
+                    }
+                };
+
+                // Stereo effects such as pan upmix; `mono` collapses the
+                // result again for mono deliverables
+                let mono = get_attr(node, "mono")
+                    .and_then(|v| v.parse::<bool>().ok())
+                    .unwrap_or(false);
+                if mono && steps.iter().any(|(name, _)| name == "binaural") {
+                    let message = "Downmixing binaural audio to mono; the beat effect is lost";
+                    ctx.emit_warning(message);
+                }
+
+                segments.extend(process_wrapped(ctx, node, |ctx, mut target| {
+                    for (effect_name, options) in &steps {
+                        let started = Instant::now();
+                        target = ctx.apply_effect(effect_name, &target, options);
+                        ctx.record_time(&format!("effect:{}", effect_name), started);
+                    }
+                    if mono {
+                        target = target.conform_channels(1);
+                    }
+                    Ok(target)
+                })?);
+            }
+
+            "channel" => {
+                let value = get_attr(node, "side").unwrap_or_default();
+                match ChannelSide::parse(&value) {
+                    Some(side) => {
+                        let prev_side = ctx.channel_side.replace(side);
+                        let children = process_children(ctx, node);
+                        ctx.channel_side = prev_side;
+                        let children = children?;
+                        if !children.is_empty() {
+                            segments.push(AudioBuffer::concat(&children)?.conform_channels(2));
+                        }
+                    }
+                    None => {
+                        let message = format!(
+                            "Unknown channel side \"{}\"; expected left, right, mid or side",
+                            value
+                        );
+                        if ctx.strict {
+                            anyhow::bail!(message);
+                        }
+                        ctx.emit_warning(&message);
+                        segments.extend(process_children(ctx, node)?);
+                    }
+                }
+            }
+
+            "loop" => {
+                if get_attr(node, "value").is_some() && get_attr(node, "duration").is_some() {
+                    anyhow::bail!("<loop> cannot have both a value and a duration");
+                }
+                if get_attr(node, "gap").is_some() && get_attr(node, "crossfade").is_some() {
+                    anyhow::bail!("<loop> cannot have both a gap and a crossfade");
+                }
+
+                let loops: usize = get_attr(node, "value")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1);
+                let duration: Option<f32> = get_attr(node, "duration").and_then(|v| v.parse().ok());
+                let gap: f32 = get_attr(node, "gap")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.0)
+                    .max(0.0);
+                let crossfade: f32 = get_attr(node, "crossfade")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.0)
+                    .max(0.0);
+
+                let child_segments = process_children(ctx, node)?;
+
+                if !child_segments.is_empty() {
+                    let single_iteration = AudioBuffer::concat(&child_segments)?;
+                    let gap_buffer = AudioBuffer::silence(gap, ctx.sample_rate);
+                    // Each iteration must still add something after the overlap
+                    let crossfade = crossfade.min(single_iteration.duration_secs() / 2.0);
+                    let overlap = (crossfade * ctx.sample_rate as f32) as usize;
+
+                    // Refuse loops that would end past the output cap before
+                    // cloning any iterations
+                    let projected_samples = match duration {
+                        Some(duration) => duration.max(0.0) as f64 * ctx.sample_rate as f64,
+                        None => {
+                            let step =
+                                (single_iteration.length() + gap_buffer.length() - overlap) as f64;
+                            step * loops as f64 - gap_buffer.length() as f64 + overlap as f64
+                        }
+                    };
+                    check_output_cap(
+                        ctx,
+                        node,
+                        &["value", "duration", "gap"],
+                        start_samples as f64 + projected_samples,
+                    )?;
+
+                    if overlap > 0 {
+                        // Overlap iterations so tails blend into the next start
+                        let looped = match duration {
+                            Some(duration) => {
+                                let target = (duration.max(0.0) * ctx.sample_rate as f32) as usize;
+                                // Enough iterations to reach the target, the
+                                // last one cut short
+                                let length = single_iteration.length();
+                                let count = if target > length {
+                                    1 + (target - length).div_ceil(length - overlap)
+                                } else {
+                                    1
+                                };
+                                let mut looped =
+                                    single_iteration.repeat_crossfade(count, crossfade);
+                                looped.truncate(target);
+                                looped
+                            }
+                            None => single_iteration.repeat_crossfade(loops, crossfade),
+                        };
+                        if looped.length() > 0 && (duration.is_some() || loops > 0) {
+                            segments.push(looped);
+                        }
+                    } else if let Some(duration) = duration {
+                        // Loop until the target length, truncating the final iteration
+                        let mut remaining = (duration.max(0.0) * ctx.sample_rate as f32) as usize;
+                        let step = single_iteration.length() + gap_buffer.length();
+                        let mut first = true;
+                        while remaining > 0 && step > 0 {
+                            if !first && gap_buffer.length() > 0 {
+                                let mut silence = gap_buffer.clone();
+                                silence.truncate(remaining);
+                                remaining -= silence.length();
+                                segments.push(silence);
+                            }
+                            if remaining == 0 {
+                                break;
+                            }
+                            let mut iteration = single_iteration.clone();
+                            iteration.truncate(remaining);
+                            remaining -= iteration.length();
+                            segments.push(iteration);
+                            first = false;
+                        }
+                    } else {
+                        for i in 0..loops {
+                            if i > 0 && gap_buffer.length() > 0 {
+                                segments.push(gap_buffer.clone());
+                            }
+                            segments.push(single_iteration.clone());
+                        }
+                    }
+                }
+            }
+
+            "random" => {
+                // Pick one <item> child per render
+                let items: Vec<NodeRef> = node
+                    .children()
+                    .filter(|child| get_tag_name(child).as_deref() == Some("item"))
+                    .collect();
+                if !items.is_empty() {
+                    let index = ctx.rng.gen_range(0..items.len());
+                    segments.extend(process_children(ctx, &items[index])?);
+                }
+            }
+
+            "sequence" => {
+                let shuffle = get_attr(node, "shuffle")
+                    .and_then(|v| v.parse::<bool>().ok())
+                    .unwrap_or(false);
+                if shuffle {
+                    // Render the children in a seeded random order, so markers
+                    // and timing follow the order they are heard in
+                    let mut children: Vec<NodeRef> =
+                        node.children().filter(|child| !is_blank(child)).collect();
+                    children.shuffle(&mut ctx.rng);
+                    for child in &children {
+                        segments.extend(process_node(ctx, child)?);
+                    }
+                } else {
+                    segments.extend(process_children(ctx, node)?);
+                }
+            }
+
+            "volume" => {
+                let volume: f32 = get_attr(node, "value")
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .unwrap_or(1.0)
+                    .max(0.0);
+
+                segments.extend(process_wrapped(ctx, node, |_, target| {
+                    Ok(apply_volume(&target, volume))
+                })?);
+            }
+
+            "automation" => {
+                let param = get_attr(node, "param").unwrap_or_else(|| "volume".to_string());
+                let from = get_attr(node, "from").and_then(|v| v.parse::<f32>().ok());
+                let to = get_attr(node, "to").and_then(|v| v.parse::<f32>().ok());
+                let curve = get_attr(node, "curve")
+                    .and_then(|v| AutomationCurve::parse(&v))
+                    .unwrap_or(AutomationCurve::Linear);
+
+                segments.extend(process_wrapped(ctx, node, |ctx, target| {
+                    if param == "volume" {
+                        let from = from.unwrap_or(1.0);
+                        let to = to.unwrap_or(from);
+                        Ok(apply_gain_envelope(&target, from, to, curve))
+                    } else {
+                        let message = format!("Unsupported automation param \"{}\"", param);
+                        ctx.emit_warning(&message);
+                        Ok(target)
+                    }
+                })?);
+            }
+
+            // For root, html, head, body, or unknown elements - just process children
+            _ => {
+                segments.extend(process_children(ctx, node)?);
+            }
+        }
+    } else {
+        // For other node types, process children
+        segments.extend(process_children(ctx, node)?);
+    }
+
+    // Loops, overlays and effects can change the length of their children
+    ctx.elapsed_samples = start_samples + segments.iter().map(|s| s.length()).sum::<usize>();
+
+    Ok(segments)
+}
+
+/// One rendered `<overlay>` part and the position of its overlay, in sample
+/// frames
+pub struct Stem {
+    pub position: usize,
+    pub audio: AudioBuffer,
+}
+
+/// Place stems on a common timeline: each is delayed to its position and
+/// zero-padded so all of them (and the master) have the same length
+pub fn align_stems(stems: &[Stem], master_length: usize) -> Vec<AudioBuffer> {
+    let length = stems
+        .iter()
+        .map(|stem| stem.position + stem.audio.length())
+        .fold(master_length, usize::max);
+
+    stems
+        .iter()
+        .map(|stem| {
+            let mut aligned =
+                AudioBuffer::new(stem.audio.num_channels(), length, stem.audio.sample_rate);
+            for (out, data) in aligned.samples.iter_mut().zip(&stem.audio.samples) {
+                out[stem.position..stem.position + data.len()].copy_from_slice(data);
+            }
+            aligned
+        })
+        .collect()
+}
+
+/// Rendered script audio plus the markers placed in it and, if requested,
+/// one time-aligned stem per overlay part
+pub struct RenderedAudio {
+    pub audio: AudioBuffer,
+    pub markers: Vec<Marker>,
+    pub stems: Vec<AudioBuffer>,
+    pub profile: RenderProfile,
+    /// Seed the render's randomness started from; passing it back as the
+    /// `seed` option reproduces the render
+    pub seed: u64,
+}
+
+/// Where the time of a render went, as returned by `profile_script`
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RenderProfile {
+    /// Seconds per operation: `model_load`, `tts:<tag>` keyed by the tag
+    /// around the text, `effect:<name>`, `sound_load`, `resample`, `limiter`,
+    /// `write`
+    pub stages: BTreeMap<String, f64>,
+    pub tts_calls: usize,
+    /// Speech generated by the model before trimming, in seconds
+    pub tts_audio_secs: f64,
+    /// Wall time of the whole profiled run
+    pub total_secs: f64,
+}
+
+/// Render a script to audio without Tauri, loading its own model. Voice and
+/// sound files in `paths` are picked up as they are on disk.
+pub async fn render_script(
+    script: &str,
+    paths: RenderPaths,
+    opts: RenderOptions,
+) -> Result<AudioBuffer> {
+    let tts_state = TtsState::default();
+    let rendered = script_to_audio(
+        script,
+        &paths,
+        None,
+        "render".to_string(),
+        &opts,
+        &tts_state,
+        None,
+    )
+    .await?;
+    Ok(rendered.audio)
+}
+
+/// Convert script to audio buffer, reporting progress to `progress` from
+/// `start` through `complete`
+pub async fn script_to_audio(
+    script: &str,
+    paths: &RenderPaths,
+    progress: Option<Arc<dyn ProgressSink>>,
+    job_id: String,
+    options: &RenderOptions,
+    tts_state: &TtsState,
+    timeout: Option<Duration>,
+) -> Result<RenderedAudio> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    if let Some(ref sink) = progress {
+        sink.report(TtsProgressEvent {
+            job_id: job_id.clone(),
+            message: "Starting audio generation".to_string(),
+            progress: 0.0,
+            stage: "start".to_string(),
+            index: None,
+        });
+    }
+    let mut ctx = prepare_context(script, paths, progress, job_id, options, tts_state).await?;
+    ctx.assets = AssetScan::read(&ctx.voice_dir, &ctx.sound_effects_dir);
+    ctx.deadline = deadline;
+    let audio = render_with_context(&mut ctx, script)?;
+    let sink = ctx.progress.clone();
+    let job_id = ctx.job_id.clone();
+    let rendered = finish_render(ctx, audio)?;
+    if let Some(sink) = sink {
+        sink.report(TtsProgressEvent {
+            job_id,
+            message: "Audio generation complete".to_string(),
+            progress: 1.0,
+            stage: "complete".to_string(),
+            index: None,
+        });
+    }
+    Ok(rendered)
+}
+
+/// Fetch the script's remote sounds, load the model if it speaks and
+/// configure a context from `options`, ready to render `script`
+async fn prepare_context(
+    script: &str,
+    paths: &RenderPaths,
+    progress: Option<Arc<dyn ProgressSink>>,
+    job_id: String,
+    options: &RenderOptions,
+    tts_state: &TtsState,
+) -> Result<ScriptToAudioContext> {
+    // Fail before loading the model when there is nothing to render, and
+    // skip the model entirely when there is nothing to speak
+    let needs_speech = {
+        let root = parse_script(script);
+        if options.empty_silence_secs.is_none() && !has_content(&root) {
+            anyhow::bail!(EMPTY_SCRIPT_ERROR);
+        }
+        has_speech(&root)
+    };
+
+    // Download sounds referenced by URL before rendering
+    let sound_failures = fetch_remote_sounds(
+        script,
+        &paths.sound_effects_dir,
+        progress.as_deref(),
+        &job_id,
+    )
+    .await;
+
+    // Create context
+    let load_started = Instant::now();
+    let mut ctx = ScriptToAudioContext::new(
+        paths,
+        progress,
+        job_id,
+        options.execution_provider,
+        tts_state,
+        needs_speech,
+    )
+    .await?;
+    ctx.record_time("model_load", load_started);
+    if let Some(max_chunk_chars) = options.max_chunk_chars {
+        ctx.max_chunk_chars = max_chunk_chars;
+    }
+    if let Some(chunk_pause_secs) = options.chunk_pause_secs {
+        ctx.chunk_pause_secs = chunk_pause_secs.max(0.0);
+    }
+    if let Some(trim) = options.trim {
+        ctx.trim_enabled = trim;
+    }
+    if let Some(trim_threshold) = options.trim_threshold {
+        ctx.trim_threshold = trim_threshold.max(0.0);
+    }
+    if let Some(trim_min_silence_ms) = options.trim_min_silence_ms {
+        ctx.trim_min_silence_ms = trim_min_silence_ms.max(0.0);
+    }
+    if let Some(tts_gain) = options.tts_gain {
+        ctx.tts_gain = tts_gain.max(0.0);
+    }
+    if let Some(voice_crossfade_ms) = options.voice_crossfade_ms {
+        ctx.voice_crossfade_ms = voice_crossfade_ms.max(0.0);
+    }
+    if let Some(seed) = options.seed {
+        ctx.set_seed(seed);
+    }
+    if let Some(strict) = options.strict {
+        ctx.strict = strict;
+    }
+    if let Some(remove_dc) = options.remove_dc {
+        ctx.remove_dc_on_import = remove_dc;
+    }
+    if let Some(downmix_sounds) = options.downmix_sounds {
+        ctx.downmix_on_import = downmix_sounds;
+    }
+    if options.normalize_text == Some(false) {
+        ctx.text_normalizer = None;
+    } else if options.abbreviations.is_some()
+        || options.units.is_some()
+        || options.currency.is_some()
+    {
+        ctx.text_normalizer = Some(
+            TextNormalizer::with_overrides(options.abbreviations.as_ref(), options.units.as_ref())
+                .with_currency(options.currency.unwrap_or_default()),
+        );
+    }
+    if let Some(declick) = options.declick {
+        ctx.declick = declick;
+    }
+    if let Some(humanize) = options.humanize {
+        ctx.humanize = humanize.clamp(0.0, 1.0);
+    }
+    if let Some(humanize_range) = options.humanize_range {
+        ctx.humanize_range = humanize_range.clamp(0.0, 0.5);
+    }
+    if let Some(ref speakers) = options.speakers {
+        for (name, voice) in speakers {
+            ctx.speakers.insert(name.to_lowercase(), voice.clone());
+        }
+    }
+    if let Some(export_stems) = options.export_stems {
+        ctx.capture_stems = export_stems;
+    }
+    if let Some(parallel_overlay) = options.parallel_overlay {
+        ctx.parallel_overlay = parallel_overlay;
+    }
+    if let Some(max_output_secs) = options.max_output_secs {
+        ctx.max_output_secs = max_output_secs.max(0.0);
+    }
+    if let Some(empty_silence_secs) = options.empty_silence_secs {
+        ctx.empty_silence_secs = Some(empty_silence_secs.max(0.0));
+    }
+    ctx.output_channels = options.output_channels;
+    ctx.output_sample_rate = options.output_sample_rate.filter(|&rate| rate > 0);
+    if options.limiter.unwrap_or(false) {
+        ctx.limiter_ceiling_db = Some(options.limiter_ceiling_db.unwrap_or(LIMITER_CEILING_DB));
+    }
+    if let Some(download_weight) = options.download_weight {
+        ctx.download_weight = download_weight.clamp(0.0, 1.0);
+    }
+    if let Some(ref presets) = options.presets {
+        let (presets, warnings) = parse_effect_presets(presets.clone());
+        for warning in warnings {
+            ctx.emit_warning(&warning);
+        }
+        for (effect, presets) in presets {
+            ctx.registered_presets
+                .entry(effect)
+                .or_default()
+                .extend(presets);
+        }
+    }
+
+    // Missing sounds are skipped unless the job is strict
+    for (url, error) in sound_failures {
+        let message = format!("Failed to fetch sound {}: {}", url, error);
+        if ctx.strict {
+            anyhow::bail!(message);
+        }
+        ctx.emit_warning(&message);
+    }
+
+    Ok(ctx)
+}
+
+/// Bring rendered `audio` and its stems and markers to the job's output
+/// format
+fn finish_render(mut ctx: ScriptToAudioContext, audio: AudioBuffer) -> Result<RenderedAudio> {
+    if let Some(channels) = ctx.output_channels {
+        if audio.num_channels() > channels.count() {
+            let message = format!(
+                "Downmixing {}-channel audio to {}; stereo effects such as binaural are lost",
+                audio.num_channels(),
+                channels.count()
+            );
+            ctx.emit_warning(&message);
+        }
+    }
+    let stems = align_stems(&ctx.stems, audio.length())
+        .iter()
+        .map(|stem| ctx.conform_output(stem))
+        .collect();
+    if let Some(rate) = ctx.output_sample_rate {
+        let scale = rate as f64 / audio.sample_rate as f64;
+        for marker in &mut ctx.markers {
+            marker.position = (marker.position as f64 * scale).round() as usize;
+        }
+    }
+    let mut audio = ctx.conform_output(&audio);
+    if let Some(ceiling_db) = ctx.limiter_ceiling_db {
+        let started = Instant::now();
+        audio = apply_limiter(&audio, ceiling_db);
+        ctx.record_time("limiter", started);
+    }
+    Ok(RenderedAudio {
+        audio,
+        profile: ctx.profile(),
+        seed: ctx.rng_seed,
+        markers: ctx.markers,
+        stems,
+    })
+}
+
+/// The same script rendered once per voice
+pub struct VoiceComparison {
+    pub a: AudioBuffer,
+    pub b: AudioBuffer,
+    /// Both renders back to back, each announced in its own voice
+    pub combined: Option<AudioBuffer>,
+}
+
+/// Silence after a "Version A" announcement and between the two halves of
+/// a combined comparison
+const ANNOUNCEMENT_PAUSE_SECS: f32 = 0.4;
+const COMPARISON_GAP_SECS: f32 = 1.0;
+
+/// Render `script` in `voice_a` and again in `voice_b`, ignoring voice
+/// changes in the script. Both renders share the context's engine and
+/// caches and start from the same seed, so random choices match.
+fn compare_voices_with_context(
+    ctx: &mut ScriptToAudioContext,
+    script: &str,
+    voice_a: &str,
+    voice_b: &str,
+    announce: bool,
+) -> Result<VoiceComparison> {
+    let seed = ctx.rng_seed;
+    let render = |ctx: &mut ScriptToAudioContext, voice: &str, script: &str| {
+        let mut fork = ctx.fork();
+        fork.set_seed(seed);
+        fork.voice_override = Some(voice.to_string());
+        let audio = render_with_context(&mut fork, script);
+        ctx.absorb(fork);
+        audio.map(|audio| ctx.conform_output(&audio))
+    };
+
+    let a = render(ctx, voice_a, script)?;
+    let b = render(ctx, voice_b, script)?;
+    let combined = if announce {
+        let intro_a = render(ctx, voice_a, "Version A.")?;
+        let intro_b = render(ctx, voice_b, "Version B.")?;
+        let gap = AudioBuffer::silence(COMPARISON_GAP_SECS, a.sample_rate);
+        let pause = AudioBuffer::silence(ANNOUNCEMENT_PAUSE_SECS, a.sample_rate);
+        Some(AudioBuffer::concat(&[
+            intro_a,
+            pause.clone(),
+            a.clone(),
+            gap,
+            intro_b,
+            pause,
+            b.clone(),
+        ])?)
+    } else {
+        None
+    };
+
+    Ok(VoiceComparison { a, b, combined })
+}
+
+/// Render each script on a fork of one context, so the engine, voice styles
+/// and decoded sounds are loaded once, and hand every outcome to `finish` as
+/// soon as it is ready. A failing script does not stop the ones after it.
+fn render_batch_with_context<T>(
+    ctx: &mut ScriptToAudioContext,
+    scripts: &[AudioScript],
+    mut finish: impl FnMut(usize, &AudioScript, Result<RenderedAudio>) -> T,
+) -> Vec<T> {
+    let seed = ctx.rng_seed;
+    let mut results = Vec::with_capacity(scripts.len());
+    for (index, script) in scripts.iter().enumerate() {
+        let mut item = ctx.fork();
+        item.set_seed(script.seed.unwrap_or(seed));
+        item.batch_index = Some(index);
+        if let Some(ref sink) = item.progress {
+            sink.report(TtsProgressEvent {
+                job_id: item.job_id.clone(),
+                message: format!(
+                    "Rendering {} of {}: {}",
+                    index + 1,
+                    scripts.len(),
+                    script.title
+                ),
+                progress: 0.0,
+                stage: "start".to_string(),
+                index: Some(index),
+            });
+        }
+
+        let audio = render_with_context(&mut item, &script.script);
+        // Keep what was loaded for the scripts that follow
+        for (key, buffer) in item.sound_cache.drain() {
+            ctx.sound_cache.entry(key).or_insert(buffer);
+        }
+        for (path, entry) in item.style_cache.drain() {
+            ctx.style_cache.entry(path).or_insert(entry);
+        }
+        let rendered = audio.and_then(|audio| finish_render(item, audio));
+        results.push(finish(index, script, rendered));
+    }
+    results
+}
+
+const EMPTY_SCRIPT_ERROR: &str = "Script is empty: it has no text or tags to render";
+
+/// Preprocess a script and parse it, returning the `<root>` wrapping it
+fn parse_script(script: &str) -> NodeRef {
+    // Preprocess script
+    let preprocessed = preprocess_script(script);
+    let wrapped = format!("<root>{}</root>", preprocessed);
+
+    // Parse with kuchiki (more robust HTML/XML parsing)
+    let document = kuchiki::parse_html().one(wrapped);
+
+    // Find the root element we created
+    document
+        .select_first("root")
+        .map(|n| n.as_node().clone())
+        .unwrap_or_else(|_| document.clone())
+}
+
+/// Whether a node is a comment or whitespace-only text
+fn is_blank(node: &NodeRef) -> bool {
+    if is_comment(node) {
+        return true;
+    }
+    match node.as_text() {
+        Some(text) => text.borrow().trim().is_empty(),
+        None => false,
+    }
+}
+
+/// Whether a parsed script holds anything besides whitespace and comments
+fn has_content(root: &NodeRef) -> bool {
+    root.children().any(|child| !is_blank(&child))
+}
+
+/// Whether a parsed script has any text for the model to speak
+fn has_speech(root: &NodeRef) -> bool {
+    root.descendants().any(|node| {
+        node.as_text()
+            .is_some_and(|text| !text.borrow().trim().is_empty())
+    })
+}
+
+/// Parse a script and render it with an existing context
+fn render_with_context(ctx: &mut ScriptToAudioContext, script: &str) -> Result<AudioBuffer> {
+    let root = parse_script(script);
+
+    // Report markup the renderer would otherwise silently ignore
+    let mut issues = Vec::new();
+    collect_stray_tags(script, &mut issues);
+    collect_unknown_markup(&root, &mut issues);
+    if ctx.strict && !issues.is_empty() {
+        anyhow::bail!("Invalid script: {}", issues.join("; "));
+    }
+    for issue in &issues {
+        ctx.emit_warning(issue);
+    }
+
+    ctx.total_nodes = count_nodes(&root);
+    ctx.current_node.store(0, Ordering::SeqCst);
+    ctx.elapsed_samples = 0;
+    ctx.markers.clear();
+    ctx.stems.clear();
+    ctx.chunks_streamed = 0;
+
+    if !has_content(&root) {
+        return match ctx.empty_silence_secs {
+            Some(secs) => Ok(AudioBuffer::silence(secs, ctx.sample_rate)),
+            None => anyhow::bail!(EMPTY_SCRIPT_ERROR),
+        };
+    }
+
+    // Process all nodes, streaming top-level segments if anyone is listening
+    let stream = ctx.chunk_sink.is_some();
+    let audio_segments = process_children_streaming(ctx, &root, stream)?;
+
+    // Concatenate all segments
+    if audio_segments.is_empty() {
+        Ok(AudioBuffer::new(1, 1, ctx.sample_rate))
+    } else if ctx.declick {
+        AudioBuffer::concat_declicked(&audio_segments, DECLICK_FADE_SECS)
+    } else {
+        AudioBuffer::concat(&audio_segments)
+    }
+}
+
+// ============================================================================
+// Script Validation
+// ============================================================================
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a script, positioned at the offending tag (1-based)
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Tags that never need a closing tag (see `preprocess_script`)
+const VOID_TAGS: &[&str] = &["pause", "silence", "sound", "marker", "duck"];
+
+/// Attributes that must parse as numbers, per tag
+fn numeric_attributes(tag: &str) -> &'static [&'static str] {
+    match tag {
+        "speed" => &["value", "from", "to"],
+        "pause" | "silence" | "volume" => &["value"],
+        "sound" => &["volume", "pan", "repeat"],
+        "tts" => &[
+            "steps",
+            "temperature",
+            "trim_threshold",
+            "trim_min_silence_ms",
+            "gain",
+        ],
+        "pauses" => &["sentence", "paragraph"],
+        "overlay" => &["gain"],
+        "part" => &["offset", "gain"],
+        "loop" => &["value", "duration", "gap", "crossfade"],
+        "voice" => &["amount"],
+        "automation" => &["from", "to"],
+        "duck" => &["amount", "attack", "release"],
+        _ => &[],
+    }
+}
+
+/// Build a diagnostic for a byte offset into `source`
+fn diagnostic(source: &str, offset: usize, severity: Severity, message: String) -> Diagnostic {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Diagnostic {
+        severity,
+        message,
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// Check a script for problems without rendering it. Tags are scanned from
+/// the raw script so positions match what the user wrote.
+pub fn check_script(script: &str) -> Vec<Diagnostic> {
+    check_script_with_assets(script, &AssetScan::default())
+}
+
+/// Check a script like `check_script`, also accepting the voice files in
+/// `assets` that a render would find
+pub fn check_script_with_assets(script: &str, assets: &AssetScan) -> Vec<Diagnostic> {
+    // Blank out <raw> contents, keeping line and column positions intact
+    let masked = raw_regex().replace_all(script, |caps: &regex::Captures| {
+        let contents: String = caps[1]
+            .chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect();
+        format!("<raw>{}</raw>", contents)
+    });
+    let script = masked.as_ref();
+
+    let tag_re = Regex::new(r"<(/?)([A-Za-z][\w-]*)([^>]*)>").unwrap();
+    let attr_re = Regex::new(r#"([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let voices = get_voices();
+
+    let mut diagnostics = Vec::new();
+    let mut open_tags: Vec<(String, usize)> = Vec::new();
+
+    for caps in tag_re.captures_iter(script) {
+        let offset = caps.get(0).map_or(0, |m| m.start());
+        let tag = caps[2].to_lowercase();
+        let is_void = VOID_TAGS.contains(&tag.as_str());
+
+        if &caps[1] == "/" {
+            match open_tags.iter().rposition(|(open, _)| *open == tag) {
+                Some(index) => {
+                    for (unclosed, start) in open_tags.drain(index + 1..) {
+                        let message = format!("Unclosed tag <{}>", unclosed);
+                        diagnostics.push(diagnostic(script, start, Severity::Error, message));
+                    }
+                    open_tags.pop();
+                }
+                None if !is_void => {
+                    let message = format!("Closing tag </{}> has no matching opening tag", tag);
+                    diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                }
+                None => {}
+            }
+            continue;
+        }
+
+        let Some(known) = known_attributes(&tag) else {
+            let message = format!("Unknown tag <{}>", tag);
+            diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+            continue;
+        };
+
+        let attrs: HashMap<String, String> = attr_re
+            .captures_iter(&caps[3])
+            .map(|a| {
+                let value = a.get(2).or_else(|| a.get(3)).map_or("", |m| m.as_str());
+                (a[1].to_lowercase(), value.to_string())
+            })
+            .collect();
+
+        for (name, value) in &attrs {
+            if !known.contains(&name.as_str()) {
+                let message = format!("Unknown attribute \"{}\" on <{}>", name, tag);
+                diagnostics.push(diagnostic(script, offset, Severity::Warning, message));
+            } else if numeric_attributes(&tag).contains(&name.as_str())
+                && value.trim().parse::<f32>().is_err()
+            {
+                let message = format!("<{}> {} must be a number, got \"{}\"", tag, name, value);
+                diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+            } else if tag == "tts" && name == "trim" && value.parse::<bool>().is_err() {
+                let message = format!("<tts> trim must be true or false, got \"{}\"", value);
+                diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+            }
+        }
+
+        match tag.as_str() {
+            "voice" => {
+                for voice in [attrs.get("value"), attrs.get("blend")]
+                    .into_iter()
+                    .flatten()
+                {
+                    if !voices.contains_key(voice.as_str())
+                        && !assets.voices.contains_key(&voice.to_lowercase())
+                    {
+                        let message = format!("Unknown voice \"{}\"", voice);
+                        diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                    }
+                }
+            }
+            "quality" => {
+                let value = attrs.get("value").map(String::as_str).unwrap_or_default();
+                if !QUALITY_PRESETS.iter().any(|(name, _)| *name == value) {
+                    let message = format!("Unknown quality \"{}\"", value);
+                    diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                }
+            }
+            "channel" => {
+                let side = attrs.get("side").map(String::as_str).unwrap_or_default();
+                if ChannelSide::parse(side).is_none() {
+                    let message = format!("Unknown channel side \"{}\"", side);
+                    diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                }
+            }
+            "effect" if attrs.contains_key("chain") => {
+                let chain = attrs["chain"].split(',').map(str::trim);
+                for effect in chain.filter(|name| !name.is_empty()) {
+                    if !KNOWN_EFFECTS.contains(&effect) {
+                        let message = format!("Unknown effect \"{}\" in chain", effect);
+                        diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                    }
+                }
+            }
+            "effect" => {
+                let effect = attrs.get("value").map(String::as_str).unwrap_or_default();
+                if !KNOWN_EFFECTS.contains(&effect) {
+                    let message = format!("Unknown effect \"{}\"", effect);
+                    diagnostics.push(diagnostic(script, offset, Severity::Error, message));
+                } else if let Some(preset) = attrs.get("preset") {
+                    // Not an error: it may still be defined in the user's presets file
+                    if get_effect_preset(effect, preset).is_none() {
+                        let message = format!("Unknown {} preset \"{}\"", effect, preset);
+                        diagnostics.push(diagnostic(script, offset, Severity::Warning, message));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if !is_void && !caps[3].trim_end().ends_with('/') {
+            open_tags.push((tag, offset));
+        }
+    }
+
+    for (unclosed, start) in open_tags {
+        let message = format!("Unclosed tag <{}>", unclosed);
+        diagnostics.push(diagnostic(script, start, Severity::Error, message));
+    }
+
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    diagnostics
+}
+
+// ============================================================================
+// Playback
+// ============================================================================
+
+/// How often the playback thread checks whether it should stop
+const PLAYBACK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Feeds an `AudioBuffer` to an output stream callback as interleaved frames
+struct PlaybackFeeder {
+    samples: Vec<Vec<f32>>,
+    position: usize,
+}
+
+impl PlaybackFeeder {
+    /// Convert `buffer` to the device's sample rate and channel count
+    fn new(buffer: &AudioBuffer, sample_rate: u32, channels: usize) -> Self {
+        let converted = buffer.resample(sample_rate).conform_channels(channels);
+        PlaybackFeeder {
+            samples: converted.samples,
+            position: 0,
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.samples.first().map_or(0, Vec::len)
+    }
+
+    /// Fill one callback's worth of interleaved output, with silence past the
+    /// end. Returns false once every frame has been handed out.
+    fn fill<T: cpal::FromSample<f32>>(&mut self, out: &mut [T]) -> bool {
+        let channels = self.samples.len().max(1);
+        for frame in out.chunks_mut(channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let value = self
+                    .samples
+                    .get(ch)
+                    .and_then(|channel| channel.get(self.position))
+                    .copied()
+                    .unwrap_or(0.0);
+                *sample = T::from_sample(value);
+            }
+            self.position = (self.position + 1).min(self.frames());
+        }
+        self.position < self.frames()
+    }
+}
+
+/// Tauri managed state for the clip currently playing, if any
+#[derive(Default)]
+pub struct PlaybackState {
+    stop: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl PlaybackState {
+    /// Stop the current clip. Returns whether one was still playing.
+    pub fn stop(&self) -> bool {
+        let flag = self.stop.lock().ok().and_then(|mut stop| stop.take());
+        match flag {
+            Some(flag) => !flag.swap(true, Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    /// Play `buffer` on the default output device, stopping any current clip
+    pub fn play(&self, buffer: AudioBuffer) -> Result<()> {
+        self.stop();
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+
+        // cpal streams are not Send on every platform, so the stream lives on
+        // its own thread. It reports whether the device opened before playing.
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let stream = match open_output_stream(&buffer, flag.clone()) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+            while !flag.load(Ordering::SeqCst) {
+                std::thread::sleep(PLAYBACK_POLL_INTERVAL);
+            }
+            // Let the device drain the last callback before closing
+            std::thread::sleep(PLAYBACK_POLL_INTERVAL);
+            drop(stream);
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow::anyhow!("Audio playback thread exited"))??;
+        if let Ok(mut current) = self.stop.lock() {
+            *current = Some(stop);
+        }
+        Ok(())
+    }
+}
+
+/// Open and start a stream on the default output device. `stop` is set when
+/// the whole buffer has been played; setting it from outside ends playback.
+fn open_output_stream(buffer: &AudioBuffer, stop: Arc<AtomicBool>) -> Result<cpal::Stream> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    let device = cpal::default_host()
+        .default_output_device()
+        .context("No audio output device available")?;
+    let supported = device
+        .default_output_config()
+        .context("Audio output device is unavailable")?;
+    let config: cpal::StreamConfig = supported.config();
+    let feeder = PlaybackFeeder::new(buffer, config.sample_rate.0, config.channels as usize);
+
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => build_output_stream::<f32>(&device, &config, feeder, stop),
+        cpal::SampleFormat::I16 => build_output_stream::<i16>(&device, &config, feeder, stop),
+        cpal::SampleFormat::U16 => build_output_stream::<u16>(&device, &config, feeder, stop),
+        format => anyhow::bail!("Unsupported output sample format {:?}", format),
+    }?;
+    stream.play().context("Failed to start audio playback")?;
+    Ok(stream)
+}
+
+fn build_output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut feeder: PlaybackFeeder,
+    stop: Arc<AtomicBool>,
+) -> Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    use cpal::traits::DeviceTrait;
+
+    let stream = device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                if stop.load(Ordering::SeqCst) {
+                    data.fill(T::EQUILIBRIUM);
+                } else if !feeder.fill(data) {
+                    stop.store(true, Ordering::SeqCst);
+                }
+            },
+            |e| eprintln!("Audio playback error: {}", e),
+            None,
+        )
+        .context("Failed to open audio output stream")?;
+    Ok(stream)
+}
+
+// ============================================================================
+// Tauri Commands
+// ============================================================================
+
+/// Longest file name (in characters) written to the app data directory
+const MAX_FILENAME_CHARS: usize = 150;
+
+/// Turn a title or user-supplied name into a plain file name that cannot
+/// escape the output directory: separators and control characters become
+/// `_`, runs of dots collapse and leading dots are dropped.
+pub fn sanitize_filename(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars() {
+        let c = match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        };
+        if c == '.' && sanitized.ends_with('.') {
+            continue;
+        }
+        sanitized.push(c);
+    }
+
+    let sanitized: String = sanitized
+        .trim()
+        .trim_start_matches('.')
+        .chars()
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    let sanitized = sanitized.trim_end_matches(['.', ' ']);
+
+    if sanitized.is_empty() {
+        "audio".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// Pick a name in `dir` that does not exist yet by appending ` (1)`, ` (2)`,
+/// ... before the extension
+fn unique_filename(dir: &Path, filename: &str) -> String {
+    if !dir.join(filename).exists() {
+        return filename.to_string();
+    }
+
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| format!("{} ({}){}", stem, n, extension))
+        .find(|candidate| !dir.join(candidate).exists())
+        .unwrap_or_else(|| filename.to_string())
+}
+
+/// Write `audio` into `dir` with any markers as cue points and optional
+/// `LIST/INFO` metadata, returning the file name actually used. Unless
+/// `overwrite` is set, an existing file is kept and a numbered name is chosen.
+fn write_audio_file(
+    rendered: &RenderedAudio,
+    info: Option<&WavInfo>,
+    dir: &Path,
+    filename: &str,
+    overwrite: bool,
+) -> Result<(String, usize)> {
+    let filename = if overwrite {
+        filename.to_string()
+    } else {
+        unique_filename(dir, filename)
+    };
+    let path = dir.join(&filename);
+    let clipped = rendered.audio.write_to_file(&path)?;
+
+    let mut chunks = Vec::new();
+    if !rendered.markers.is_empty() {
+        chunks.extend(cue_chunks(&rendered.markers));
+    }
+    if let Some(info) = info {
+        chunks.push(info_chunk(info));
+    }
+    if !chunks.is_empty() {
+        append_riff_chunks(&path, &chunks)?;
+    }
+    Ok((filename, clipped))
+}
+
+/// Warning text for a file where more than `CLIP_WARNING_RATIO` of the
+/// samples were clipped, if it is that hot
+fn clip_warning(clipped: usize, audio: &AudioBuffer) -> Option<String> {
+    let total = audio.length() * audio.num_channels();
+    if total == 0 || (clipped as f32 / total as f32) <= CLIP_WARNING_RATIO {
+        return None;
+    }
+    Some(format!(
+        "{} samples ({:.2}%) clipped while writing; lower the volume or normalize the mix",
+        clipped,
+        clipped as f32 / total as f32 * 100.0
+    ))
+}
+
+/// Write stems next to the master file as `{basename}.stem{N}.wav`,
+/// replacing stems left by an earlier render of the same name
+fn write_stems(stems: &[AudioBuffer], dir: &Path, master_filename: &str) -> Result<Vec<String>> {
+    let basename = Path::new(master_filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| master_filename.to_string());
+
+    stems
+        .iter()
+        .enumerate()
+        .map(|(i, stem)| {
+            let filename = format!("{}.stem{}.wav", basename, i + 1);
+            stem.write_to_file(&dir.join(&filename))?;
+            Ok(filename)
+        })
+        .collect()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AudioScript {
+    pub title: String,
+    pub script: String,
+    pub filename: Option<String>,
+    /// Length of the rendered audio, filled in by `generate_audio`
+    #[serde(default)]
+    pub duration_secs: Option<f32>,
+    /// Stem files written next to the audio when `export_stems` is set
+    #[serde(default)]
+    pub stems: Vec<String>,
+    /// Samples clipped to ±1 in the written file, filled in by `generate_audio`
+    #[serde(default)]
+    pub clipped_samples: Option<usize>,
+    /// Seed for random choices. Overrides the `seed` option when set; the
+    /// result always carries the seed used so the render can be reproduced.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// A script saved together with everything needed to render it again the
+/// same way. `options` carries the speaker map (`speakers`), custom
+/// `presets` and the output format.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectBundle {
+    pub title: String,
+    pub script: String,
+    /// Seed for random choices, overriding `options.seed` like
+    /// `AudioScript::seed`
+    pub seed: Option<u64>,
+    pub options: RenderOptions,
+}
+
+impl ProjectBundle {
+    /// Pretty JSON with object keys sorted, so saving a reloaded bundle
+    /// writes the same bytes
+    pub fn to_json(&self) -> Result<String> {
+        let value = serde_json::to_value(self)?;
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_json()?)
+            .with_context(|| format!("Failed to write project {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read project {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Invalid project file {}", path.display()))
+    }
+}
+
+/// Channel layout of the written file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputChannels {
+    Mono,
+    Stereo,
+}
+
+impl OutputChannels {
+    pub fn count(&self) -> usize {
+        match self {
+            OutputChannels::Mono => 1,
+            OutputChannels::Stereo => 2,
+        }
+    }
+}
+
+/// Optional render settings passed alongside an `AudioScript`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderOptions {
+    pub execution_provider: ExecutionProvider,
+    /// Maximum characters per TTS call before text is split further
+    pub max_chunk_chars: Option<usize>,
+    /// Silence inserted between sentence chunks of a text node
+    pub chunk_pause_secs: Option<f32>,
+    /// Whether to trim leading/trailing silence from generated speech
+    pub trim: Option<bool>,
+    pub trim_threshold: Option<f32>,
+    pub trim_min_silence_ms: Option<f32>,
+    /// Linear gain applied to generated speech
+    pub tts_gain: Option<f32>,
+    /// Crossfade length at voice-change boundaries (0 = hard cut)
+    pub voice_crossfade_ms: Option<f32>,
+    /// Seed for reproducible random choices
+    pub seed: Option<u64>,
+    /// Share of the progress bar reserved for downloads when files are missing
+    pub download_weight: Option<f32>,
+    /// Fail on unknown tags or attributes instead of emitting warnings
+    pub strict: Option<bool>,
+    /// Replace an existing file with the same name (default). When false a
+    /// numbered name such as `Title (1).wav` is used instead.
+    pub overwrite: Option<bool>,
+    /// Embed title/artist `LIST/INFO` metadata in the WAV (default true)
+    pub embed_metadata: Option<bool>,
+    pub artist: Option<String>,
+    /// Remove DC offset from sound effects when they are loaded
+    pub remove_dc: Option<bool>,
+    /// Fold sound effects with more than two channels down to stereo on load
+    pub downmix_sounds: Option<bool>,
+    /// Expand abbreviations and units such as "Dr." and "km" (default true)
+    pub normalize_text: Option<bool>,
+    /// Abbreviation -> spoken form, merged over the defaults. An empty value
+    /// disables a default.
+    pub abbreviations: Option<HashMap<String, String>>,
+    /// Unit -> spoken form, expanded after numbers and merged like
+    /// `abbreviations`
+    pub units: Option<HashMap<String, String>>,
+    /// Currency for the `¤` sign and for reading ambiguous amounts such as
+    /// `1,50` (default USD)
+    pub currency: Option<Currency>,
+    /// Apply short fades where segments join to avoid clicks
+    pub declick: Option<bool>,
+    /// Speaker name -> voice key for `<speaker>` tags
+    pub speakers: Option<HashMap<String, String>>,
+    /// Amount (0..1) of seeded random speed variation per sentence chunk
+    pub humanize: Option<f32>,
+    /// Relative speed variation at full humanize (default 0.08)
+    pub humanize_range: Option<f32>,
+    /// Longest output in seconds a `<loop>` may produce (default 7200)
+    pub max_output_secs: Option<f32>,
+    /// Render `<overlay>` parts on separate threads (default true)
+    pub parallel_overlay: Option<bool>,
+    /// Also write each `<overlay>` part to `{basename}.stem{N}.wav`
+    pub export_stems: Option<bool>,
+    /// Emit each finished top-level segment as a `tts-audio-chunk` event
+    pub stream: Option<bool>,
+    /// Render this many seconds of silence for an empty or comment-only
+    /// script instead of failing
+    pub empty_silence_secs: Option<f32>,
+    /// Force every file to mono or stereo (default: whatever the script produces)
+    pub output_channels: Option<OutputChannels>,
+    /// Resample the finished audio to this rate (default: the model's 24000 Hz)
+    pub output_sample_rate: Option<u32>,
+    /// Run the finished audio through a peak limiter instead of letting
+    /// samples over full scale be clamped when written (default false)
+    pub limiter: Option<bool>,
+    /// Highest peak the limiter lets through, in dBFS (default -0.3)
+    pub limiter_ceiling_db: Option<f32>,
+    /// Effect presets for this render in the `presets.json` layout, consulted
+    /// before registered and file presets
+    pub presets: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Base directory for models, voices and sounds. Overrides
+    /// `DOMGPT_DATA_DIR`; both fall back to the app data directory.
+    pub data_dir: Option<PathBuf>,
+}
+
+/// Where a render reads its model, voices and sounds from
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderPaths {
+    pub onnx_dir: PathBuf,
+    pub voice_dir: PathBuf,
+    pub sound_effects_dir: PathBuf,
+    /// Bundled app assets, searched for built-in sound effects
+    pub resource_dir: Option<PathBuf>,
+}
+
+impl RenderPaths {
+    pub fn new(dirs: DataDirs, resource_dir: Option<PathBuf>) -> Self {
+        RenderPaths {
+            onnx_dir: dirs.onnx_dir,
+            voice_dir: dirs.voice_dir,
+            sound_effects_dir: dirs.sound_effects_dir,
+            resource_dir,
+        }
+    }
+}
+
+/// Where model, voice and sound effect files are cached
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataDirs {
+    pub onnx_dir: PathBuf,
+    pub voice_dir: PathBuf,
+    pub sound_effects_dir: PathBuf,
+}
+
+impl DataDirs {
+    /// Lay the caches out under `options.data_dir`, else `DOMGPT_DATA_DIR`,
+    /// else `app_data_dir`, so several installs can share one model cache
+    pub fn resolve(app_data_dir: &Path, options: &RenderOptions) -> Self {
+        let base = options
+            .data_dir
+            .clone()
+            .or_else(|| std::env::var_os(DATA_DIR_ENV).map(PathBuf::from))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| app_data_dir.to_path_buf());
+
+        DataDirs {
+            onnx_dir: base.join("models").join("onnx"),
+            voice_dir: base.join("models").join("voice_styles"),
+            sound_effects_dir: base.join("sounds"),
+        }
+    }
+}
+
+/// Unique-enough id tying progress events to one job
+fn new_job_id() -> String {
+    format!(
+        "tts-{}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    )
+}
+
+/// Wait for a render slot in the `JobQueue`, then render `script` with the
+/// app's model, voice and sound directories
+async fn render_queued(
+    app_handle: &AppHandle,
+    job_id: &str,
+    title: &str,
+    script: &str,
+    options: &RenderOptions,
+    timeout: Option<Duration>,
+) -> Result<RenderedAudio, String> {
+    let paths = app_render_paths(app_handle, options)?;
+    let _permit = wait_for_slot(app_handle, job_id, title).await?;
+
+    // Generate audio
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut ctx = prepare_app_context(app_handle, script, &paths, job_id, options).await?;
+    ctx.deadline = deadline;
+    let audio = render_with_context(&mut ctx, script).map_err(|e| e.to_string())?;
+    finish_render(ctx, audio).map_err(|e| e.to_string())
+}
+
+/// The app's model, voice and sound directories for a render with `options`
+fn app_render_paths(
+    app_handle: &AppHandle,
+    options: &RenderOptions,
+) -> Result<RenderPaths, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+
+    // Get resource directory for bundled assets (sound effects)
+    let resource_dir = app_handle.path().resource_dir().ok();
+
+    Ok(RenderPaths::new(
+        DataDirs::resolve(&app_data_dir, options),
+        resource_dir,
+    ))
+}
+
+/// `prepare_context` with the app's shared model, reporting progress as
+/// `tts-progress` events, plus what only the app has: registered sounds and
+/// presets, the asset catalog and streaming of finished segments
+async fn prepare_app_context(
+    app_handle: &AppHandle,
+    script: &str,
+    paths: &RenderPaths,
+    job_id: &str,
+    options: &RenderOptions,
+) -> Result<ScriptToAudioContext, String> {
+    let mut ctx = prepare_context(
+        script,
+        paths,
+        Some(Arc::new(app_handle.clone())),
+        job_id.to_string(),
+        options,
+        app_handle.state::<TtsState>().inner(),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if options.stream.unwrap_or(false) {
+        let handle = app_handle.clone();
+        let job_id = job_id.to_string();
+        ctx.chunk_sink = Some(Box::new(move |chunk: AudioChunk| {
+            let data = match encode_chunk(&chunk.audio) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to encode audio chunk {}: {}", chunk.index, e);
+                    return;
+                }
+            };
+            let _ = handle.emit(
+                "tts-audio-chunk",
+                AudioChunkEvent {
+                    job_id: job_id.clone(),
+                    index: chunk.index,
+                    is_final: chunk.is_final,
+                    data,
+                },
+            );
+        }));
+    }
+
+    ctx.custom_sounds = app_handle.state::<SoundRegistry>().snapshot();
+    // Presets passed with the render's options stay ahead of registered ones
+    for (effect, presets) in app_handle.state::<PresetRegistry>().snapshot() {
+        let existing = ctx.registered_presets.entry(effect).or_default();
+        for (name, preset) in presets {
+            existing.entry(name).or_insert(preset);
+        }
+    }
+    ctx.assets = app_handle
+        .state::<AssetCatalog>()
+        .snapshot(&ctx.voice_dir, &ctx.sound_effects_dir);
+    Ok(ctx)
+}
+
+/// Wait for a free slot in the `JobQueue`, reporting the queue position
+/// while waiting, then announce the start of the job
+async fn wait_for_slot<'a>(
+    app_handle: &'a AppHandle,
+    job_id: &str,
+    title: &str,
+) -> Result<JobPermit<'a>, String> {
+    let queue = app_handle.state::<JobQueue>().inner();
+    let permit = queue
+        .acquire(|position| {
+            app_handle.report(TtsProgressEvent {
+                job_id: job_id.to_string(),
+                message: format!("Queued at position {}", position),
+                progress: 0.0,
+                stage: "queued".to_string(),
+                index: None,
+            });
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Emit start progress
+    app_handle.report(TtsProgressEvent {
+        job_id: job_id.to_string(),
+        message: format!("Starting audio generation: {}", title),
+        progress: 0.0,
+        stage: "start".to_string(),
+        index: None,
+    });
+    Ok(permit)
+}
+
+/// Generate audio from script and save to file
+#[tauri::command]
+pub async fn generate_audio(
+    app_handle: AppHandle,
+    script: AudioScript,
+    options: Option<RenderOptions>,
+    timeout_secs: Option<f32>,
+    project: Option<ProjectBundle>,
+) -> Result<AudioScript, String> {
+    // A loaded project replaces the script text, seed and options; `script`
+    // still names the output file
+    let (script, mut options) = match project {
+        Some(project) => (
+            AudioScript {
+                title: project.title,
+                script: project.script,
+                seed: project.seed,
+                ..script
+            },
+            project.options,
+        ),
+        None => (script, options.unwrap_or_default()),
+    };
+    if script.seed.is_some() {
+        options.seed = script.seed;
+    }
+    let timeout = timeout_secs.map(|secs| Duration::from_secs_f32(secs.max(0.0)));
+    let job_id = new_job_id();
+
+    let rendered = render_queued(
+        &app_handle,
+        &job_id,
+        &script.title,
+        &script.script,
+        &options,
+        timeout,
+    )
+    .await?;
+
+    let result = save_rendered(&app_handle, &job_id, None, script, &rendered, &options)?;
+
+    // Emit completion
+    app_handle.report(TtsProgressEvent {
+        job_id: job_id.clone(),
+        message: "Audio generation complete".to_string(),
+        progress: 1.0,
+        stage: "complete".to_string(),
+        index: None,
+    });
+
+    Ok(result)
+}
+
+/// Write a rendered script to the app data directory as `generate_audio`
+/// does, returning the script with its output fields filled in
+fn save_rendered(
+    app_handle: &AppHandle,
+    job_id: &str,
+    index: Option<usize>,
+    script: AudioScript,
+    rendered: &RenderedAudio,
+    options: &RenderOptions,
+) -> Result<AudioScript, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+
+    // Write to file
+    let filename = match script.filename {
+        Some(ref filename) => sanitize_filename(filename),
+        None => format!("{}.wav", sanitize_filename(&script.title)),
+    };
+    let overwrite = options.overwrite.unwrap_or(true);
+    let info = WavInfo {
+        title: Some(script.title.clone()),
+        artist: options.artist.clone(),
+    };
+    let info = options.embed_metadata.unwrap_or(true).then_some(&info);
+
+    app_handle.report(TtsProgressEvent {
+        job_id: job_id.to_string(),
+        message: format!("Writing audio file: {}", filename),
+        progress: 0.99,
+        stage: "write".to_string(),
+        index,
+    });
+
+    let (filename, clipped) = write_audio_file(rendered, info, &app_data_dir, &filename, overwrite)
+        .map_err(|e| e.to_string())?;
+    if let Some(warning) = clip_warning(clipped, &rendered.audio) {
+        app_handle.report(TtsProgressEvent {
+            job_id: job_id.to_string(),
+            message: warning,
+            progress: 0.99,
+            stage: "warning".to_string(),
+            index,
+        });
+    }
+    let stems =
+        write_stems(&rendered.stems, &app_data_dir, &filename).map_err(|e| e.to_string())?;
+
+    Ok(AudioScript {
+        title: script.title,
+        script: script.script,
+        filename: Some(filename),
+        duration_secs: Some(rendered.audio.duration_secs()),
+        stems,
+        clipped_samples: Some(clipped),
+        seed: Some(rendered.seed),
+    })
+}
+
+/// Outcome of one script of a `generate_audio_batch` call
+#[derive(Clone, Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    /// The script with its output fields filled in, if it rendered
+    pub script: Option<AudioScript>,
+    pub error: Option<String>,
+}
+
+/// Render several scripts with one loaded model, writing each to its own
+/// file. Scripts that fail are reported in their result instead of stopping
+/// the batch.
+#[tauri::command]
+pub async fn generate_audio_batch(
+    app_handle: AppHandle,
+    scripts: Vec<AudioScript>,
+    options: Option<RenderOptions>,
+) -> Result<Vec<BatchItemResult>, String> {
+    let options = options.unwrap_or_default();
+    let job_id = new_job_id();
+    let paths = app_render_paths(&app_handle, &options)?;
+
+    let _permit = wait_for_slot(&app_handle, &job_id, "batch").await?;
+    // Prepare for every script at once so remote sounds are fetched and the
+    // model is loaded up front
+    let combined = scripts
+        .iter()
+        .map(|script| script.script.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut ctx = prepare_app_context(&app_handle, &combined, &paths, &job_id, &options).await?;
+
+    let results = render_batch_with_context(&mut ctx, &scripts, |index, script, rendered| {
+        let outcome = rendered.map_err(|e| e.to_string()).and_then(|rendered| {
+            save_rendered(
+                &app_handle,
+                &job_id,
+                Some(index),
+                script.clone(),
+                &rendered,
+                &options,
+            )
+        });
+        match outcome {
+            Ok(script) => BatchItemResult {
+                index,
+                script: Some(script),
+                error: None,
+            },
+            Err(error) => {
+                app_handle.report(TtsProgressEvent {
+                    job_id: job_id.clone(),
+                    message: format!("Failed to render {}: {}", script.title, error),
+                    progress: 1.0,
+                    stage: "warning".to_string(),
+                    index: Some(index),
+                });
+                BatchItemResult {
+                    index,
+                    script: None,
+                    error: Some(error),
+                }
+            }
+        }
+    });
+
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    app_handle.report(TtsProgressEvent {
+        job_id,
+        message: format!(
+            "Batch complete: {} rendered, {} failed",
+            results.len() - failed,
+            failed
+        ),
+        progress: 1.0,
+        stage: "complete".to_string(),
+        index: None,
+    });
+    Ok(results)
+}
+
+/// Render a script and play it on the default output device without writing
+/// a file. Any clip already playing is stopped. Resolves to the clip length in
+/// seconds once playback has started.
+#[tauri::command]
+pub async fn play_audio(
+    app_handle: AppHandle,
+    script: String,
+    options: Option<RenderOptions>,
+) -> Result<f32, String> {
+    let options = options.unwrap_or_default();
+    let job_id = new_job_id();
+    let rendered = render_queued(&app_handle, &job_id, "playback", &script, &options, None).await?;
+
+    let duration = rendered.audio.duration_secs();
+    app_handle
+        .state::<PlaybackState>()
+        .play(rendered.audio)
+        .map_err(|e| e.to_string())?;
+    Ok(duration)
+}
+
+/// Encode audio as base64 WAV bytes
+fn encode_chunk(audio: &AudioBuffer) -> Result<String> {
+    use base64::Engine;
+
+    let bytes = audio.to_wav_bytes()?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Encode audio as a `data:audio/wav;base64,...` URI
+fn wav_data_uri(audio: &AudioBuffer) -> Result<String> {
+    Ok(format!("data:audio/wav;base64,{}", encode_chunk(audio)?))
+}
+
+/// Render a script and return it as a `data:audio/wav;base64,...` URI the
+/// webview can play without file access. Meant for short clips and previews:
+/// the whole WAV is inlined, about 64 KB per second of mono audio.
+#[tauri::command]
+pub async fn preview_audio(
+    app_handle: AppHandle,
+    script: String,
+    options: Option<RenderOptions>,
+) -> Result<String, String> {
+    let options = options.unwrap_or_default();
+    let job_id = new_job_id();
+    let rendered = render_queued(&app_handle, &job_id, "preview", &script, &options, None).await?;
+    wav_data_uri(&rendered.audio).map_err(|e| e.to_string())
+}
+
+/// The same script in two voices as `data:audio/wav;base64,...` URIs
+#[derive(Clone, Debug, Serialize)]
+pub struct VoiceComparisonUris {
+    pub voice_a: String,
+    pub voice_b: String,
+    pub audio_a: String,
+    pub audio_b: String,
+    /// Both versions back to back, announced as "Version A" and "Version B"
+    pub combined: Option<String>,
+}
+
+/// Render a script once in each of two voices to compare them, ignoring the
+/// script's own voice changes. The model is loaded once for both renders.
+#[tauri::command]
+pub async fn compare_voices(
+    app_handle: AppHandle,
+    script: String,
+    voice_a: String,
+    voice_b: String,
+    separator: Option<bool>,
+    options: Option<RenderOptions>,
+) -> Result<VoiceComparisonUris, String> {
+    let options = options.unwrap_or_default();
+    let job_id = new_job_id();
+    let paths = app_render_paths(&app_handle, &options)?;
+
+    let _permit = wait_for_slot(&app_handle, &job_id, "voice comparison").await?;
+    let mut ctx = prepare_app_context(&app_handle, &script, &paths, &job_id, &options).await?;
+    let comparison = compare_voices_with_context(
+        &mut ctx,
+        &script,
+        &voice_a,
+        &voice_b,
+        separator.unwrap_or(false),
+    )
+    .map_err(|e| e.to_string())?;
+
+    let uri = |audio: &AudioBuffer| wav_data_uri(audio).map_err(|e| e.to_string());
+    Ok(VoiceComparisonUris {
+        audio_a: uri(&comparison.a)?,
+        audio_b: uri(&comparison.b)?,
+        combined: comparison.combined.as_ref().map(uri).transpose()?,
+        voice_a,
+        voice_b,
+    })
+}
+
+/// Render a script without saving it and report where the time went. Overlay
+/// parts render one after another so stage times add up to wall time.
+#[tauri::command]
+pub async fn profile_script(
+    app_handle: AppHandle,
+    script: String,
+    options: Option<RenderOptions>,
+) -> Result<RenderProfile, String> {
+    let started = Instant::now();
+    let mut options = options.unwrap_or_default();
+    options.parallel_overlay = Some(false);
+    let job_id = new_job_id();
+    let rendered = render_queued(&app_handle, &job_id, "profile", &script, &options, None).await?;
+
+    let mut profile = rendered.profile;
+    let write_started = Instant::now();
+    rendered.audio.to_wav_bytes().map_err(|e| e.to_string())?;
+    profile
+        .stages
+        .insert("write".to_string(), write_started.elapsed().as_secs_f64());
+    profile.total_secs = started.elapsed().as_secs_f64();
+    Ok(profile)
+}
+
+/// Stop backend playback. Resolves to whether a clip was still playing.
+#[tauri::command]
+pub async fn stop_playback(app_handle: AppHandle) -> Result<bool, String> {
+    Ok(app_handle.state::<PlaybackState>().stop())
+}
+
+/// Check a script for problems before rendering it, knowing the voice files
+/// a render with `options` would find
+#[tauri::command]
+pub async fn validate_script(
+    app_handle: AppHandle,
+    script: String,
+    options: Option<RenderOptions>,
+) -> Result<Vec<Diagnostic>, String> {
+    let paths = app_render_paths(&app_handle, &options.unwrap_or_default())?;
+    let assets = app_handle
+        .state::<AssetCatalog>()
+        .snapshot(&paths.voice_dir, &paths.sound_effects_dir);
+    Ok(check_script_with_assets(&script, &assets))
+}
+
+/// Register a sound effect usable as `<sound value="key">`, replacing any
+/// previous registration of the same key
+#[tauri::command]
+pub async fn register_sound_effect(
+    app_handle: AppHandle,
+    key: String,
+    source: SoundSource,
+) -> Result<(), String> {
+    // Reject sounds that would fail later in the middle of a job
+    source
+        .load()
+        .map_err(|e| format!("Invalid sound '{}': {}", key, e))?;
+    app_handle.state::<SoundRegistry>().register(key, source);
+    Ok(())
+}
+
+/// List built-in and registered sound effect keys
+#[tauri::command]
+pub async fn list_sound_effects(app_handle: AppHandle) -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = get_sound_effects().keys().map(|k| k.to_string()).collect();
+    let mut extra: Vec<String> = app_handle
+        .state::<SoundRegistry>()
+        .snapshot()
+        .into_keys()
+        .collect();
+    if let Ok(app_data_dir) = app_handle.path().app_data_dir() {
+        let dirs = DataDirs::resolve(&app_data_dir, &RenderOptions::default());
+        let scan = app_handle
+            .state::<AssetCatalog>()
+            .snapshot(&dirs.voice_dir, &dirs.sound_effects_dir);
+        extra.extend(scan.sounds.into_keys());
+    }
+    for key in extra {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+/// Write a script and its render settings to `path` as one JSON file
+#[tauri::command]
+pub async fn save_project(path: PathBuf, project: ProjectBundle) -> Result<(), String> {
+    project.save(&path).map_err(|e| e.to_string())
+}
+
+/// Read a project written by `save_project`
+#[tauri::command]
+pub async fn load_project(path: PathBuf) -> Result<ProjectBundle, String> {
+    ProjectBundle::load(&path).map_err(|e| e.to_string())
+}
+
+/// Save effect settings as a preset usable as `<effect value="effect"
+/// preset="name">`, replacing any previous registration of the same name
+#[tauri::command]
+pub async fn register_preset(
+    app_handle: AppHandle,
+    effect: String,
+    name: String,
+    options_json: String,
+) -> Result<(), String> {
+    if !KNOWN_EFFECTS.contains(&effect.as_str()) {
+        return Err(format!("Unknown effect '{}'", effect));
+    }
+    let options = EffectOptions::try_from_json(&options_json)
+        .map_err(|e| format!("Invalid {} preset '{}': {}", effect, name, e))?;
+    app_handle
+        .state::<PresetRegistry>()
+        .register(effect, name, options);
+    Ok(())
+}
+
+/// List built-in, `presets.json` and registered preset names for an effect
+#[tauri::command]
+pub async fn list_presets(app_handle: AppHandle, effect: String) -> Result<Vec<String>, String> {
+    let mut names = builtin_preset_names(&effect);
+    if let Ok(app_data_dir) = app_handle.path().app_data_dir() {
+        let dirs = DataDirs::resolve(&app_data_dir, &RenderOptions::default());
+        let (file_presets, _) =
+            load_effect_presets(&dirs.sound_effects_dir.with_file_name(PRESETS_FILE));
+        if let Some(presets) = file_presets.get(&effect) {
+            names.extend(presets.keys().cloned());
+        }
+    }
+    if let Some(presets) = app_handle.state::<PresetRegistry>().snapshot().get(&effect) {
+        names.extend(presets.keys().cloned());
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Scan the voice and sound effect directories again so files added while
+/// the app runs can be used by the next render. Resolves to what was found.
+#[tauri::command]
+pub async fn refresh_assets(
+    app_handle: AppHandle,
+    options: Option<RenderOptions>,
+) -> Result<AssetScan, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let dirs = DataDirs::resolve(&app_data_dir, &options.unwrap_or_default());
+    Ok(app_handle
+        .state::<AssetCatalog>()
+        .refresh(&dirs.voice_dir, &dirs.sound_effects_dir))
+}
+
+/// Set how many `generate_audio` jobs may render at once; the rest queue
+#[tauri::command]
+pub async fn set_max_concurrent_jobs(app_handle: AppHandle, max: usize) -> Result<(), String> {
+    app_handle
+        .state::<JobQueue>()
+        .set_limit(max)
+        .map_err(|e| e.to_string())
+}
+
+/// Check the cached model and voice files, re-downloading broken ones when
+/// `repair` is set
+#[tauri::command]
+pub async fn verify_models(
+    app_handle: AppHandle,
+    repair: Option<bool>,
+    options: Option<RenderOptions>,
+) -> Result<Vec<ModelFileStatus>, String> {
+    let options = options.unwrap_or_default();
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let dirs = DataDirs::resolve(&app_data_dir, &options);
+
+    Ok(verify_model_files(
+        &dirs.onnx_dir,
+        &dirs.voice_dir,
+        MODEL_REPO,
+        repair.unwrap_or(false),
+        Some(&app_handle),
+        "tts-verify",
+    )
+    .await)
+}
+
+/// Download and load the TTS model ahead of the first generation
+#[tauri::command]
+pub async fn warm_up_tts(
+    app_handle: AppHandle,
+    options: Option<RenderOptions>,
+) -> Result<(), String> {
+    let options = options.unwrap_or_default();
+    let job_id = "tts-warm-up".to_string();
+
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    let onnx_dir = DataDirs::resolve(&app_data_dir, &options).onnx_dir;
+
+    ensure_model_files(&onnx_dir, Some(&app_handle), &job_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let (_, used_provider) = app_handle
+        .state::<TtsState>()
+        .get_or_load(&onnx_dir, options.execution_provider, || {
+            load_text_to_speech_internal(&onnx_dir, options.execution_provider)
+        })
+        .map_err(|e| e.to_string())?;
+
+    app_handle.report(TtsProgressEvent {
+        job_id,
+        message: format!("Using {} for inference", used_provider.name()),
+        progress: 1.0,
+        stage: "load".to_string(),
+        index: None,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocess_script() {
+        // Test ellipsis replacement
+        let input = "Hello... world";
+        let result = preprocess_script(input);
+        assert!(result.contains(r#"<pause value="0.5"></pause>"#));
+
+        // Entities are left for the parser to decode
+        let input2 = "&amp; &lt; &gt;";
+        let result2 = preprocess_script(input2);
+        assert!(result2.contains("&amp; &lt; &gt;"));
+    }
+
+    #[test]
+    fn test_stray_angle_brackets_are_text() {
+        assert_eq!(
+            escape_stray_markup(r#"a < b & c > d <voice value="x>y">e</voice><!-- <n> -->"#),
+            r#"a &lt; b &amp; c &gt; d <voice value="x>y">e</voice><!-- <n> -->"#
+        );
+        assert_eq!(
+            escape_stray_markup("x<3 &amp; <<voice>"),
+            "x&lt;3 &amp; &lt;<voice>"
+        );
+        // Only script tags are markup
+        assert_eq!(
+            escape_stray_markup("vector<int> v, a<b>c"),
+            "vector&lt;int&gt; v, a&lt;b&gt;c"
+        );
+
+        let (mut ctx, calls) = mock_context("stray-markup");
+        render_with_context(&mut ctx, "Compare a < b in code, then x > y & z.").unwrap();
+        let text = calls.lock().unwrap()[0].0.clone();
+        assert_eq!(text, ". Compare a < b in code, then x > y & z.");
+
+        calls.lock().unwrap().clear();
+        render_with_context(&mut ctx, "Stop if x<y and y>z holds.").unwrap();
+        let text = calls.lock().unwrap()[0].0.clone();
+        assert_eq!(text, ". Stop if x<y and y>z holds.");
+    }
+
+    #[test]
+    fn test_entities_decoded_once() {
+        let (mut ctx, calls) = mock_context("entities");
+        render_with_context(&mut ctx, "Salt &amp;amp; pepper &lt;b&gt;.").unwrap();
+        let text = calls.lock().unwrap()[0].0.clone();
+        assert_eq!(text, ". Salt &amp; pepper <b>.");
+    }
+
+    #[test]
+    fn test_bracket_directives() {
+        assert_eq!(
+            expand_bracket_directives("Hello [pause 0.5] world [sound beep]"),
+            r#"Hello <pause value="0.5"></pause> world <sound value="beep"></sound>"#
+        );
+        assert_eq!(
+            expand_bracket_directives(r"a \[pause 1] b"),
+            "a [pause 1] b"
+        );
+        assert_eq!(
+            expand_bracket_directives("[not a directive]"),
+            "[not a directive]"
+        );
+
+        // Brackets inside tags are untouched
+        let tagged = r#"<effect value="pan" options='[pause 1]'>Hi</effect>"#;
+        assert_eq!(expand_bracket_directives(tagged), tagged);
+    }
+
+    #[test]
+    fn test_raw_text_is_verbatim() {
+        let (mut ctx, calls) = mock_context("raw-text");
+        render_with_context(&mut ctx, "<raw>a &amp; b...c <d></raw>").unwrap();
+        let text = calls.lock().unwrap()[0].0.clone();
+        assert_eq!(text, ". a &amp; b...c <d>");
+
+        assert!(check_script("<raw>a <vocie> b</raw>").is_empty());
+    }
+
+    #[test]
+    fn test_text_is_normalized_before_synthesis() {
+        let (mut ctx, calls) = mock_context("normalize");
+        render_with_context(&mut ctx, "Dr. Smith walked 5 km. <raw>Dr. Who</raw>").unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls[0].0, ". Doctor Smith walked 5 kilometers.");
+        assert_eq!(calls[1].0, ". Dr. Who");
+    }
+
+    #[test]
+    fn test_refresh_assets_finds_new_voice_files() {
+        let (mut ctx, _) = mock_context("refresh-assets");
+        let voice_dir = ctx.voice_dir.clone();
+        let sounds_dir = voice_dir.join("sounds");
+        let _ = fs::remove_file(voice_dir.join("Narrator.json"));
+
+        let catalog = AssetCatalog::default();
+        assert!(!catalog
+            .snapshot(&voice_dir, &sounds_dir)
+            .voices
+            .contains_key("narrator"));
+
+        // Added after the first scan: invisible until a refresh
+        fs::copy(voice_dir.join("M1.json"), voice_dir.join("Narrator.json")).unwrap();
+        assert!(!catalog
+            .snapshot(&voice_dir, &sounds_dir)
+            .voices
+            .contains_key("narrator"));
+
+        catalog.refresh(&voice_dir, &sounds_dir);
+        ctx.assets = catalog.snapshot(&voice_dir, &sounds_dir);
+        assert_eq!(ctx.assets.voices["narrator"], "Narrator.json");
+        let narrator = ctx.get_voice_style("Narrator").unwrap();
+        let male = ctx.get_voice_style("male").unwrap();
+        assert_eq!(narrator.ttl[[0, 0, 0]], male.ttl[[0, 0, 0]]);
+    }
+
+    #[test]
+    fn test_compare_voices_renders_each_voice() {
+        let (mut ctx, _) = mock_context("compare-voices");
+        let script = r#"Hello <voice value="male2">there</voice>."#;
+        let comparison =
+            compare_voices_with_context(&mut ctx, script, "female", "male", true).unwrap();
+
+        let voice_dir = ctx.voice_dir.clone();
+        assert!(ctx.style_cache.contains_key(&voice_dir.join("F1.json")));
+        assert!(ctx.style_cache.contains_key(&voice_dir.join("M1.json")));
+        // The script's own voice change is ignored
+        assert!(!ctx.style_cache.contains_key(&voice_dir.join("M2.json")));
+
+        assert_eq!(comparison.a.length(), comparison.b.length());
+        assert!(!comparison.a.approx_eq(&comparison.b, 1e-3));
+        let combined = comparison.combined.unwrap();
+        assert!(combined.length() > comparison.a.length() + comparison.b.length());
+    }
+
+    #[test]
+    fn test_audio_buffer_silence() {
+        let buffer = AudioBuffer::silence(1.0, 24000);
+        assert_eq!(buffer.length(), 24000);
+        assert_eq!(buffer.num_channels(), 1);
+    }
+
+    #[test]
+    fn test_audio_buffer_duration_secs() {
+        let buffer = AudioBuffer::new(1, 24000, 24000);
+        assert_eq!(buffer.duration_secs(), 1.0);
+    }
+
+    #[test]
+    fn test_audio_buffer_concat() {
+        let b1 = AudioBuffer::from_mono(vec![0.5; 100], 24000);
+        let b2 = AudioBuffer::from_mono(vec![-0.5; 100], 24000);
+        let result = AudioBuffer::concat(&[b1, b2]).unwrap();
+        assert_eq!(result.length(), 200);
+    }
+
+    #[test]
+    fn test_concat_declicked_smooths_boundary() {
+        let max_delta = |buffer: &AudioBuffer| {
+            let data = buffer.get_channel_data(0);
+            data.windows(2)
+                .fold(0.0f32, |m, w| m.max((w[1] - w[0]).abs()))
+        };
+        let high = AudioBuffer::from_mono(vec![0.8; 1000], 24000);
+        let low = AudioBuffer::from_mono(vec![-0.8; 1000], 24000);
+
+        let hard = AudioBuffer::concat(&[high.clone(), low.clone()]).unwrap();
+        let smooth = AudioBuffer::concat_declicked(&[high, low], DECLICK_FADE_SECS).unwrap();
+        assert!(max_delta(&hard) > 1.5);
+        assert!(max_delta(&smooth) < 0.05);
+        assert_eq!(smooth.length(), hard.length());
+    }
+
+    #[test]
+    fn test_concat_matches_resample_then_copy() {
+        // The straightforward version: convert everything, then copy
+        fn reference(buffers: &[AudioBuffer]) -> AudioBuffer {
+            let rate = buffers[0].sample_rate;
+            let channels = buffers.iter().map(|b| b.num_channels()).max().unwrap();
+            let converted: Vec<AudioBuffer> = buffers
+                .iter()
+                .map(|b| b.resample(rate).conform_channels(channels))
+                .collect();
+            AudioBuffer {
+                samples: (0..channels)
+                    .map(|ch| {
+                        converted
+                            .iter()
+                            .flat_map(|b| b.get_channel_data(ch).to_vec())
+                            .collect()
+                    })
+                    .collect(),
+                sample_rate: rate,
+            }
+        }
+
+        let ramp = |len: usize, scale: f32| -> Vec<f32> {
+            (0..len).map(|i| (i as f32 * scale).sin()).collect()
+        };
+        let buffers = vec![
+            AudioBuffer::from_mono(ramp(1000, 0.01), 24000),
+            AudioBuffer::from_stereo(ramp(777, 0.02), ramp(777, 0.03), 48000),
+            AudioBuffer::from_mono(ramp(333, 0.05), 16000),
+            AudioBuffer::from_stereo(ramp(501, 0.07), ramp(501, 0.011), 22050),
+            AudioBuffer::from_mono(Vec::new(), 44100),
+        ];
+
+        for start in 0..buffers.len() - 1 {
+            let slice = &buffers[start..];
+            let concatenated = AudioBuffer::concat(slice).unwrap();
+            let expected = reference(slice);
+            assert_eq!(concatenated.sample_rate, expected.sample_rate);
+            assert_eq!(concatenated.samples, expected.samples);
+        }
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let buffer = AudioBuffer::from_stereo(vec![0.5, -0.25, 0.1], vec![0.0, 0.3, -0.9], 24000);
+        assert!(buffer.approx_eq(&buffer, 0.0));
+
+        let scaled = apply_volume(&buffer, 0.9);
+        assert!(!buffer.approx_eq(&scaled, 0.01));
+        assert!(buffer.approx_eq(&scaled, 0.1));
+
+        // Layout and rate must match regardless of tolerance
+        let mono = AudioBuffer::from_mono(vec![0.5, -0.25, 0.1], 24000);
+        assert!(!buffer.approx_eq(&mono, 1.0));
+        let mut shorter = buffer.clone();
+        shorter.truncate(2);
+        assert!(!buffer.approx_eq(&shorter, 1.0));
+        let resampled = AudioBuffer {
+            sample_rate: 48000,
+            ..buffer.clone()
+        };
+        assert!(!buffer.approx_eq(&resampled, 1.0));
+    }
+
+    #[test]
+    fn test_rate_policy_picks_target_rate() {
+        let low = AudioBuffer::from_mono(vec![0.25; 240], 24000);
+        let high = AudioBuffer::from_mono(vec![0.5; 480], 48000);
+
+        // The default still follows the first buffer
+        let first = AudioBuffer::concat(&[low.clone(), high.clone()]).unwrap();
+        assert_eq!(first.sample_rate, 24000);
+        assert_eq!(first.length(), 480);
+
+        let max = AudioBuffer::concat_at(&[low.clone(), high.clone()], RatePolicy::Max).unwrap();
+        assert_eq!(max.sample_rate, 48000);
+        assert_eq!(max.length(), 960);
+        assert_eq!(max.get_channel_data(0)[959], 0.5);
+
+        let fixed =
+            AudioBuffer::concat_at(&[low.clone(), high.clone()], RatePolicy::Fixed(16000)).unwrap();
+        assert_eq!(fixed.sample_rate, 16000);
+        assert_eq!(fixed.length(), 320);
+
+        let mixed = AudioBuffer::merge_at(&[low, high], RatePolicy::Max).unwrap();
+        assert_eq!(mixed.sample_rate, 48000);
+        assert_eq!(mixed.length(), 480);
+    }
+
+    #[test]
+    fn test_concat_with_gap() {
+        let buffers: Vec<AudioBuffer> = (1..=3)
+            .map(|i| AudioBuffer::from_mono(vec![i as f32; 100], 1000))
+            .collect();
+
+        let spaced = AudioBuffer::concat_with_gap(&buffers, 0.05).unwrap();
+        assert_eq!(spaced.length(), 300 + 2 * 50);
+        let data = spaced.get_channel_data(0);
+        assert_eq!(data[99], 1.0);
+        assert!(data[100..150].iter().all(|&s| s == 0.0));
+        assert_eq!(data[150], 2.0);
+        assert_eq!(data[399], 3.0);
+
+        let plain = AudioBuffer::concat(&buffers).unwrap();
+        assert_eq!(
+            AudioBuffer::concat_with_gap(&buffers, 0.0).unwrap().samples,
+            plain.samples
+        );
+    }
+
+    #[test]
+    fn test_audio_buffer_slice() {
+        let buffer = AudioBuffer::from_stereo(
+            (0..100).map(|i| i as f32).collect(),
+            (0..100).map(|i| -(i as f32)).collect(),
+            24000,
+        );
+
+        let slice = buffer.slice(10, 20);
+        assert_eq!(slice.num_channels(), 2);
+        assert_eq!(slice.length(), 10);
+        assert_eq!(slice.samples[0][0], 10.0);
+        assert_eq!(slice.samples[1][9], -19.0);
+
+        // Out-of-range indices clamp instead of panicking
+        assert_eq!(buffer.slice(90, 500).length(), 10);
+        assert_eq!(buffer.slice(50, 10).length(), 1);
+        assert_eq!(buffer.slice(200, 300).length(), 1);
+    }
+
+    #[test]
+    fn test_audio_buffer_trim() {
+        let buffer = AudioBuffer::from_mono((0..24000).map(|i| i as f32).collect(), 24000);
+
+        let trimmed = buffer.trim(0.25, 0.5);
+        assert_eq!(trimmed.length(), 6000);
+        assert_eq!(trimmed.samples[0][0], 6000.0);
+        assert_eq!(trimmed.sample_rate, 24000);
+
+        assert_eq!(buffer.trim(0.75, 0.75).length(), 1);
+        assert_eq!(buffer.trim(-1.0, 0.0).length(), 24000);
+    }
+
+    #[test]
+    fn test_audio_buffer_pad() {
+        let buffer = AudioBuffer::from_stereo(vec![0.5; 100], vec![-0.5; 100], 24000);
+
+        let padded = buffer.pad(0.0625, 0.125);
+        assert_eq!(padded.num_channels(), 2);
+        assert_eq!(padded.length(), 1500 + 100 + 3000);
+        assert_eq!(padded.samples[0][1499], 0.0);
+        assert_eq!(padded.samples[0][1500], 0.5);
+        assert_eq!(padded.samples[1][1599], -0.5);
+        assert_eq!(padded.samples[1][1600], 0.0);
+
+        assert_eq!(buffer.pad(-1.0, -1.0).length(), 100);
+    }
+
+    #[test]
+    fn test_gain_db_and_metering() {
+        let buffer = AudioBuffer::from_mono(vec![0.1, -0.2, 0.05], 24000);
+        assert!((buffer.peak() - 0.2).abs() < 1e-6);
+
+        let boosted = buffer.gain_db(6.0);
+        assert!((boosted.peak() / buffer.peak() - 2.0).abs() < 0.01);
+
+        let square = AudioBuffer::from_mono(
+            (0..1000)
+                .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+                .collect(),
+            24000,
+        );
+        assert!((square.rms() - 1.0).abs() < 1e-6);
+
+        let empty = AudioBuffer::new(1, 0, 24000);
+        assert_eq!(empty.peak(), 0.0);
+        assert_eq!(empty.rms(), 0.0);
+    }
+
+    #[test]
+    fn test_truncated_wav_pads_last_frame() {
+        let mut bytes = Vec::new();
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut bytes), spec).unwrap();
+        for sample in [1000i16, -1000, 2000, -2000, 3000, -3000, 4000, -4000] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        // Cut the download off inside the third frame's right sample
+        bytes.truncate(bytes.len() - 5);
+        let buffer = AudioBuffer::from_bytes(&bytes).unwrap();
+        assert_eq!(buffer.num_channels(), 2);
+        assert_eq!(buffer.length(), 3);
+        assert_eq!(buffer.get_channel_data(0)[2], 3000.0 / 32768.0);
+        assert_eq!(buffer.get_channel_data(1)[2], 0.0);
+
+        assert_eq!(
+            deinterleave(vec![0.1, 0.2, 0.3], 2).unwrap(),
+            vec![vec![0.1, 0.3], vec![0.2, 0.0]]
+        );
+        assert!(deinterleave(vec![0.1], 0).is_err());
+    }
+
+    #[test]
+    fn test_multichannel_sound_downmixes_on_import() {
+        let quad = AudioBuffer {
+            samples: vec![
+                vec![0.4; 100],
+                vec![0.2; 100],
+                vec![0.0; 100],
+                vec![0.6; 100],
+            ],
+            sample_rate: 24000,
+        };
+        let bytes = quad.to_wav_bytes().unwrap();
+
+        let registry = SoundRegistry::default();
+        registry.register("quad".to_string(), SoundSource::Bytes(bytes));
+        let (mut ctx, _) = mock_context("downmix-import");
+        ctx.custom_sounds = registry.snapshot();
+        assert_eq!(ctx.fetch_sound_effect("quad").unwrap().num_channels(), 4);
+
+        ctx.sound_cache.clear();
+        ctx.downmix_on_import = true;
+        let stereo = ctx.fetch_sound_effect("quad").unwrap();
+        assert_eq!(stereo.num_channels(), 2);
+        // Even channels fold into the left, odd ones into the right
+        assert!((stereo.get_channel_data(0)[0] - 0.2).abs() < 1e-3);
+        assert!((stereo.get_channel_data(1)[0] - 0.4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_remove_dc() {
+        let left: Vec<f32> = (0..100)
+            .map(|i| 0.3 + if i % 2 == 0 { 0.1 } else { -0.1 })
+            .collect();
+        let right = vec![-0.25; 100];
+        let centered = AudioBuffer::from_stereo(left, right, 24000).remove_dc();
+        for channel in &centered.samples {
+            let mean: f32 = channel.iter().sum::<f32>() / channel.len() as f32;
+            assert!(mean.abs() < 1e-6);
+        }
+        assert!((centered.samples[0][0] - 0.1).abs() < 1e-6);
+        assert!(centered.samples[1].iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_split_channels_and_to_stereo() {
+        let stereo = AudioBuffer::from_stereo(vec![0.1, 0.2], vec![0.3, 0.4], 24000);
+        let split = stereo.split_channels();
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].samples, vec![vec![0.1, 0.2]]);
+        assert_eq!(split[1].samples, vec![vec![0.3, 0.4]]);
+        assert_eq!(split[1].sample_rate, 24000);
+
+        let mono = AudioBuffer::from_mono(vec![0.5, -0.5, 0.25], 24000);
+        let upmixed = mono.to_stereo();
+        assert_eq!(upmixed.num_channels(), 2);
+        assert_eq!(upmixed.samples[0], upmixed.samples[1]);
+        assert_eq!(upmixed.samples[0], mono.samples[0]);
+
+        let quad = AudioBuffer {
+            samples: vec![vec![0.2], vec![0.4], vec![0.6], vec![0.8]],
+            sample_rate: 24000,
+        };
+        let downmixed = quad.to_stereo();
+        assert!((downmixed.samples[0][0] - 0.4).abs() < 1e-6);
+        assert!((downmixed.samples[1][0] - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_apply_echo() {
+        let buffer = AudioBuffer::from_mono(vec![1.0; 1000], 24000);
+        let options = EffectOptions {
+            delay: Some(0.1),
+            decay: Some(0.5),
+            repeats: Some(2),
+            ..Default::default()
+        };
+        let result = apply_echo(&buffer, &options);
+        assert!(result.length() > buffer.length());
+    }
+
+    #[test]
+    fn test_bitcrush_reduces_distinct_values() {
+        let data: Vec<f32> = (0..2400)
+            .map(|i| 0.8 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 24000.0).sin())
+            .collect();
+        let input = AudioBuffer::from_mono(data, 24000);
+        let distinct = |buffer: &AudioBuffer| {
+            let mut values: Vec<i32> = buffer.samples[0]
+                .iter()
+                .map(|s| (s * 1e5).round() as i32)
+                .collect();
+            values.sort_unstable();
+            values.dedup();
+            values.len()
+        };
+
+        let options = EffectOptions {
+            bits: Some(4),
+            ..Default::default()
+        };
+        let crushed = apply_bitcrush(&input, &options);
+        assert!(distinct(&input) > 100);
+        assert!(distinct(&crushed) <= 17);
+        assert_eq!(crushed.length(), input.length());
+
+        let held = apply_bitcrush(
+            &input,
+            &EffectOptions {
+                bits: Some(16),
+                rate_reduction: Some(4),
+                ..Default::default()
+            },
+        );
+        let data = held.get_channel_data(0);
+        assert!(data
+            .chunks(4)
+            .all(|chunk| chunk.iter().all(|&s| s == chunk[0])));
+
+        let parsed = EffectOptions::from_json(r#"{"bits": 40, "rateReduction": 0}"#);
+        assert_eq!(parsed.bits, Some(40));
+        let clamped = apply_bitcrush(&input, &parsed);
+        assert_eq!(clamped.length(), input.length());
+    }
+
+    #[test]
+    fn test_ring_mod_of_constant_is_carrier_sine() {
+        let input = AudioBuffer::from_mono(vec![0.5; 24000], 24000);
+        let options = EffectOptions::from_json(r#"{"carrier": 100}"#);
+        let output = apply_ring_mod(&input, &options);
+        let data = output.get_channel_data(0);
+
+        for (i, &sample) in data.iter().enumerate().step_by(37) {
+            let expected = 0.5 * (2.0 * std::f32::consts::PI * 100.0 * i as f32 / 24000.0).sin();
+            assert!((sample - expected).abs() < 1e-3, "sample {}", i);
+        }
+
+        // One second of a 100 Hz sine crosses zero about 200 times
+        let crossings = data
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        assert!((198..=201).contains(&crossings), "{} crossings", crossings);
+    }
+
+    #[test]
+    fn test_robot_effect_is_bounded_and_deterministic() {
+        // A vowel-like tone swelling in and out
+        let input = AudioBuffer::from_mono(
+            (0..24000)
+                .map(|i| {
+                    let t = i as f32 / 24000.0;
+                    let swell = (std::f32::consts::PI * t).sin();
+                    0.8 * swell * (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+                })
+                .collect(),
+            24000,
+        );
+        let options = EffectOptions::from_json(r#"{"carrier": 90}"#);
+        let output = apply_robot(&input, &options);
+
+        assert_eq!(output.length(), input.length());
+        assert!(output.samples[0].iter().all(|s| s.is_finite()));
+        assert!(output.peak() <= 1.0);
+        assert!(output.rms() > 0.05);
+
+        // Little of the original waveform survives
+        let dot: f32 = input.samples[0]
+            .iter()
+            .zip(&output.samples[0])
+            .map(|(a, b)| a * b)
+            .sum();
+        let correlation = dot / (input.rms() * output.rms() * input.length() as f32);
+        assert!(correlation.abs() < 0.5, "correlation {}", correlation);
+
+        assert_eq!(apply_robot(&input, &options).samples, output.samples);
+    }
+
+    #[test]
+    fn test_doppler_pitch_rises_then_falls() {
+        let input = AudioBuffer::from_mono(
+            (0..48000)
+                .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 24000.0).sin())
+                .collect(),
+            24000,
+        );
+        let options = EffectOptions::from_json(r#"{"speed": 30, "closestDistance": 2}"#);
+        let output = apply_doppler(&input, &options);
+        let mono = output.to_mono();
+        let crossings = |data: &[f32]| {
+            data.windows(2)
+                .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+                .count()
+        };
+
+        // 0.3s of the 1 kHz input crosses zero about 600 times
+        let window = 7200;
+        let original = crossings(&input.samples[0][..window]);
+        let approaching = crossings(&mono[..window]);
+        let receding = crossings(&mono[mono.len() - window..]);
+        assert!(
+            approaching > original + 30,
+            "{} vs {}",
+            approaching,
+            original
+        );
+        assert!(receding + 30 < original, "{} vs {}", receding, original);
+
+        assert_eq!(output.num_channels(), 2);
+        assert!(output.length().abs_diff(input.length()) < 2400);
+    }
+
+    #[test]
+    fn test_spatial_azimuth_sets_interaural_differences() {
+        let mut click = vec![0.0; 1000];
+        click[100] = 0.5;
+        let input = AudioBuffer::from_mono(click, 24000);
+        let place = |azimuth: f32| {
+            let options = EffectOptions {
+                azimuth: Some(azimuth),
+                ..Default::default()
+            };
+            apply_spatial(&input, &options)
+        };
+        let arrival = |data: &[f32]| data.iter().position(|s| s.abs() > 0.01).unwrap();
+        let level = |data: &[f32]| data.iter().map(|s| s * s).sum::<f32>();
+
+        let front = place(0.0);
+        assert_eq!(front.length(), input.length());
+        assert_eq!(front.samples[0], front.samples[1]);
+
+        let right = place(60.0);
+        let (left_ear, right_ear) = (right.get_channel_data(0), right.get_channel_data(1));
+        assert!(arrival(right_ear) + 5 < arrival(left_ear));
+        assert!(level(right_ear) > level(left_ear) * 1.5);
+
+        // Mirrored for the other side
+        let left = place(-60.0);
+        let mirrored = left.samples[0]
+            .iter()
+            .zip(&right.samples[1])
+            .chain(left.samples[1].iter().zip(&right.samples[0]))
+            .all(|(a, b)| (a - b).abs() < 1e-4);
+        assert!(mirrored);
+
+        // A wider angle means a larger time difference
+        let far_right = place(90.0);
+        let itd = |buffer: &AudioBuffer| {
+            arrival(buffer.get_channel_data(0)) - arrival(buffer.get_channel_data(1))
+        };
+        assert!(itd(&far_right) > itd(&right));
+    }
+
+    /// Power-weighted mean frequency of `data`, in Hz
+    fn spectral_centroid(data: &[f32], sample_rate: u32) -> f32 {
+        let mut spectrum: Vec<Complex<f32>> = data.iter().map(|&s| Complex::new(s, 0.0)).collect();
+        FftPlanner::new()
+            .plan_fft_forward(spectrum.len())
+            .process(&mut spectrum);
+        let hz_per_bin = sample_rate as f32 / spectrum.len() as f32;
+        let (weighted, total) = spectrum[..spectrum.len() / 2].iter().enumerate().fold(
+            (0.0f32, 0.0f32),
+            |(weighted, total), (bin, value)| {
+                let power = value.norm_sqr();
+                (weighted + power * bin as f32 * hz_per_bin, total + power)
+            },
+        );
+        weighted / total
+    }
+
+    #[test]
+    fn test_autowah_follows_loudness() {
+        let quiet = add_noise(&AudioBuffer::new(1, 12000, 24000), 0.05, 1);
+        let loud = add_noise(&AudioBuffer::new(1, 12000, 24000), 0.8, 2);
+        let input = AudioBuffer::concat(&[quiet, loud]).unwrap();
+        let output = apply_autowah(&input, &EffectOptions::default());
+        let data = output.get_channel_data(0);
+
+        assert_eq!(output.length(), input.length());
+        assert!(output.peak() <= 1.0);
+        // Skip the envelope settling at the start of each region
+        let quiet_centroid = spectral_centroid(&data[2400..12000], 24000);
+        let loud_centroid = spectral_centroid(&data[14400..24000], 24000);
+        assert!(
+            loud_centroid > quiet_centroid * 1.5,
+            "{} vs {}",
+            loud_centroid,
+            quiet_centroid
+        );
+    }
+
+    #[test]
+    fn test_denoise_lowers_noise_floor_and_keeps_tone() {
+        // Half a second of hiss, then a tone over the same hiss
+        let clean: Vec<f32> = (0..36000)
+            .map(|i| {
+                if i < 12000 {
+                    0.0
+                } else {
+                    0.3 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 24000.0).sin()
+                }
+            })
+            .collect();
+        let noisy = add_noise(&AudioBuffer::from_mono(clean, 24000), 0.05, 7);
+        let cleaned = noisy.denoise(1.5);
+        assert_eq!(cleaned.length(), noisy.length());
+
+        let section_rms = |buffer: &AudioBuffer, range: std::ops::Range<usize>| {
+            AudioBuffer::from_mono(buffer.get_channel_data(0)[range].to_vec(), 24000).rms()
+        };
+        let hiss_before = section_rms(&noisy, 2000..10000);
+        let hiss_after = section_rms(&cleaned, 2000..10000);
+        assert!(
+            hiss_after < hiss_before * 0.3,
+            "{} -> {}",
+            hiss_before,
+            hiss_after
+        );
+
+        // Amplitude at 440 Hz over whole cycles of the tone section
+        let amplitude = |buffer: &AudioBuffer| {
+            let data = &buffer.get_channel_data(0)[18000..30000];
+            let (re, im) = data
+                .iter()
+                .enumerate()
+                .fold((0.0f32, 0.0f32), |(re, im), (i, s)| {
+                    let phase = 2.0 * std::f32::consts::PI * 440.0 * i as f32 / 24000.0;
+                    (re + s * phase.cos(), im + s * phase.sin())
+                });
+            2.0 * (re * re + im * im).sqrt() / data.len() as f32
+        };
+        let tone = amplitude(&cleaned);
+        assert!((tone - 0.3).abs() < 0.03, "tone amplitude {}", tone);
+
+        assert_eq!(noisy.denoise(0.0).samples, noisy.samples);
+    }
+
+    #[test]
+    fn test_distortion_drive_adds_odd_harmonics() {
+        let tone = AudioBuffer::from_mono(
+            (0..24000)
+                .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 24000.0).sin())
+                .collect(),
+            24000,
+        );
+        // Power at one frequency (whole cycles fit the one-second buffer)
+        let power_at = |data: &[f32], hz: f32| {
+            let (re, im) = data
+                .iter()
+                .enumerate()
+                .fold((0.0f32, 0.0f32), |(re, im), (i, s)| {
+                    let phase = 2.0 * std::f32::consts::PI * hz * i as f32 / 24000.0;
+                    (re + s * phase.cos(), im + s * phase.sin())
+                });
+            re * re + im * im
+        };
+        let odd_ratio = |drive: f32| {
+            let options = EffectOptions {
+                drive: Some(drive),
+                tone: Some(10000.0),
+                ..Default::default()
+            };
+            let output = apply_distortion(&tone, &options);
+            assert!(output.peak() <= 1.0);
+            let data = output.get_channel_data(0);
+            let odd: f32 = [3.0, 5.0, 7.0]
+                .iter()
+                .map(|h| power_at(data, 440.0 * h))
+                .sum();
+            odd / power_at(data, 440.0)
+        };
+
+        let ratios: Vec<f32> = [1.0, 4.0, 16.0].iter().map(|&d| odd_ratio(d)).collect();
+        assert!(ratios[1] > ratios[0] * 2.0, "{:?}", ratios);
+        assert!(ratios[2] > ratios[1], "{:?}", ratios);
+
+        let extreme = EffectOptions {
+            drive: Some(1e9),
+            ..Default::default()
+        };
+        let output = apply_distortion(&tone, &extreme);
+        assert!(output.samples[0]
+            .iter()
+            .all(|s| s.is_finite() && s.abs() <= 1.0));
+        assert!(get_effect_preset("distortion", "fuzz").is_some());
+    }
+
+    #[test]
+    fn test_ping_pong_alternates_channels() {
+        let mut left = vec![0.0; 100];
+        left[0] = 1.0;
+        let input = AudioBuffer::from_stereo(left, vec![0.0; 100], 1000);
+        let output = apply_ping_pong(&input, 0.1, 0.5, 2);
+
+        assert_eq!(output.num_channels(), 2);
+        assert_eq!(output.length(), 300);
+        let (l, r) = (output.get_channel_data(0), output.get_channel_data(1));
+        assert_eq!((l[0], r[0]), (1.0, 0.0));
+        // First echo bounces to the right, second comes back left
+        assert_eq!((l[100], r[100]), (0.0, 0.5));
+        assert_eq!((l[200], r[200]), (0.25, 0.0));
+
+        // Mono input is upmixed, so the dry signal sits in both channels
+        let mono = AudioBuffer::from_mono(vec![0.5; 10], 1000);
+        let output = apply_ping_pong(&mono, 0.01, 0.5, 1);
+        assert_eq!(output.num_channels(), 2);
+        assert_eq!(output.get_channel_data(0)[15], 0.25);
+        assert_eq!(output.get_channel_data(1)[5], 0.5);
+    }
+
+    #[test]
+    fn test_haas_delays_right_channel() {
+        let data: Vec<f32> = (0..500).map(|i| (i as f32 * 0.01).sin()).collect();
+        let input = AudioBuffer::from_mono(data.clone(), 1000);
+        let output = apply_haas(&input, &EffectOptions::from_json(r#"{"delayMs": 20}"#));
+
+        assert_eq!(output.num_channels(), 2);
+        let (left, right) = (output.get_channel_data(0), output.get_channel_data(1));
+        assert_eq!(&left[..500], &data[..]);
+        assert!(right[..20].iter().all(|&s| s == 0.0));
+        assert_eq!(&right[20..520], &data[..]);
+
+        // Delays beyond 40ms clamp
+        let clamped = apply_haas(
+            &input,
+            &EffectOptions {
+                delay_ms: Some(100.0),
+                ..Default::default()
+            },
+        );
+        assert_eq!(clamped.length(), 540);
+    }
+
+    #[test]
+    fn test_gain_envelope_curves() {
+        let input = AudioBuffer::from_mono(vec![0.5; 101], 1000);
+
+        let linear = apply_gain_envelope(&input, 0.2, 1.0, AutomationCurve::Linear);
+        let data = linear.get_channel_data(0);
+        assert!((data[0] - 0.1).abs() < 1e-6);
+        assert!((data[50] - 0.3).abs() < 1e-6);
+        assert!((data[100] - 0.5).abs() < 1e-6);
+
+        let exp = apply_gain_envelope(&input, 0.25, 1.0, AutomationCurve::Exp);
+        let data = exp.get_channel_data(0);
+        assert!((data[0] - 0.125).abs() < 1e-6);
+        // Geometric midpoint of 0.25 and 1.0
+        assert!((data[50] - 0.25).abs() < 1e-5);
+        assert!((data[100] - 0.5).abs() < 1e-5);
+
+        // Negative gains clamp to silence, loud ones clamp the samples
+        let clamped = apply_gain_envelope(&input, -1.0, 10.0, AutomationCurve::Linear);
+        assert_eq!(clamped.get_channel_data(0)[0], 0.0);
+        assert_eq!(clamped.get_channel_data(0)[100], 1.0);
+    }
+
+    #[test]
+    fn test_automation_tag_ramps_block() {
+        let (mut ctx, _) = mock_context("automation");
+        let plain = render_with_context(&mut ctx, "Swell.").unwrap();
+        let ramped = render_with_context(
+            &mut ctx,
+            r#"<automation param="volume" from="0.2" to="1.0">Swell.</automation>"#,
+        )
+        .unwrap();
+
+        assert_eq!(ramped.length(), plain.length());
+        let last = (plain.length() - 1) as f32;
+        let (plain, ramped) = (plain.get_channel_data(0), ramped.get_channel_data(0));
+        assert_eq!(ramped[0], plain[0] * 0.2);
+        for (i, (&p, &r)) in plain.iter().zip(ramped).enumerate() {
+            let gain = 0.2 + 0.8 * i as f32 / last;
+            assert!((r - p * gain).abs() < 1e-5, "sample {}", i);
+        }
+    }
+
+    #[test]
+    fn test_sidechain_duck_follows_key() {
+        // Key is active for the middle second of three
+        let mut key = vec![0.0; 3000];
+        key[1000..2000].fill(0.5);
+        let key = AudioBuffer::from_mono(key, 1000);
+        let target = AudioBuffer::from_mono(vec![0.5; 3000], 1000);
+
+        let ducked = apply_sidechain_duck(&target, &key, -12.0, 10.0, 100.0);
+        let data = ducked.get_channel_data(0);
+        let full = 0.5 * 10f32.powf(-12.0 / 20.0);
+
+        assert_eq!(data[500], 0.5);
+        assert!((data[1500] - full).abs() < 1e-3, "{}", data[1500]);
+        // Release eases back up rather than jumping
+        assert!(data[2300] > data[1500] && data[2300] < 0.49);
+        assert!((data[2999] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_duck_tag_in_overlay() {
+        let (mut ctx, _) = mock_context("duck");
+        let bed = "Background bed keeps going for a long while and then on and on without a break";
+        let mut render = |script: String| render_with_context(&mut ctx, &script).unwrap();
+
+        // The key part is muted in the mix, so only the ducked target is heard
+        let ducked = render(format!(
+            concat!(
+                r#"<overlay><duck key="voice" target="bed" amount="-20" release="20"/>"#,
+                r#"<part name="bed">{}</part>"#,
+                r#"<part name="voice" gain="0" offset="0.1">Hi.</part></overlay>"#
+            ),
+            bed
+        ));
+        let plain = render(format!("<overlay><part>{}</part></overlay>", bed));
+        let key = render(r#"<overlay><part offset="0.1">Hi.</part></overlay>"#.to_string());
+
+        let (ducked, plain) = (ducked.get_channel_data(0), plain.get_channel_data(0));
+        assert_eq!(ducked.len(), plain.len());
+        let key = key.get_channel_data(0);
+        let key_start = key.iter().position(|&s| s != 0.0).unwrap();
+        let key_end = key.iter().rposition(|&s| s != 0.0).unwrap();
+
+        // Dips while the key speaks, recovers by the end of the bed
+        let speaking = (key_start + key_end) / 2;
+        assert!(ducked[speaking] < plain[speaking] * 0.2);
+        let tail = plain.iter().rposition(|&s| s != 0.0).unwrap();
+        assert!(tail > key_end + 3000);
+        assert!(ducked[tail] > plain[tail] * 0.9);
+    }
+
+    #[test]
+    fn test_telephone_effect_band_limits() {
+        let (ctx, _) = mock_context("telephone");
+        let sample_rate = 24000;
+        let tone = |hz: f32| {
+            let data = (0..sample_rate)
+                .map(|i| 0.3 * (2.0 * std::f32::consts::PI * hz * i as f32 / 24000.0).sin())
+                .collect();
+            AudioBuffer::from_mono(data, sample_rate as u32)
+        };
+        // Skip the filter's start-up transient
+        let level = |buffer: &AudioBuffer| buffer.slice(4800, buffer.length()).rms();
+
+        let options = EffectOptions::default();
+        for (hz, passes) in [(100.0, false), (1000.0, true), (8000.0, false)] {
+            let input = tone(hz);
+            let output = ctx.apply_effect("telephone", &input, &options);
+            let ratio = level(&output) / level(&input);
+            if passes {
+                assert!(ratio > 0.5, "{} Hz ratio {}", hz, ratio);
+            } else {
+                assert!(ratio < 0.1, "{} Hz ratio {}", hz, ratio);
+            }
+        }
+    }
+
+    #[test]
+    fn test_whisper_is_quiet_and_thin() {
+        let (mut ctx, _) = mock_context("whisper");
+        let plain = render_with_context(&mut ctx, "Psst, over here.").unwrap();
+        let whisper = render_with_context(&mut ctx, "<whisper>Psst, over here.</whisper>").unwrap();
+        let mean = |buffer: &AudioBuffer| {
+            let data = buffer.get_channel_data(0);
+            data.iter().sum::<f32>() / data.len() as f32
+        };
+
+        assert_eq!(whisper.num_channels(), 2);
+        assert!(whisper.rms() < plain.rms() * 0.5);
+        // The mock voice is all low end (a constant level), which the
+        // high-pass removes
+        assert!(mean(&whisper).abs() < mean(&plain) * 0.05);
+    }
+
+    #[test]
+    fn test_merge_weighted() {
+        let voice = AudioBuffer::from_mono(vec![0.2; 100], 24000);
+        let effect = AudioBuffer::from_mono(vec![0.4; 100], 24000);
+
+        let buffers = [voice.clone(), effect.clone()];
+        let full = AudioBuffer::merge_weighted(&buffers, &[1.0, 1.0]).unwrap();
+        let halved = AudioBuffer::merge_weighted(&buffers, &[1.0, 0.5]).unwrap();
+        assert!((full.samples[0][0] - 0.6).abs() < 1e-6);
+        assert!((halved.samples[0][0] - 0.4).abs() < 1e-6);
+
+        assert!(AudioBuffer::merge_weighted(&[voice, effect], &[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_conform_channels_in_concat_and_merge() {
+        let mono = AudioBuffer::from_mono(vec![0.2; 4], 24000);
+        let stereo = AudioBuffer::from_stereo(vec![0.1; 4], vec![-0.1; 4], 24000);
+
+        // Mono into stereo concat is duplicated into both channels
+        let joined = AudioBuffer::concat(&[mono.clone(), stereo.clone()]).unwrap();
+        assert_eq!(joined.num_channels(), 2);
+        assert_eq!(
+            joined.samples[0],
+            vec![0.2, 0.2, 0.2, 0.2, 0.1, 0.1, 0.1, 0.1]
+        );
+        assert_eq!(
+            joined.samples[1],
+            vec![0.2, 0.2, 0.2, 0.2, -0.1, -0.1, -0.1, -0.1]
+        );
+
+        // Stereo merged into mono keeps its imaging on top of the mono bed
+        let mixed = AudioBuffer::merge(&[mono.clone(), stereo.clone()]).unwrap();
+        assert!(mixed.samples[0].iter().all(|s| (s - 0.3).abs() < 1e-6));
+        assert!(mixed.samples[1].iter().all(|s| (s - 0.1).abs() < 1e-6));
+
+        // Explicit downmix averages the channels
+        let down = stereo.conform_channels(1);
+        assert_eq!(down.num_channels(), 1);
+        assert!(down.samples[0].iter().all(|s| s.abs() < 1e-6));
+        assert_eq!(mono.conform_channels(2).samples, vec![vec![0.2; 4]; 2]);
+    }
+
+    #[test]
+    fn test_merge_normalized() {
+        let a = AudioBuffer::from_mono(vec![1.0, 0.5], 24000);
+        let b = AudioBuffer::from_mono(vec![1.0, 0.5], 24000);
+        let c = AudioBuffer::from_mono(vec![1.0, 0.0], 24000);
+
+        let mixed = AudioBuffer::merge_normalized(&[a, b, c]).unwrap();
+        let data = mixed.get_channel_data(0);
+        assert!(data[0] <= 1.0);
+        assert!((data[0] - 10f32.powf(-0.1 / 20.0)).abs() < 1e-5);
+        assert!((data[1] / data[0] - 1.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_effect_options_from_json() {
+        let json = r#"{"delay": 0.5, "decay": 0.3}"#;
+        let opts = EffectOptions::from_json(json);
+        assert_eq!(opts.delay, Some(0.5));
+        assert_eq!(opts.decay, Some(0.3));
+    }
+
+    #[test]
+    fn test_kuchiki_parsing() {
+        let html = "<root><voice value=\"female\">Hello world</voice></root>";
+        let document = kuchiki::parse_html().one(html);
+        let root = document.select_first("root").unwrap();
+        let voice = root.as_node().select_first("voice").unwrap();
+        let attrs = voice.as_node().as_element().unwrap().attributes.borrow();
+        assert_eq!(attrs.get("value"), Some("female"));
+    }
+
+    #[test]
+    fn test_session_builder_cpu() {
+        let (_, used) = session_builder(ExecutionProvider::Cpu).unwrap();
+        assert_eq!(used, ExecutionProvider::Cpu);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_session_builder_unavailable_provider_falls_back() {
+        // DirectML only exists on Windows, so registration must fail elsewhere
+        let (_, used) = session_builder(ExecutionProvider::DirectMl).unwrap();
+        assert_eq!(used, ExecutionProvider::Cpu);
+    }
+
+    /// Silent padding the mock engine adds around its output
+    const MOCK_PADDING: usize = 2400;
+
+    /// Length of the raw mock engine output for `text`
+    fn mock_len(text: &str) -> usize {
+        text.chars().count() * 100 + MOCK_PADDING * 2
+    }
+
+    /// Speech engine double returning a constant level proportional to the
+    /// text length padded with silence, recording every call it receives.
+    /// The level depends on the voice style (0.5 for F1, +0.1 per voice file).
+    #[derive(Default)]
+    struct MockEngine {
+        calls: Arc<Mutex<Vec<(String, SynthesisParams)>>>,
+    }
+
+    impl SpeechEngine for MockEngine {
+        fn sample_rate(&self) -> u32 {
+            24000
+        }
+
+        fn synthesize(
+            &mut self,
+            text: &str,
+            style: &Style,
+            params: &SynthesisParams,
+        ) -> Result<Vec<f32>> {
+            self.calls.lock().unwrap().push((text.to_string(), *params));
+            let level = 0.5 + 0.1 * style.ttl[[0, 0, 0]];
+            let mut wav = vec![0.0; MOCK_PADDING];
+            wav.extend(vec![level; text.chars().count() * 100]);
+            wav.extend(vec![0.0; MOCK_PADDING]);
+            Ok(wav)
+        }
+    }
+
+    /// Create a scratch directory with a minimal voice style file for each voice
+    fn test_voice_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("domgpt-test-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        let mut files: Vec<&str> = get_voices().values().copied().collect();
+        files.sort();
+        // Each voice gets a distinct style value so outputs can be told apart
+        for (i, file) in files.iter().enumerate() {
+            let style = format!(
+                r#"{{
+                    "style_ttl": {{"data": [[[{i}.0, 0.0]]], "dims": [1, 1, 2], "type": "float32"}},
+                    "style_dp": {{"data": [[[{i}.0, 0.0]]], "dims": [1, 1, 2], "type": "float32"}}
+                }}"#
+            );
+            fs::write(dir.join(file), style).unwrap();
+        }
+        dir
+    }
+
+    type MockCalls = Arc<Mutex<Vec<(String, SynthesisParams)>>>;
+
+    fn mock_context(name: &str) -> (ScriptToAudioContext, MockCalls) {
+        let engine = MockEngine::default();
+        let calls = engine.calls.clone();
+        let dir = test_voice_dir(name);
+        let ctx = ScriptToAudioContext::with_engine(Arc::new(Mutex::new(engine)), dir.clone(), dir)
+            .unwrap();
+        (ctx, calls)
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        let chunks = split_sentences("Dr. Smith paid 3.5 dollars. Really?\nYes!", 300);
+        assert_eq!(
+            chunks,
+            vec!["Dr. Smith paid 3.5 dollars.", "Really?", "Yes!"]
+        );
+
+        let chunks = split_sentences("one two three four", 9);
+        assert_eq!(chunks, vec!["one two", "three", "four"]);
+    }
+
+    #[test]
+    fn test_generate_tts_chunks_long_text() {
+        let (mut ctx, calls) = mock_context("chunks");
+        let single = ctx.generate_tts("This is one sentence.").unwrap();
+        let long = ctx
+            .generate_tts("This is one sentence. Here is another one! And a third?")
+            .unwrap();
+
+        assert!(long.length() > single.length());
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 4);
+        assert!(calls.iter().all(|(text, _)| text.starts_with(". ")));
+    }
+
+    #[test]
+    fn test_tts_tag_overrides_steps() {
+        let (mut ctx, calls) = mock_context("tts-tag");
+        render_with_context(
+            &mut ctx,
+            r#"Before. <tts steps="30" temperature="0.5">Inside.</tts> After."#,
+        )
+        .unwrap();
+
+        {
+            let calls = calls.lock().unwrap();
+            assert_eq!(calls.len(), 3);
+            assert_eq!(calls[0].1.total_step, DEFAULT_TTS_STEPS);
+            assert_eq!(calls[1].1.total_step, 30);
+            assert_eq!(calls[1].1.temperature, 0.5);
+            assert_eq!(calls[2].1.total_step, DEFAULT_TTS_STEPS);
+        }
+
+        render_with_context(&mut ctx, r#"<tts steps="1">Clamped.</tts>"#).unwrap();
+        let last = calls.lock().unwrap().last().unwrap().1;
+        assert_eq!(last.total_step, MIN_TTS_STEPS);
+    }
+
+    #[test]
+    fn test_quality_tag_sets_steps() {
+        let (mut ctx, calls) = mock_context("quality-tag");
+        render_with_context(
+            &mut ctx,
+            concat!(
+                r#"<quality value="fast">Quick.</quality> "#,
+                r#"<quality value="best">Careful.</quality> Plain."#
+            ),
+        )
+        .unwrap();
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].1.total_step, 15);
+        assert_eq!(calls[1].1.total_step, 60);
+        assert!(calls[0].1.total_step < calls[1].1.total_step);
+        assert_eq!(calls[2].1.total_step, DEFAULT_TTS_STEPS);
+
+        let diagnostics = check_script(r#"<quality value="ultra">Hi</quality>"#);
+        assert!(diagnostics[0].message.contains("Unknown quality"));
+        drop(calls);
+
+        // Unknown presets keep the current steps, or fail in strict mode
+        let script = r#"<quality value="ultra">Hi.</quality>"#;
+        assert!(render_with_context(&mut ctx, script).is_ok());
+        ctx.strict = true;
+        let err = render_with_context(&mut ctx, script).unwrap_err();
+        assert!(err.to_string().contains("ultra"), "{}", err);
+    }
+
+    #[test]
+    fn test_speed_ramp_steps_across_chunks() {
+        let (mut ctx, calls) = mock_context("speed-ramp");
+        render_with_context(
+            &mut ctx,
+            r#"<speed from="0.8" to="1.2">One. Two. Three.</speed> After."#,
+        )
+        .unwrap();
+
+        // The mapping generate_tts_chunk applies before calling the model
+        let model_speed = |speed: f32| 0.75 + (speed - 0.5) / 1.5 * 0.5;
+        let calls = calls.lock().unwrap();
+        let speeds: Vec<f32> = calls.iter().map(|(_, params)| params.speed).collect();
+        assert_eq!(speeds.len(), 4);
+        assert!((speeds[0] - model_speed(0.8)).abs() < 1e-4);
+        assert!((speeds[1] - model_speed(1.0)).abs() < 1e-4);
+        assert!((speeds[2] - model_speed(1.2)).abs() < 1e-4);
+        // Constant speed resumes after the block
+        assert!((speeds[3] - model_speed(1.0)).abs() < 1e-4);
+        drop(calls);
+
+        // Unspoken items and <audio> fallbacks don't stretch the ramp
+        let (mut ctx, calls) = mock_context("speed-ramp-random");
+        render_with_context(
+            &mut ctx,
+            concat!(
+                r#"<speed from="0.8" to="1.2">One. <random><item>Two.</item>"#,
+                r#"<item>Deux.</item></random> <audio src="pop">Pop.</audio> Three.</speed>"#
+            ),
+        )
+        .unwrap();
+        let speeds: Vec<f32> = calls.lock().unwrap().iter().map(|(_, p)| p.speed).collect();
+        assert_eq!(speeds.len(), 3);
+        assert!((speeds[2] - model_speed(1.2)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_nested_tags_apply_inside_out() {
+        let (mut ctx, _) = mock_context("nesting-order");
+        let plain = render_with_context(&mut ctx, "Words.").unwrap();
+        let echo = |audio: &AudioBuffer| apply_echo(audio, &EffectOptions::default());
+        let distort = |audio: &AudioBuffer| apply_distortion(audio, &EffectOptions::default());
+
+        let render = |ctx: &mut ScriptToAudioContext, script: &str| {
+            render_with_context(ctx, script).unwrap()
+        };
+        let echo_then_halve = render(
+            &mut ctx,
+            r#"<volume value="0.5"><effect value="echo">Words.</effect></volume>"#,
+        );
+        assert!(echo_then_halve.approx_eq(&apply_volume(&echo(&plain), 0.5), 1e-6));
+
+        // Distortion doesn't commute with gain, so these tell the orders apart
+        let distort_then_double = render(
+            &mut ctx,
+            r#"<volume value="2"><effect value="distortion">Words.</effect></volume>"#,
+        );
+        let double_then_distort = render(
+            &mut ctx,
+            r#"<effect value="distortion"><volume value="2">Words.</volume></effect>"#,
+        );
+        assert!(distort_then_double.approx_eq(&apply_volume(&distort(&plain), 2.0), 1e-6));
+        assert!(double_then_distort.approx_eq(&distort(&apply_volume(&plain, 2.0)), 1e-6));
+        assert!(!distort_then_double.approx_eq(&double_then_distort, 1e-3));
+
+        let distorted_echo = render(
+            &mut ctx,
+            r#"<effect value="distortion"><effect value="echo">Words.</effect></effect>"#,
+        );
+        assert!(distorted_echo.approx_eq(&distort(&echo(&plain)), 1e-6));
+
+        let ramp =
+            |audio: &AudioBuffer| apply_gain_envelope(audio, 0.5, 2.0, AutomationCurve::Linear);
+        let distort_then_ramp = render(
+            &mut ctx,
+            r#"<automation from="0.5" to="2"><effect value="distortion">Words.</effect></automation>"#,
+        );
+        let ramp_then_distort = render(
+            &mut ctx,
+            r#"<effect value="distortion"><automation from="0.5" to="2">Words.</automation></effect>"#,
+        );
+        assert!(distort_then_ramp.approx_eq(&ramp(&distort(&plain)), 1e-6));
+        assert!(ramp_then_distort.approx_eq(&distort(&ramp(&plain)), 1e-6));
+        assert!(!distort_then_ramp.approx_eq(&ramp_then_distort, 1e-3));
+    }
+
+    #[test]
+    fn test_presets_file_adds_user_presets() {
+        let dir = std::env::temp_dir().join("domgpt-test-presets-file");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(PRESETS_FILE);
+        fs::write(
+            &path,
+            r#"{
+                "echo": {"cathedral": {"delay": 0.45, "decay": 0.7}, "broken": {"delay": "long"}},
+                "pan": {"hard-left": {"pan": -0.8}},
+                "warp": {"x": {}},
+                "distortion": 3
+            }"#,
+        )
+        .unwrap();
+
+        let (presets, warnings) = load_effect_presets(&path);
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+
+        let (mut ctx, _) = mock_context("presets-file");
+        ctx.effect_presets = presets;
+        assert_eq!(
+            ctx.get_preset("echo", "cathedral").unwrap().delay,
+            Some(0.45)
+        );
+        assert_eq!(ctx.get_preset("pan", "hard-left").unwrap().pan, Some(-0.8));
+        assert!(ctx.get_preset("echo", "broken").is_none());
+        // Built-ins are still available
+        assert!(ctx.get_preset("distortion", "warm").is_some());
+
+        let (missing, warnings) = load_effect_presets(&dir.join("missing.json"));
+        assert!(missing.is_empty() && warnings.is_empty());
+    }
+
+    #[test]
+    fn test_channel_limits_effect_to_one_side() {
+        let (mut ctx, _) = mock_context("channel-side");
+        let plain = render_with_context(&mut ctx, "Words.").unwrap();
+        let echo =
+            r#"<effect value="echo" options='{"delay": 0.05, "decay": 0.8}'>Words.</effect>"#;
+
+        let right = render_with_context(
+            &mut ctx,
+            &format!(r#"<channel side="right">{}</channel>"#, echo),
+        )
+        .unwrap();
+        assert_eq!(right.num_channels(), 2);
+        let (left_channel, right_channel) = (&right.samples[0], &right.samples[1]);
+        // The left channel is the dry speech, padded to the echo tail
+        assert_eq!(&left_channel[..plain.length()], &plain.samples[0][..]);
+        assert!(left_channel[plain.length()..].iter().all(|&s| s == 0.0));
+        assert!(right_channel
+            .iter()
+            .zip(left_channel)
+            .any(|(r, l)| (r - l).abs() > 1e-3));
+
+        // Mono input has no side signal, so processing the mid stays centred
+        let mid = render_with_context(
+            &mut ctx,
+            &format!(r#"<channel side="mid">{}</channel>"#, echo),
+        )
+        .unwrap();
+        assert_eq!(mid.samples[0], mid.samples[1]);
+        assert!(mid.samples[0].len() > plain.length());
+
+        // Automation is limited to the side like any other processing
+        let fade = render_with_context(
+            &mut ctx,
+            r#"<channel side="right"><automation from="1" to="0">Words.</automation></channel>"#,
+        )
+        .unwrap();
+        let ramp = apply_gain_envelope(&plain, 1.0, 0.0, AutomationCurve::Linear);
+        assert_eq!(fade.samples[0], plain.samples[0]);
+        assert_eq!(fade.samples[1], ramp.samples[0]);
+    }
+
+    #[test]
+    fn test_malformed_voice_falls_back_unless_strict() {
+        let (mut ctx, _) = mock_context("malformed-voice");
+        fs::write(ctx.voice_dir.join("Broken.json"), "{ not a style").unwrap();
+        ctx.assets
+            .voices
+            .insert("broken".to_string(), "Broken.json".to_string());
+        let script = r#"<voice value="broken">Hello there.</voice>"#;
+
+        let fallback = render_with_context(&mut ctx, script).unwrap();
+        let default = render_with_context(&mut ctx, "Hello there.").unwrap();
+        assert!(fallback.approx_eq(&default, 1e-6));
+        // Later lookups reuse the fallback instead of retrying the file
+        let loads = ctx.style_loads;
+        render_with_context(&mut ctx, script).unwrap();
+        assert_eq!(ctx.style_loads, loads);
+
+        ctx.strict = true;
+        ctx.style_cache.clear();
+        assert!(render_with_context(&mut ctx, script).is_err());
+    }
+
+    #[test]
+    fn test_effect_mono_downmixes_binaural() {
+        let (mut ctx, _) = mock_context("effect-mono");
+        let stereo =
+            render_with_context(&mut ctx, r#"<effect value="binaural">Words.</effect>"#).unwrap();
+        assert_eq!(stereo.num_channels(), 2);
+
+        let mono = render_with_context(
+            &mut ctx,
+            r#"<effect value="binaural" mono="true">Words.</effect>"#,
+        )
+        .unwrap();
+        assert_eq!(mono.num_channels(), 1);
+        assert_eq!(mono.length(), stereo.length());
+        assert!(mono.samples[0].iter().any(|&s| s != 0.0));
+
+        let panned = render_with_context(
+            &mut ctx,
+            r#"<effect value="pan" options='{"pan": -1}' mono="true">Words.</effect>"#,
+        )
+        .unwrap();
+        assert_eq!(panned.num_channels(), 1);
+    }
+
+    #[test]
+    fn test_batch_continues_past_failed_script() {
+        let (mut ctx, calls) = mock_context("batch");
+        ctx.strict = true;
+        let script = |title: &str, text: &str| AudioScript {
+            title: title.to_string(),
+            script: text.to_string(),
+            filename: None,
+            duration_secs: None,
+            stems: Vec::new(),
+            clipped_samples: None,
+            seed: None,
+        };
+        let scripts = vec![
+            script("good", "First script."),
+            script("bad", "<bogus>Second script.</bogus>"),
+            script("also good", "Third script."),
+        ];
+
+        let results = render_batch_with_context(&mut ctx, &scripts, |index, script, rendered| {
+            (index, script.title.clone(), rendered.map(|r| r.audio))
+        });
+
+        assert_eq!(results.len(), 3);
+        let (index, title, audio) = &results[0];
+        assert_eq!((*index, title.as_str()), (0, "good"));
+        assert!(audio.as_ref().unwrap().length() > 0);
+        let (index, _, audio) = &results[1];
+        assert_eq!(*index, 1);
+        let error = audio.as_ref().err().unwrap().to_string();
+        assert!(error.contains("bogus"), "{}", error);
+        assert!(results[2].2.is_ok());
+        // The failed script never reached the engine
+        assert_eq!(calls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_project_bundle_round_trip() {
+        let mut options = RenderOptions {
+            seed: Some(7),
+            output_channels: Some(OutputChannels::Mono),
+            output_sample_rate: Some(44100),
+            chunk_pause_secs: Some(0.35),
+            ..Default::default()
+        };
+        options.speakers = Some(HashMap::from([
+            ("alice".to_string(), "F1".to_string()),
+            ("bob".to_string(), "M2".to_string()),
+            ("carol".to_string(), "F2".to_string()),
+        ]));
+        options.presets = serde_json::from_str(
+            r#"{"echo": {"hall": {"delay": 0.3, "decay": 0.6}, "room": {"delay": 0.1}}}"#,
+        )
+        .unwrap();
+        let project = ProjectBundle {
+            title: "Chapter One".to_string(),
+            script: r#"<speaker name="alice">Hello.</speaker>"#.to_string(),
+            seed: Some(42),
+            options,
+        };
+
+        let dir = std::env::temp_dir().join("domgpt-test-project-bundle");
+        let (first, second) = (dir.join("first.json"), dir.join("second.json"));
+        project.save(&first).unwrap();
+        let loaded = ProjectBundle::load(&first).unwrap();
+        loaded.save(&second).unwrap();
+
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+        assert_eq!(loaded.to_json().unwrap(), project.to_json().unwrap());
+        assert_eq!(loaded.seed, Some(42));
+        assert_eq!(loaded.options.output_channels, Some(OutputChannels::Mono));
+        assert_eq!(loaded.options.speakers.unwrap()["bob"], "M2");
+
+        let (presets, warnings) = parse_effect_presets(loaded.options.presets.unwrap());
+        assert!(warnings.is_empty());
+        assert_eq!(presets["echo"]["hall"].decay, Some(0.6));
+        assert!(ProjectBundle::load(&dir.join("missing.json")).is_err());
+    }
+
+    #[test]
+    fn test_registered_preset_overrides_builtin() {
+        let registry = PresetRegistry::default();
+        let loud = EffectOptions::try_from_json(r#"{"drive": 9, "mix": 1}"#).unwrap();
+        registry.register("distortion".to_string(), "warm".to_string(), loud);
+        registry.register(
+            "echo".to_string(),
+            "cathedral".to_string(),
+            EffectOptions::from_json(r#"{"delay": 0.3}"#),
+        );
+        // Re-registering replaces the earlier options
+        registry.register(
+            "echo".to_string(),
+            "cathedral".to_string(),
+            EffectOptions::from_json(r#"{"delay": 0.5}"#),
+        );
+
+        let (mut ctx, _) = mock_context("registered-presets");
+        ctx.effect_presets
+            .entry("echo".to_string())
+            .or_default()
+            .insert(
+                "cathedral".to_string(),
+                EffectOptions::from_json(r#"{"delay": 0.1}"#),
+            );
+        ctx.registered_presets = registry.snapshot();
+
+        let warm = ctx.get_preset("distortion", "warm").unwrap();
+        assert_eq!((warm.drive, warm.mix), (Some(9.0), Some(1.0)));
+        assert_eq!(
+            ctx.get_preset("echo", "cathedral").unwrap().delay,
+            Some(0.5)
+        );
+        // Other built-ins are untouched
+        assert_eq!(
+            ctx.get_preset("distortion", "crunch").unwrap().drive,
+            Some(6.0)
+        );
+        assert!(builtin_preset_names("distortion").contains(&"warm".to_string()));
+    }
+
+    #[test]
+    fn test_effect_chain_applies_in_order() {
+        let (mut ctx, _) = mock_context("effect-chain");
+        let echo = r#"{"delay": 0.05, "decay": 0.8}"#;
+        let drive = r#"{"drive": 8}"#;
+        let chain = |order: &str| {
+            format!(
+                r#"<effect chain="{}" options='{{"echo": {}, "distortion": {}}}'>Words.</effect>"#,
+                order, echo, drive
+            )
+        };
+
+        let chained = render_with_context(&mut ctx, &chain("echo, distortion")).unwrap();
+        let nested = render_with_context(
+            &mut ctx,
+            &format!(
+                concat!(
+                    r#"<effect value="distortion" options='{}'>"#,
+                    r#"<effect value="echo" options='{}'>Words.</effect></effect>"#
+                ),
+                drive, echo
+            ),
+        )
+        .unwrap();
+        let reversed = render_with_context(&mut ctx, &chain("distortion,echo")).unwrap();
+
+        assert!(chained.approx_eq(&nested, 1e-6));
+        assert!(!chained.approx_eq(&reversed, 1e-3));
+
+        let diagnostics = check_script(r#"<effect chain="echo,reverb">Hi</effect>"#);
+        assert!(diagnostics[0].message.contains("Unknown effect \"reverb\""));
+        ctx.strict = true;
+        assert!(render_with_context(&mut ctx, r#"<effect chain="reverb">Hi</effect>"#).is_err());
+    }
+
+    #[test]
+    fn test_sentence_pauses_lengthen_output() {
+        let (mut ctx, _) = mock_context("sentence-pauses");
+        let plain = render_with_context(&mut ctx, "One. Two.").unwrap();
+        let paused =
+            render_with_context(&mut ctx, r#"<pauses sentence="0.2"></pauses>One. Two."#).unwrap();
+
+        let pause_len = AudioBuffer::silence(0.2, 24000).length();
+        assert_eq!(paused.length(), plain.length() + pause_len);
+    }
+
+    #[test]
+    fn test_ssml_structure_adds_pauses() {
+        let (mut ctx, calls) = mock_context("ssml-structure");
+        ctx.trim_min_silence_ms = 0.0;
+        let one = render_with_context(&mut ctx, "One.").unwrap().length();
+        let two = render_with_context(&mut ctx, "Two.").unwrap().length();
+        calls.lock().unwrap().clear();
+
+        let script = concat!(
+            r#"<pauses sentence="0.2" paragraph="0.5"></pauses>"#,
+            "<p> <s>One.</s> <s>Two.</s> </p>"
+        );
+        let audio = render_with_context(&mut ctx, script).unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        let sentence = AudioBuffer::silence(0.2, 24000).length();
+        let paragraph = AudioBuffer::silence(0.5, 24000).length();
+        assert_eq!(audio.length(), one + sentence + two + paragraph);
+        let data = audio.get_channel_data(0);
+        assert!(data[one..one + sentence].iter().all(|&s| s == 0.0));
+        assert!(data[one + sentence] != 0.0);
+        assert!(data[audio.length() - paragraph..].iter().all(|&s| s == 0.0));
+
+        // A lone sentence still gets its pause
+        let script = r#"<pauses sentence="0.2"></pauses><s>One.</s>"#;
+        let lone = render_with_context(&mut ctx, script).unwrap();
+        assert_eq!(lone.length(), one + sentence);
+    }
+
+    #[test]
+    fn test_disabling_trim_keeps_raw_length() {
+        let (mut ctx, _) = mock_context("trim");
+        let trimmed = render_with_context(&mut ctx, "Hello.").unwrap();
+        let untrimmed = render_with_context(&mut ctx, r#"<tts trim="false">Hello.</tts>"#).unwrap();
+
+        assert!(untrimmed.length() >= mock_len(". Hello."));
+        assert!(trimmed.length() < untrimmed.length());
+        assert!(ctx.trim_enabled);
+    }
+
+    #[test]
+    fn test_voice_crossfade_blends_boundary() {
+        let script = r#"Hello.<voice value="male">World.</voice>"#;
+        let (mut ctx, _) = mock_context("voice-crossfade");
+        ctx.trim_min_silence_ms = 0.0;
+        let hard = render_with_context(&mut ctx, script).unwrap();
+
+        ctx.voice_crossfade_ms = 20.0;
+        let faded = render_with_context(&mut ctx, script).unwrap();
+
+        let fade = ((20.0f32 / 1000.0) * 24000.0) as usize;
+        assert_eq!(faded.length(), hard.length() - fade);
+
+        // The hard cut jumps straight from the female level to the male level,
+        // while the crossfade passes through a blend of both
+        let female = hard.samples[0][0];
+        let male = hard.samples[0][hard.length() - 1];
+        let first_len = mock_len(". Hello.") - MOCK_PADDING * 2;
+        let mid = faded.samples[0][first_len - fade / 2];
+        assert!((mid - female).abs() > 0.01);
+        assert!((mid - male).abs() > 0.01);
+    }
+
+    #[test]
+    fn test_overlay_part_offset() {
+        let (mut ctx, _) = mock_context("overlay-offset");
+        ctx.trim_min_silence_ms = 0.0;
+        let merged = render_with_context(
+            &mut ctx,
+            r#"<overlay><part>Hi.</part><part offset="0.5">Yo.</part></overlay>"#,
+        )
+        .unwrap();
+
+        let start = AudioBuffer::silence(0.5, 24000).length();
+        assert_eq!(
+            merged.length(),
+            start + mock_len(". Yo.") - MOCK_PADDING * 2
+        );
+        assert_eq!(merged.samples[0][start - 1], 0.0);
+        assert!(merged.samples[0][start] > 0.0);
+    }
+
+    #[test]
+    fn test_parallel_overlay_matches_sequential() {
+        let script = concat!(
+            r#"<marker name="start"/>Intro.<overlay>"#,
+            r#"<part><voice value="male">First layer here.</voice></part>"#,
+            r#"<part offset="0.2"><random><item>One.</item><item>Two words.</item>"#,
+            r#"<item>Three words now.</item></random><marker name="inner"/></part>"#,
+            r#"<part gain="0.5"><speed value="1.5">Third.</speed></part>"#,
+            r#"</overlay>Outro."#
+        );
+        let render = |parallel: bool| {
+            let (mut ctx, _) = mock_context("overlay-parallel");
+            ctx.set_seed(7);
+            ctx.parallel_overlay = parallel;
+            let audio = render_with_context(&mut ctx, script).unwrap();
+            let done = ctx.current_node.load(Ordering::SeqCst);
+            (audio, ctx.markers, done, ctx.total_nodes)
+        };
+
+        let (parallel, parallel_markers, parallel_done, total) = render(true);
+        let (sequential, sequential_markers, sequential_done, _) = render(false);
+
+        assert_eq!(parallel.samples, sequential.samples);
+        assert_eq!(parallel_markers, sequential_markers);
+        assert_eq!(parallel_markers.len(), 2);
+        assert_eq!(parallel_done, sequential_done);
+        assert_eq!(parallel_done, total - 1);
+    }
+
+    #[test]
+    fn test_overlay_stems_are_aligned() {
+        let (mut ctx, _) = mock_context("overlay-stems");
+        ctx.capture_stems = true;
+        let script = concat!(
+            r#"Intro.<overlay><part>Hi.</part>"#,
+            r#"<part offset="0.2" gain="0.5">Yo there.</part></overlay>"#
+        );
+        let master = render_with_context(&mut ctx, script).unwrap();
+
+        assert_eq!(ctx.stems.len(), 2);
+        let stems = align_stems(&ctx.stems, master.length());
+        assert!(stems.iter().all(|stem| stem.length() == master.length()));
+
+        // Silent before the overlay, and mixing the stems gives back the master
+        let overlay_start = ctx.stems[0].position;
+        assert!(overlay_start > 0);
+        assert!(stems[0].samples[0][..overlay_start]
+            .iter()
+            .all(|&s| s == 0.0));
+        for i in (overlay_start..master.length()).step_by(97) {
+            let mixed = stems[0].samples[0][i] + stems[1].samples[0][i];
+            assert!((mixed - master.samples[0][i]).abs() < 1e-6, "sample {}", i);
+        }
+
+        let dir = std::env::temp_dir().join("domgpt-stems");
+        fs::create_dir_all(&dir).unwrap();
+        let written = write_stems(&stems, &dir, "mix (1).wav").unwrap();
+        assert_eq!(written, vec!["mix (1).stem1.wav", "mix (1).stem2.wav"]);
+        assert!(written.iter().all(|name| dir.join(name).exists()));
+
+        // Stems are only kept when asked for
+        ctx.capture_stems = false;
+        render_with_context(&mut ctx, "<overlay><part>Hi.</part></overlay>").unwrap();
+        assert!(ctx.stems.is_empty());
+    }
+
+    #[test]
+    fn test_streaming_emits_one_chunk_per_segment() {
+        let (mut ctx, _) = mock_context("stream-chunks");
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let sink = chunks.clone();
+        ctx.chunk_sink = Some(Box::new(move |chunk: AudioChunk| {
+            sink.lock()
+                .unwrap()
+                .push((chunk.index, chunk.is_final, chunk.audio.length()));
+        }));
+        let script = concat!(
+            r#"One.<pause value="0.2"></pause>Two."#,
+            r#"<overlay><part>A.</part><part>Be.</part></overlay>"#
+        );
+        let audio = render_with_context(&mut ctx, script).unwrap();
+
+        let chunks = chunks.lock().unwrap();
+        let indices: Vec<usize> = chunks.iter().map(|(index, _, _)| *index).collect();
+        let finals: Vec<bool> = chunks.iter().map(|(_, is_final, _)| *is_final).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        assert_eq!(finals, vec![false, false, false, true]);
+        assert_eq!(chunks[1].2, 4800);
+        let total: usize = chunks.iter().map(|(_, _, length)| length).sum();
+        assert_eq!(total, audio.length());
+    }
+
+    #[test]
+    fn test_loop_gap() {
+        let (mut ctx, _) = mock_context("loop-gap");
+        ctx.trim_min_silence_ms = 0.0;
+        let looped =
+            render_with_context(&mut ctx, r#"<loop value="2" gap="0.5">Hi.</loop>"#).unwrap();
+
+        let iteration = mock_len(". Hi.") - MOCK_PADDING * 2;
+        let gap = AudioBuffer::silence(0.5, 24000).length();
+        assert_eq!(looped.length(), 2 * iteration + gap);
+    }
+
+    #[test]
+    fn test_loop_crossfade_overlaps_iterations() {
+        let (mut ctx, _) = mock_context("loop-crossfade");
+        ctx.trim_min_silence_ms = 0.0;
+        let text = "Round and round the loop goes, where it stops nobody knows.";
+        let ramp = format!(r#"<automation from="0" to="1">{}</automation>"#, text);
+        let render = |ctx: &mut ScriptToAudioContext, attrs: &str| {
+            let script = format!(r#"<loop value="3"{}>{}</loop>"#, attrs, ramp);
+            render_with_context(ctx, &script).unwrap()
+        };
+        let max_delta = |buffer: &AudioBuffer| {
+            let data = buffer.get_channel_data(0);
+            data.windows(2)
+                .fold(0.0f32, |m, w| m.max((w[1] - w[0]).abs()))
+        };
+
+        let iteration = mock_len(&format!(". {}", text)) - MOCK_PADDING * 2;
+        let fade = (0.05f32 * 24000.0) as usize;
+        let crossfaded = render(&mut ctx, r#" crossfade="0.05""#);
+        assert_eq!(crossfaded.length(), 3 * iteration - 2 * fade);
+
+        // Each ramp ends loud and starts silent, so a hard cut jumps
+        let hard = render(&mut ctx, "");
+        assert!(max_delta(&hard) > 0.3);
+        assert!(max_delta(&crossfaded) < 0.05);
+
+        let both = render_with_context(
+            &mut ctx,
+            r#"<loop value="2" gap="0.5" crossfade="0.1">Hi.</loop>"#,
+        );
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn test_repeat_crossfade_matches_pairwise_crossfade() {
+        let tone: Vec<f32> = (0..2400).map(|i| (i as f32 * 0.03).sin() * 0.8).collect();
+        let single = AudioBuffer::from_mono(tone, SAMPLE_RATE);
+        let fade = 0.02;
+
+        // Crossfading one pair at a time, as each seam is defined
+        let mut pairwise = single.clone();
+        for _ in 1..4 {
+            pairwise = AudioBuffer::concat_crossfade(&[pairwise, single.clone()], fade).unwrap();
+        }
+        let repeated = single.repeat_crossfade(4, fade);
+        let overlap = (fade * SAMPLE_RATE as f32) as usize;
+        assert_eq!(repeated.length(), 4 * single.length() - 3 * overlap);
+        assert_eq!(repeated.samples, pairwise.samples);
+
+        let looped = {
+            let (mut ctx, _) = mock_context("loop-duration-crossfade");
+            ctx.trim_min_silence_ms = 0.0;
+            render_with_context(
+                &mut ctx,
+                r#"<loop duration="2.5" crossfade="0.05">Hi.</loop>"#,
+            )
+            .unwrap()
+        };
+        assert_eq!(looped.length(), (2.5f32 * 24000.0) as usize);
+    }
+
+    #[test]
+    fn test_loop_duration_truncates() {
+        let (mut ctx, _) = mock_context("loop-duration");
+        ctx.trim_min_silence_ms = 0.0;
+        let looped = render_with_context(&mut ctx, r#"<loop duration="0.51">Hi.</loop>"#).unwrap();
+        assert_eq!(looped.length(), (0.51f32 * 24000.0) as usize);
+
+        let both = render_with_context(&mut ctx, r#"<loop value="2" duration="1">Hi.</loop>"#);
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn test_sequence_shuffle_is_seeded() {
+        let (mut ctx, calls) = mock_context("sequence");
+        let words = [
+            "one", "two", "three", "four", "five", "six", "seven", "eight",
+        ];
+        let items: String = words
+            .iter()
+            .map(|word| format!(r#"<speed value="1">{}</speed>"#, word))
+            .collect();
+        let mut render = |script: String| {
+            ctx.set_seed(42);
+            calls.lock().unwrap().clear();
+            render_with_context(&mut ctx, &script).unwrap();
+            let texts: Vec<String> = calls
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(t, _)| t.clone())
+                .collect();
+            texts
+        };
+
+        let in_order = render(format!("<sequence>{}</sequence>", items));
+        let document: Vec<String> = words.iter().map(|word| format!(". {}", word)).collect();
+        assert_eq!(in_order, document);
+
+        let shuffled = render(format!(r#"<sequence shuffle="true">{}</sequence>"#, items));
+        assert_eq!(
+            shuffled,
+            render(format!(r#"<sequence shuffle="true">{}</sequence>"#, items))
+        );
+        assert_ne!(shuffled, document);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        let mut expected = document.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn test_random_is_seeded() {
+        let script = "<random><item>Alpha.</item><item>Beta.</item><item>Gamma.</item>\
+                      <item>Delta.</item></random>";
+        let (mut ctx, calls) = mock_context("random");
+
+        let mut picks = Vec::new();
+        for _ in 0..2 {
+            ctx.set_seed(42);
+            render_with_context(&mut ctx, script).unwrap();
+            picks.push(calls.lock().unwrap().last().unwrap().0.clone());
+        }
+        assert_eq!(picks[0], picks[1]);
+
+        let before = calls.lock().unwrap().len();
+        let empty = render_with_context(&mut ctx, "<random></random>").unwrap();
+        assert_eq!(calls.lock().unwrap().len(), before);
+        assert_eq!(empty.peak(), 0.0);
+    }
+
+    #[test]
+    fn test_progress_is_throttled() {
+        let (mut ctx, _) = mock_context("progress-throttle");
+        ctx.total_nodes = 100_000;
+
+        let calls = 1000;
+        for i in 0..calls {
+            ctx.current_node.store(i, Ordering::SeqCst);
+            ctx.emit_progress("Processing script", "generate");
+        }
+        assert!(ctx.emitted_progress_events < calls / 10);
+
+        // Completion always gets through
+        let before = ctx.emitted_progress_events;
+        ctx.current_node.store(ctx.total_nodes, Ordering::SeqCst);
+        ctx.emit_progress("Processing script", "generate");
+        assert_eq!(ctx.emitted_progress_events, before + 1);
+    }
+
+    #[test]
+    fn test_progress_skips_download_band_when_files_present() {
+        let (mut ctx, _) = mock_context("progress-weight");
+        ctx.total_nodes = 100;
+        ctx.current_node.store(1, Ordering::SeqCst);
+        ctx.emit_progress("Processing script", "generate");
+        assert!(ctx.last_emitted_progress < 0.05);
+
+        let (mut ctx, _) = mock_context("progress-weight");
+        ctx.files_downloaded = true;
+        ctx.total_nodes = 100;
+        ctx.current_node.store(1, Ordering::SeqCst);
+        ctx.emit_progress("Processing script", "generate");
+        assert!(ctx.last_emitted_progress >= DEFAULT_DOWNLOAD_WEIGHT);
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_unknown_tag() {
+        let (mut ctx, _) = mock_context("strict-tag");
+        ctx.strict = true;
+        let err = render_with_context(&mut ctx, r#"<vocie value="male">Hi</vocie>"#).unwrap_err();
+        assert!(err.to_string().contains("vocie"));
+
+        let err = render_with_context(&mut ctx, r#"<pause valeu="1"></pause>"#).unwrap_err();
+        assert!(err.to_string().contains("valeu"));
+
+        // Non-strict mode still renders, ignoring the typo
+        ctx.strict = false;
+        assert!(render_with_context(&mut ctx, r#"<vocie value="male">Hi</vocie>"#).is_ok());
+    }
+
+    #[test]
+    fn test_deadline_times_out_render() {
+        let (mut ctx, calls) = mock_context("timeout");
+        ctx.deadline = Some(Instant::now() + Duration::from_millis(1));
+        std::thread::sleep(Duration::from_millis(5));
+
+        let err = render_with_context(&mut ctx, "<loop value=\"1000\">Hi.</loop>").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("timed out"), "{}", message);
+        assert!(message.contains("nodes"), "{}", message);
+        assert!(calls.lock().unwrap().is_empty());
+
+        ctx.deadline = None;
+        assert!(render_with_context(&mut ctx, "Hi.").is_ok());
+    }
+
+    #[test]
+    fn test_loop_over_output_cap_fails_early() {
+        let (mut ctx, calls) = mock_context("loop-cap");
+        let err = render_with_context(&mut ctx, r#"<loop value="100000000">Hi.</loop>"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(r#"<loop value="100000000">"#), "{}", err);
+        assert!(err.contains("7200s limit"), "{}", err);
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        ctx.max_output_secs = 1.0;
+        let err = render_with_context(&mut ctx, r#"<loop duration="5">Hi.</loop>"#).unwrap_err();
+        assert!(err.to_string().contains("duration=\"5\""));
+        assert!(render_with_context(&mut ctx, r#"<loop duration="0.5">Hi.</loop>"#).is_ok());
+
+        ctx.max_output_secs = DEFAULT_MAX_OUTPUT_SECS;
+        let err = render_with_context(&mut ctx, r#"<sound value="pop" repeat="100000000"/>"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(r#"<sound value="pop" repeat="100000000">"#),
+            "{}",
+            err
+        );
+        assert!(render_with_context(&mut ctx, r#"<sound value="pop" repeat="2"/>"#).is_ok());
+    }
+
+    #[test]
+    fn test_check_script_bad_pause_value() {
+        let diagnostics = check_script("Hello\n  <pause value=\"abc\"></pause>");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0].message.contains("abc"));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 3));
+    }
+
+    #[test]
+    fn test_check_script_unknown_effect() {
+        let diagnostics = check_script(r#"<effect value="reverbb">Hi</effect>"#);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("reverbb"));
+
+        let valid = r#"<voice value="male"><effect value="echo">Hi</effect></voice>
+<pause value="1"> <sound value="pop">"#;
+        assert!(check_script(valid).is_empty());
+        assert!(!check_script("<voice value=\"male\">Hi").is_empty());
+    }
+
+    #[test]
+    fn test_check_script_accepts_scanned_voice() {
+        let dir = test_voice_dir("check-scanned-voice");
+        fs::copy(dir.join("M1.json"), dir.join("Narrator.json")).unwrap();
+        let assets = AssetScan::read(&dir, &dir.join("sounds"));
+
+        let script = r#"<voice value="narrator">Hi</voice>"#;
+        assert!(check_script_with_assets(script, &assets).is_empty());
+        let diagnostics = check_script(script);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("narrator"));
+    }
+
+    #[test]
+    fn test_sanitize_filename_strips_separators() {
+        assert_eq!(sanitize_filename("Intro/Part: 1"), "Intro_Part_ 1");
+        assert_eq!(sanitize_filename("a\\b\tc"), "a_b_c");
+        assert_eq!(sanitize_filename("  "), "audio");
+    }
+
+    #[test]
+    fn test_sanitize_filename_neutralizes_parent_dirs() {
+        for name in ["../../etc/passwd", "..", "..\\secret.wav", "a/../../b.wav"] {
+            let sanitized = sanitize_filename(name);
+            assert!(!sanitized.contains(".."), "{}", sanitized);
+            let components: Vec<_> = Path::new(&sanitized).components().collect();
+            assert_eq!(components.len(), 1, "{}", sanitized);
+            assert!(matches!(components[0], std::path::Component::Normal(_)));
+        }
+    }
+
+    #[test]
+    fn test_write_audio_file_unique_names() {
+        let dir = std::env::temp_dir().join("domgpt-unique-filenames");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let audio = RenderedAudio {
+            audio: AudioBuffer::silence(0.01, 44100),
+            markers: Vec::new(),
+            stems: Vec::new(),
+            profile: RenderProfile::default(),
+            seed: 0,
+        };
+
+        let (first, _) = write_audio_file(&audio, None, &dir, "Title.wav", false).unwrap();
+        let (second, _) = write_audio_file(&audio, None, &dir, "Title.wav", false).unwrap();
+        assert_eq!(first, "Title.wav");
+        assert_eq!(second, "Title (1).wav");
+        assert!(dir.join(&first).exists() && dir.join(&second).exists());
+
+        let (overwritten, _) = write_audio_file(&audio, None, &dir, "Title.wav", true).unwrap();
+        assert_eq!(overwritten, "Title.wav");
+    }
+
+    #[test]
+    fn test_silence_tag_is_exact() {
+        let (mut ctx, calls) = mock_context("silence");
+        let audio = render_with_context(&mut ctx, r#"<silence value="2"/>"#).unwrap();
+        assert_eq!(audio.length(), 2 * ctx.sample_rate as usize);
+        assert_eq!(audio.peak(), 0.0);
+
+        let script = r#"<silence value="2">Not spoken.</silence>"#;
+        let nested = render_with_context(&mut ctx, script).unwrap();
+        assert_eq!(nested.length(), 2 * ctx.sample_rate as usize);
+        assert!(calls.lock().unwrap().is_empty());
+
+        // Text after a self-closing <silence/> is not swallowed
+        let followed = render_with_context(&mut ctx, r#"<silence value="2" />Hi."#).unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+        assert!(followed.length() > 2 * ctx.sample_rate as usize);
+    }
+
+    #[test]
+    fn test_markers_written_as_cue_points() {
+        let (mut ctx, _) = mock_context("markers");
+        ctx.trim_min_silence_ms = 0.0;
+        let script = r#"<marker name="start"/>Hi.<pause value="0.5"/><marker name="end"/>"#;
+        let audio = render_with_context(&mut ctx, script).unwrap();
+
+        let positions: Vec<usize> = ctx.markers.iter().map(|m| m.position).collect();
+        let pause = AudioBuffer::silence(0.5, ctx.sample_rate).length();
+        assert_eq!(
+            positions,
+            vec![0, mock_len(". Hi.") - MOCK_PADDING * 2 + pause]
+        );
+
+        let dir = std::env::temp_dir().join("domgpt-markers");
+        fs::create_dir_all(&dir).unwrap();
+        let rendered = RenderedAudio {
+            audio,
+            markers: ctx.markers.clone(),
+            stems: Vec::new(),
+            profile: RenderProfile::default(),
+            seed: 0,
+        };
+        let (filename, _) = write_audio_file(&rendered, None, &dir, "markers.wav", true).unwrap();
+
+        let bytes = fs::read(dir.join(filename)).unwrap();
+        let cue = bytes.windows(4).position(|w| w == b"cue ").unwrap();
+        let count = u32::from_le_bytes(bytes[cue + 8..cue + 12].try_into().unwrap());
+        assert_eq!(count, 2);
+        let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size, bytes.len() - 8);
+        // Existing readers still accept the file
+        assert!(AudioBuffer::from_file(dir.join("markers.wav")).is_ok());
+    }
+
+    #[test]
+    fn test_clipping_is_counted_on_write() {
+        let dir = std::env::temp_dir().join("domgpt-clipping");
+        fs::create_dir_all(&dir).unwrap();
+
+        let clean = AudioBuffer::from_mono(vec![0.5; 1000], 24000);
+        let clean_clips = clean.write_to_file(dir.join("clean.wav")).unwrap();
+        assert_eq!(clean_clips, 0);
+        assert!(clip_warning(clean_clips, &clean).is_none());
+
+        let mut hot = clean.clone();
+        for sample in hot.samples[0].iter_mut().step_by(10) {
+            *sample = 1.7;
+        }
+        let hot_clips = hot.write_to_file(dir.join("hot.wav")).unwrap();
+        assert_eq!(hot_clips, 100);
+        assert!(clip_warning(hot_clips, &hot).unwrap().contains("10.00%"));
+    }
+
+    #[test]
+    fn test_wav_info_metadata() {
+        let dir = std::env::temp_dir().join("domgpt-wav-info");
+        fs::create_dir_all(&dir).unwrap();
+        let rendered = RenderedAudio {
+            audio: AudioBuffer::silence(0.01, 44100),
+            markers: Vec::new(),
+            stems: Vec::new(),
+            profile: RenderProfile::default(),
+            seed: 0,
+        };
+        let info = WavInfo {
+            title: Some("Evening Session".to_string()),
+            artist: Some("Narrator".to_string()),
+        };
+        write_audio_file(&rendered, Some(&info), &dir, "info.wav", true).unwrap();
+
+        let bytes = fs::read(dir.join("info.wav")).unwrap();
+        let inam = bytes.windows(4).position(|w| w == b"INAM").unwrap();
+        let size = u32::from_le_bytes(bytes[inam + 4..inam + 8].try_into().unwrap()) as usize;
+        let value = &bytes[inam + 8..inam + 8 + size];
+        assert_eq!(value, b"Evening Session\0");
+        assert!(bytes.windows(6).any(|w| w == b"domgpt"));
+        assert!(AudioBuffer::from_file(dir.join("info.wav")).is_ok());
+    }
+
+    #[test]
+    fn test_sound_attributes() {
+        let (mut ctx, _) = mock_context("sound-attributes");
+        let plain = render_with_context(&mut ctx, r#"<sound value="pop"/>"#).unwrap();
+        let quiet = render_with_context(&mut ctx, r#"<sound value="pop" volume="0.5"/>"#).unwrap();
+        assert!((quiet.peak() - plain.peak() * 0.5).abs() < 1e-6);
+
+        let repeated = render_with_context(&mut ctx, r#"<sound value="pop" repeat="3"/>"#).unwrap();
+        assert_eq!(repeated.length(), plain.length() * 3);
+
+        let panned = render_with_context(&mut ctx, r#"<sound value="pop" pan="-1"/>"#).unwrap();
+        assert_eq!(panned.num_channels(), 2);
+        assert!(panned.get_channel_data(1).iter().all(|s| s.abs() < 1e-6));
+
+        // Unparseable attributes fall back to the defaults
+        let invalid =
+            render_with_context(&mut ctx, r#"<sound value="pop" volume="loud" repeat="x"/>"#)
+                .unwrap();
+        assert_eq!(invalid.samples, plain.samples);
+    }
+
+    #[test]
+    fn test_sound_src_is_downloaded_and_cached() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let dir = std::env::temp_dir().join("domgpt-remote-sounds");
+        let _ = fs::remove_dir_all(&dir);
+
+        // Serve the embedded pop sound exactly once
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/pop.wav", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let body = get_embedded_sound("pop").unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+
+        let script = format!(r#"<sound src="{}"/>"#, url);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let failures = runtime.block_on(fetch_remote_sounds(&script, &dir, None, "test"));
+        assert!(failures.is_empty(), "{:?}", failures);
+        server.join().unwrap();
+        assert!(remote_sound_path(&dir, &url).exists());
+
+        // The server is gone, so this only succeeds from the cache
+        let failures = runtime.block_on(fetch_remote_sounds(&script, &dir, None, "test"));
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        let (mut ctx, _) = mock_context("remote-sounds");
+        ctx.sound_effects_dir = dir.clone();
+        let remote = render_with_context(&mut ctx, &script).unwrap();
+        let embedded = render_with_context(&mut ctx, r#"<sound value="pop"/>"#).unwrap();
+        assert_eq!(remote.samples, embedded.samples);
+
+        // A local file works as a src too; a missing one warns, or fails
+        // in strict mode
+        let local = dir.join("local.wav");
+        fs::write(&local, get_embedded_sound("pop").unwrap()).unwrap();
+        let script = format!(r#"<sound src="{}"/>"#, local.to_string_lossy());
+        let from_file = render_with_context(&mut ctx, &script).unwrap();
+        assert_eq!(from_file.samples, embedded.samples);
+
+        let collector = Arc::new(ProgressCollector::default());
+        ctx.progress = Some(collector.clone());
+        let missing = r#"<sound src="/nonexistent/local.wav"/>Hi."#;
+        render_with_context(&mut ctx, missing).unwrap();
+        assert!(collector
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|e| e.stage == "warning" && e.message.contains("/nonexistent/local.wav")));
+        ctx.strict = true;
+        assert!(render_with_context(&mut ctx, missing).is_err());
+    }
 
-                // Merge with parsed options
-                let parsed_options = EffectOptions::from_json(&options_attr);
-                options = options.merge(&parsed_options);
+    #[test]
+    fn test_verify_models_flags_and_repairs_empty_file() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let dir = std::env::temp_dir().join("domgpt-verify-models");
+        let _ = fs::remove_dir_all(&dir);
+        let (onnx_dir, voice_dir) = (dir.join("onnx"), dir.join("voice_styles"));
+        fs::create_dir_all(&onnx_dir).unwrap();
+        fs::create_dir_all(&voice_dir).unwrap();
+        for file in MODEL_FILES {
+            fs::write(onnx_dir.join(file), "{}").unwrap();
+        }
+        for file in VOICE_FILES {
+            fs::write(voice_dir.join(file), "{}").unwrap();
+        }
+        // A truncated download
+        fs::write(onnx_dir.join("vocoder.onnx"), "").unwrap();
+
+        // Serve the replacement exactly once
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let read = stream.read(&mut request).unwrap();
+            let body = b"onnx";
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let verify = |repair| {
+            runtime.block_on(verify_model_files(
+                &onnx_dir, &voice_dir, &base_url, repair, None, "test",
+            ))
+        };
+        let state_of = |statuses: &[ModelFileStatus], file: &str| {
+            statuses.iter().find(|s| s.file == file).unwrap().state
+        };
 
-                let mut child_segments: Vec<AudioBuffer> = Vec::new();
-                for child in node.children() {
-                    child_segments.extend(process_node(ctx, &child)?);
-                }
+        let checked = verify(false);
+        assert_eq!(checked.len(), MODEL_FILES.len() + VOICE_FILES.len());
+        assert_eq!(
+            state_of(&checked, "onnx/vocoder.onnx"),
+            ModelFileState::Empty
+        );
+        assert_eq!(
+            state_of(&checked, "voice_styles/F1.json"),
+            ModelFileState::Ok
+        );
+        assert_eq!(
+            fs::metadata(onnx_dir.join("vocoder.onnx")).unwrap().len(),
+            0
+        );
 
-                if !child_segments.is_empty() {
-                    let target = AudioBuffer::concat(&child_segments)?;
-                    let effected = ctx.apply_effect(&effect_name, &target, &options);
-                    segments.push(effected);
-                }
-            }
+        let repaired = verify(true);
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /onnx/vocoder.onnx "),
+            "{}",
+            request
+        );
+        assert_eq!(
+            state_of(&repaired, "onnx/vocoder.onnx"),
+            ModelFileState::Repaired
+        );
+        assert_eq!(fs::read(onnx_dir.join("vocoder.onnx")).unwrap(), b"onnx");
+
+        // Truncated JSON is caught too
+        fs::write(voice_dir.join("M1.json"), "{\"style").unwrap();
+        let checked = verify(false);
+        assert_eq!(
+            state_of(&checked, "voice_styles/M1.json"),
+            ModelFileState::Corrupt
+        );
+        assert_eq!(state_of(&checked, "onnx/vocoder.onnx"), ModelFileState::Ok);
+    }
 
-            "loop" => {
-                let loops: usize = get_attr(node, "value")
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(1);
+    #[test]
+    fn test_audio_tag_inserts_clip_or_speaks_fallback() {
+        let dir = std::env::temp_dir().join("domgpt-audio-tag");
+        fs::create_dir_all(&dir).unwrap();
+        let clip_path = dir.join("clip.wav");
+        fs::write(&clip_path, get_embedded_sound("pop").unwrap()).unwrap();
+
+        let (mut ctx, calls) = mock_context("audio-tag");
+        let pop = ctx.fetch_sound_effect("pop").unwrap();
+
+        let script = format!(
+            r#"<audio src="{}">Pop.</audio>"#,
+            clip_path.to_string_lossy()
+        );
+        let from_file = render_with_context(&mut ctx, &script).unwrap();
+        assert_eq!(from_file.samples, pop.samples);
+        let by_key = render_with_context(&mut ctx, r#"<audio src="pop"/>"#).unwrap();
+        assert_eq!(by_key.samples, pop.samples);
+        assert!(calls.lock().unwrap().is_empty());
+
+        let collector = Arc::new(ProgressCollector::default());
+        ctx.progress = Some(collector.clone());
+        let broken = r#"<audio src="/nonexistent/clip.wav">Pop.</audio>"#;
+        render_with_context(&mut ctx, broken).unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].0.contains("Pop."));
+        let events = collector.events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|e| e.stage == "warning" && e.message.contains("/nonexistent/clip.wav")));
 
-                let mut child_segments: Vec<AudioBuffer> = Vec::new();
-                for child in node.children() {
-                    child_segments.extend(process_node(ctx, &child)?);
-                }
+        let urls = sound_urls(r#"<audio src="https://example.com/a.wav"/><audio src="pop"/>"#);
+        assert_eq!(urls, vec!["https://example.com/a.wav"]);
+    }
 
-                if !child_segments.is_empty() {
-                    let single_iteration = AudioBuffer::concat(&child_segments)?;
-                    for _ in 0..loops {
-                        segments.push(single_iteration.clone());
-                    }
-                }
-            }
+    #[test]
+    fn test_registered_sound_resolves() {
+        let registry = SoundRegistry::default();
+        registry.register("mykey".to_string(), SoundSource::Bytes(vec![0; 4]));
+        let pop = get_embedded_sound("pop").unwrap().to_vec();
+        registry.register("mykey".to_string(), SoundSource::Bytes(pop));
+
+        let (mut ctx, _) = mock_context("registered-sound");
+        ctx.custom_sounds = registry.snapshot();
+        assert_eq!(ctx.custom_sounds.len(), 1);
+
+        let registered = ctx.fetch_sound_effect("mykey").unwrap();
+        let builtin = ctx.fetch_sound_effect("pop").unwrap();
+        assert_eq!(registered.samples, builtin.samples);
+        assert!(ctx.fetch_sound_effect("missing").is_err());
+    }
 
-            "volume" => {
-                let volume: f32 = get_attr(node, "value")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(1.0)
-                    .max(0.0);
+    #[test]
+    fn test_voice_styles_are_cached_until_the_file_changes() {
+        let (mut ctx, _) = mock_context("style-cache");
+        let first = ctx.get_voice_style("female").unwrap();
+        let second = ctx.get_voice_style("female").unwrap();
+        assert_eq!(first.ttl, second.ttl);
+        assert_eq!(ctx.style_loads, 1);
+
+        // Rendering more speech in the same voice reuses the cached style
+        render_with_context(&mut ctx, "One. <pause/> Two.").unwrap();
+        assert_eq!(ctx.style_loads, 1);
+
+        // Editing the file (newer mtime) forces a reload
+        let path = ctx.voice_dir.join(get_voices()["female"]);
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        drop(file);
+        ctx.get_voice_style("female").unwrap();
+        assert_eq!(ctx.style_loads, 2);
+    }
 
-                let mut child_segments: Vec<AudioBuffer> = Vec::new();
-                for child in node.children() {
-                    child_segments.extend(process_node(ctx, &child)?);
-                }
+    #[test]
+    fn test_sound_effects_are_cached() {
+        let (mut ctx, _) = mock_context("sound-cache");
+        let first = ctx.fetch_sound_effect("pop").unwrap();
+        let second = ctx.fetch_sound_effect("pop").unwrap();
+        assert_eq!(first.samples, second.samples);
+        assert_eq!(ctx.sound_decodes, 1);
+
+        // A different output rate needs a fresh decode
+        ctx.sample_rate = 44100;
+        let resampled = ctx.fetch_sound_effect("pop").unwrap();
+        assert_eq!(resampled.sample_rate, 44100);
+        assert_eq!(ctx.sound_decodes, 2);
+    }
 
-                if !child_segments.is_empty() {
-                    let target = AudioBuffer::concat(&child_segments)?;
-                    let scaled = apply_volume(&target, volume);
-                    segments.push(scaled);
-                }
-            }
+    #[test]
+    fn test_emphasis_is_louder_and_slower() {
+        let (mut ctx, calls) = mock_context("emphasis");
+        let plain = render_with_context(&mut ctx, "Really.").unwrap();
+        let plain_speed = calls.lock().unwrap().last().unwrap().1.speed;
+
+        let strong =
+            render_with_context(&mut ctx, r#"<emphasis level="strong">Really.</emphasis>"#)
+                .unwrap();
+        let strong_speed = calls.lock().unwrap().last().unwrap().1.speed;
+
+        assert!(strong.peak() > plain.peak());
+        assert!(strong_speed < plain_speed);
+        assert_eq!(ctx.current_speed, 1.0);
+    }
 
-            // For root, html, head, body, or unknown elements - just process children
-            _ => {
-                for child in node.children() {
-                    segments.extend(process_node(ctx, &child)?);
-                }
-            }
-        }
-    } else {
-        // For other node types, process children
-        for child in node.children() {
-            segments.extend(process_node(ctx, &child)?);
-        }
+    #[test]
+    fn test_speakers_use_mapped_voices() {
+        let (mut ctx, _) = mock_context("speakers");
+        let cast = concat!(
+            r#"<cast><role name="Alice" voice="female"/>"#,
+            r#"<role name="bob" voice="male"/></cast>"#
+        );
+        render_with_context(&mut ctx, cast).unwrap();
+        assert_eq!(ctx.speakers.len(), 2);
+
+        let alice =
+            render_with_context(&mut ctx, r#"<speaker name="alice">Hi.</speaker>"#).unwrap();
+        let bob = render_with_context(&mut ctx, r#"<speaker name="bob">Hi.</speaker>"#).unwrap();
+        // The mock level comes from the style file (F1 = 0.5, M1 = 0.7)
+        assert!((alice.peak() - 0.5 * DEFAULT_TTS_GAIN).abs() < 1e-6);
+        assert!((bob.peak() - 0.7 * DEFAULT_TTS_GAIN).abs() < 1e-6);
+
+        // Unknown speakers keep the current voice
+        let carol =
+            render_with_context(&mut ctx, r#"<speaker name="carol">Hi.</speaker>"#).unwrap();
+        assert_eq!(carol.samples, alice.samples);
+        assert_eq!(ctx.current_voice, "female");
     }
 
-    Ok(segments)
-}
+    #[test]
+    fn test_voice_style_blend() {
+        let dir = test_voice_dir("voice-blend");
+        let load = |file: &str| {
+            load_voice_style(&[dir.join(file).to_string_lossy().to_string()], false).unwrap()
+        };
+        let (male, female) = (load("M1.json"), load("F1.json"));
+
+        let same = |a: &Style, b: &Style| {
+            a.ttl
+                .iter()
+                .zip(b.ttl.iter())
+                .all(|(x, y)| (x - y).abs() < 1e-6)
+                && a.dp
+                    .iter()
+                    .zip(b.dp.iter())
+                    .all(|(x, y)| (x - y).abs() < 1e-6)
+        };
+        assert!(same(&Style::blend(&male, &female, 0.0).unwrap(), &male));
+        assert!(same(&Style::blend(&male, &female, 1.0).unwrap(), &female));
+
+        // M1 has style value 2 and F1 has 0, so a 0.3 blend lands on 1.4
+        let (mut ctx, _) = mock_context("voice-blend");
+        let script = r#"<voice value="male" blend="female" amount="0.3">Hi.</voice>"#;
+        let blended = render_with_context(&mut ctx, script).unwrap();
+        assert!((blended.peak() - (0.5 + 0.1 * 1.4) * DEFAULT_TTS_GAIN).abs() < 1e-5);
+        assert!(ctx.current_blend.is_none());
+    }
 
-/// Convert script to audio buffer
-pub async fn script_to_audio(
-    script: &str,
-    onnx_dir: PathBuf,
-    voice_dir: PathBuf,
-    sound_effects_dir: PathBuf,
-    resource_dir: Option<PathBuf>,
-    app_handle: Option<AppHandle>,
-    job_id: String,
-) -> Result<AudioBuffer> {
-    // Create context
-    let mut ctx = ScriptToAudioContext::new(
-        onnx_dir,
-        voice_dir,
-        sound_effects_dir,
-        resource_dir,
-        app_handle.clone(),
-        job_id.clone(),
-    )
-    .await?;
+    #[test]
+    fn test_humanize_is_seeded() {
+        let script = "One. Two. Three. Four.";
+        let speeds = |humanize: f32| {
+            let (mut ctx, calls) = mock_context("humanize");
+            ctx.max_chunk_chars = 5;
+            ctx.humanize = humanize;
+            ctx.set_seed(7);
+            render_with_context(&mut ctx, script).unwrap();
+            let mut speeds = Vec::new();
+            speeds.extend(calls.lock().unwrap().iter().map(|(_, p)| p.speed));
+            speeds
+        };
 
-    // Preprocess script
-    let preprocessed = preprocess_script(script);
-    let wrapped = format!("<root>{}</root>", preprocessed);
+        let plain = speeds(0.0);
+        let first = speeds(1.0);
+        let second = speeds(1.0);
+        assert_eq!(first, second);
+        assert_ne!(first, plain);
+        assert!(plain.windows(2).all(|w| w[0] == w[1]));
+    }
 
-    // Parse with kuchiki (more robust HTML/XML parsing)
-    let document = kuchiki::parse_html().one(wrapped);
+    #[test]
+    fn test_seed_fixes_tts_noise() {
+        let noise_seeds = |seed: u64| {
+            let (mut ctx, calls) = mock_context("noise-seed");
+            ctx.max_chunk_chars = 5;
+            ctx.set_seed(seed);
+            render_with_context(&mut ctx, "One. Two. Three.").unwrap();
+            let seeds: Vec<u64> = calls
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, p)| p.noise_seed)
+                .collect();
+            seeds
+        };
 
-    // Find the root element we created
-    let root = document
-        .select_first("root")
-        .map(|n| n.as_node().clone())
-        .unwrap_or_else(|_| document.clone());
+        let first = noise_seeds(7);
+        assert_eq!(first, noise_seeds(7));
+        assert_ne!(first, noise_seeds(8));
+        // Each chunk gets its own noise
+        assert!(first.windows(2).all(|w| w[0] != w[1]));
+    }
 
-    ctx.total_nodes = count_nodes(&root);
-    ctx.current_node = 0;
+    #[test]
+    fn test_comments_are_skipped() {
+        let (mut ctx, calls) = mock_context("comments");
+        let script = concat!(
+            "Hello.<comment>Not this <voice value=\"male\">nor this</voice></comment>",
+            "<!-- or this -->"
+        );
+        render_with_context(&mut ctx, script).unwrap();
 
-    // Process all nodes
-    let mut audio_segments: Vec<AudioBuffer> = Vec::new();
-    for child in root.children() {
-        let child_segments = process_node(&mut ctx, &child)?;
-        audio_segments.extend(child_segments);
+        let texts: Vec<String> = calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(t, _)| t.clone())
+            .collect();
+        assert_eq!(texts, vec![". Hello.".to_string()]);
+        // Every counted node was visited (the root itself is never processed)
+        assert_eq!(ctx.current_node.load(Ordering::SeqCst), ctx.total_nodes - 1);
     }
 
-    // Concatenate all segments
-    if audio_segments.is_empty() {
-        Ok(AudioBuffer::new(1, 1, ctx.sample_rate))
-    } else {
-        AudioBuffer::concat(&audio_segments)
-    }
-}
+    #[test]
+    fn test_empty_scripts_fail_clearly() {
+        let (mut ctx, calls) = mock_context("empty-script");
+        for script in [
+            "",
+            "  \n\t ",
+            "<!-- just a note --> <comment>Draft</comment>",
+        ] {
+            let err = render_with_context(&mut ctx, script).unwrap_err();
+            assert!(err.to_string().contains("Script is empty"), "{}", err);
+        }
+        assert!(calls.lock().unwrap().is_empty());
 
-// ============================================================================
-// Tauri Commands
-// ============================================================================
+        ctx.empty_silence_secs = Some(0.5);
+        let silence = render_with_context(&mut ctx, "<!-- nothing -->").unwrap();
+        assert_eq!(silence.length(), 12000);
+        assert_eq!(silence.peak(), 0.0);
+    }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct AudioScript {
-    pub title: String,
-    pub script: String,
-    pub filename: Option<String>,
-}
+    #[test]
+    fn test_job_queue_bounds_concurrency() {
+        let queue = Arc::new(JobQueue::new(2));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let queued = Arc::new(AtomicUsize::new(0));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let jobs: Vec<_> = (0..6)
+                .map(|_| {
+                    let (queue, active, peak, queued) =
+                        (queue.clone(), active.clone(), peak.clone(), queued.clone());
+                    tokio::spawn(async move {
+                        let _permit = queue
+                            .acquire(|_| {
+                                queued.fetch_add(1, Ordering::SeqCst);
+                            })
+                            .await
+                            .unwrap();
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for job in jobs {
+                job.await.unwrap();
+            }
+        });
 
-/// Generate audio from script and save to file
-#[tauri::command]
-pub async fn generate_audio(
-    app_handle: AppHandle,
-    script: AudioScript,
-) -> Result<AudioScript, String> {
-    let job_id = format!(
-        "tts-{}",
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis()
-    );
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert!(queued.load(Ordering::SeqCst) >= 4);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+    }
 
-    // Get app data directory
-    let app_data_dir = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| e.to_string())?;
+    #[test]
+    fn test_job_queue_limit_changes() {
+        let queue = JobQueue::new(2);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let first = queue.acquire(|_| panic!("should not queue")).await.unwrap();
+            let second = queue.acquire(|_| panic!("should not queue")).await.unwrap();
+
+            // Lowering the limit while both run retires a slot when one ends
+            queue.set_limit(1).unwrap();
+            drop(first);
+            assert!(queue.semaphore.try_acquire().is_err());
+            drop(second);
+            assert_eq!(queue.semaphore.available_permits(), 1);
+
+            queue.set_limit(3).unwrap();
+            assert_eq!(queue.semaphore.available_permits(), 3);
+            assert_eq!(queue.limit(), 3);
+        });
+    }
 
-    // Get resource directory for bundled assets (sound effects)
-    let resource_dir = app_handle.path().resource_dir().ok();
+    #[test]
+    fn test_output_channels_forces_stereo() {
+        let (mut ctx, _) = mock_context("output-stereo");
+        ctx.output_channels = Some(OutputChannels::Stereo);
+        let audio = render_with_context(&mut ctx, "Hello.").unwrap();
+        assert_eq!(audio.num_channels(), 1);
+
+        let bytes = ctx.conform_output(&audio).to_wav_bytes().unwrap();
+        let written = AudioBuffer::from_bytes(&bytes).unwrap();
+        assert_eq!(written.num_channels(), 2);
+        assert_eq!(written.length(), audio.length());
+        assert_eq!(written.samples[0], written.samples[1]);
+
+        ctx.output_channels = Some(OutputChannels::Mono);
+        let stereo = AudioBuffer::from_stereo(vec![1.0, 0.0], vec![0.0, 1.0], 24000);
+        assert_eq!(ctx.conform_output(&stereo).samples, vec![vec![0.5, 0.5]]);
+    }
 
-    let onnx_dir = app_data_dir.join("models").join("onnx");
-    let voice_dir = app_data_dir.join("models").join("voice_styles");
-    let sound_effects_dir = app_data_dir.join("sounds");
+    #[test]
+    fn test_data_dir_override_moves_caches() {
+        let app_data = Path::new("/app-data");
+        std::env::remove_var(DATA_DIR_ENV);
+        let default = DataDirs::resolve(app_data, &RenderOptions::default());
+        assert_eq!(default.onnx_dir, app_data.join("models").join("onnx"));
+        assert_eq!(
+            default.voice_dir,
+            app_data.join("models").join("voice_styles")
+        );
+        assert_eq!(default.sound_effects_dir, app_data.join("sounds"));
+
+        std::env::set_var(DATA_DIR_ENV, "/shared");
+        let from_env = DataDirs::resolve(app_data, &RenderOptions::default());
+        let explicit = DataDirs::resolve(
+            app_data,
+            &RenderOptions {
+                data_dir: Some(PathBuf::from("/mnt/models")),
+                ..Default::default()
+            },
+        );
+        std::env::remove_var(DATA_DIR_ENV);
 
-    // Emit start progress
-    let _ = app_handle.emit(
-        "tts-progress",
-        TtsProgressEvent {
-            job_id: job_id.clone(),
-            message: format!("Starting audio generation: {}", script.title),
-            progress: 0.0,
-            stage: "start".to_string(),
-        },
-    );
+        assert_eq!(from_env.onnx_dir, Path::new("/shared/models/onnx"));
+        assert_eq!(from_env.sound_effects_dir, Path::new("/shared/sounds"));
+        assert_eq!(
+            explicit.voice_dir,
+            Path::new("/mnt/models/models/voice_styles")
+        );
+    }
 
-    // Generate audio
-    let audio = script_to_audio(
-        &script.script,
-        onnx_dir,
-        voice_dir,
-        sound_effects_dir,
-        resource_dir,
-        Some(app_handle.clone()),
-        job_id.clone(),
-    )
-    .await
-    .map_err(|e| e.to_string())?;
+    #[test]
+    fn test_limiter_keeps_peaks_under_ceiling() {
+        let rate = 24000;
+        // Half a second at a safe level, then a tone peaking at 1.5
+        let samples: Vec<f32> = (0..rate as usize)
+            .map(|i| {
+                let amplitude = if i < rate as usize / 2 { 0.4 } else { 1.5 };
+                amplitude * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / rate as f32).sin()
+            })
+            .collect();
+        let loud = AudioBuffer::from_stereo(samples.clone(), samples, rate);
+
+        let limited = apply_limiter(&loud, LIMITER_CEILING_DB);
+        let ceiling = 10f32.powf(LIMITER_CEILING_DB / 20.0);
+        assert_eq!(limited.length(), loud.length());
+        for channel in &limited.samples {
+            assert!(channel.iter().all(|s| s.abs() <= ceiling + 1e-6));
+            // Nothing is left flat-topped at full scale
+            assert!(channel.iter().all(|s| s.abs() < 0.999));
+        }
+        // Well before the loud part the audio is untouched
+        assert_eq!(
+            limited.samples[0][..rate as usize / 4],
+            loud.samples[0][..rate as usize / 4]
+        );
 
-    // Write to file
-    let filename = script
-        .filename
-        .clone()
-        .unwrap_or_else(|| format!("{}.wav", script.title));
-    let output_path = app_data_dir.join(&filename);
-
-    let _ = app_handle.emit(
-        "tts-progress",
-        TtsProgressEvent {
-            job_id: job_id.clone(),
-            message: format!("Writing audio file: {}", filename),
-            progress: 0.99,
-            stage: "write".to_string(),
-        },
-    );
+        let (mut ctx, _) = mock_context("limiter");
+        ctx.limiter_ceiling_db = Some(-6.0);
+        let rendered = finish_render(ctx, loud).unwrap();
+        let peak = rendered.audio.samples[0]
+            .iter()
+            .fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak <= 10f32.powf(-6.0 / 20.0) + 1e-6);
+        assert!(rendered.profile.stages.contains_key("limiter"));
+    }
 
-    audio
-        .write_to_file(&output_path)
-        .map_err(|e| e.to_string())?;
+    #[test]
+    fn test_output_sample_rate_resamples_final_audio() {
+        let (mut ctx, _) = mock_context("output-rate");
+        ctx.output_sample_rate = Some(48000);
+        let audio = render_with_context(&mut ctx, "Hello.").unwrap();
+        assert_eq!(audio.sample_rate, 24000);
+
+        let bytes = ctx.conform_output(&audio).to_wav_bytes().unwrap();
+        let reader = WavReader::new(std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 48000);
+        let frames = reader.duration() as usize;
+        assert!(frames.abs_diff(2 * audio.length()) <= 2);
+    }
 
-    // Emit completion
-    let _ = app_handle.emit(
-        "tts-progress",
-        TtsProgressEvent {
-            job_id: job_id.clone(),
-            message: "Audio generation complete".to_string(),
-            progress: 1.0,
-            stage: "complete".to_string(),
-        },
-    );
+    #[test]
+    fn test_profile_accounts_for_render() {
+        let (mut ctx, calls) = mock_context("profile");
+        let script = concat!(
+            r#"Hello there. <voice value="male">Bye.</voice>"#,
+            r#"<effect value="echo">Again.</effect>"#
+        );
+        let started = Instant::now();
+        render_with_context(&mut ctx, script).unwrap();
+        let wall = started.elapsed().as_secs_f64();
+
+        let profile = ctx.profile();
+        let staged: f64 = profile.stages.values().sum();
+        assert!(staged <= wall);
+        let keys: Vec<&str> = profile.stages.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["effect:echo", "tts:root", "tts:voice"]);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(profile.tts_calls, 3);
+        assert_eq!(calls.len(), 3);
+        let samples: usize = calls.iter().map(|(text, _)| mock_len(text)).sum();
+        assert!((profile.tts_audio_secs - samples as f64 / 24000.0).abs() < 1e-9);
+    }
 
-    Ok(AudioScript {
-        title: script.title,
-        script: script.script,
-        filename: Some(filename),
-    })
-}
+    #[test]
+    fn test_wav_data_uri_round_trips() {
+        use base64::Engine;
+
+        let audio = AudioBuffer::from_stereo(vec![0.5, -0.25], vec![0.0, 1.0], 24000);
+        let uri = wav_data_uri(&audio).unwrap();
+        let encoded = uri.strip_prefix("data:audio/wav;base64,").unwrap();
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        assert_eq!(bytes, audio.to_wav_bytes().unwrap());
+        let decoded = AudioBuffer::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.num_channels(), 2);
+        assert_eq!(decoded.sample_rate, 24000);
+        assert!((decoded.samples[0][1] + 0.25).abs() < 1e-3);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_playback_feeder_interleaves() {
+        let buffer = AudioBuffer::from_stereo(vec![0.1, 0.2, 0.3], vec![-0.1, -0.2, -0.3], 24000);
+        let mut feeder = PlaybackFeeder::new(&buffer, 24000, 2);
+
+        let mut out = [0.0f32; 4];
+        assert!(feeder.fill(&mut out));
+        assert_eq!(out, [0.1, -0.1, 0.2, -0.2]);
+        // The last frame is followed by silence and the feeder reports the end
+        assert!(!feeder.fill(&mut out));
+        assert_eq!(out, [0.3, -0.3, 0.0, 0.0]);
+        assert!(!feeder.fill(&mut out));
+        assert_eq!(out, [0.0; 4]);
+
+        // Mono is spread over every device channel and converted to i16
+        let mono = AudioBuffer::from_mono(vec![0.5, -0.5], 24000);
+        let mut feeder = PlaybackFeeder::new(&mono, 24000, 2);
+        let mut out = [0i16; 4];
+        feeder.fill(&mut out);
+        assert_eq!(out[0], out[1]);
+        assert_eq!(out[2], out[3]);
+        assert!(out[0] > 16000 && out[2] < -16000);
+
+        // Device rate conversion changes the frame count
+        let feeder = PlaybackFeeder::new(&AudioBuffer::silence(0.1, 24000), 48000, 2);
+        assert_eq!(feeder.frames(), 4800);
+    }
 
     #[test]
-    fn test_preprocess_script() {
-        // Test ellipsis replacement
-        let input = "Hello... world";
-        let result = preprocess_script(input);
-        assert!(result.contains(r#"<pause value="0.5"></pause>"#));
+    fn test_scripts_without_speech_skip_model_loading() {
+        // Nothing exists here, so any download or session build would fail
+        let dir = std::env::temp_dir().join("domgpt-lazy-model");
+        let _ = fs::remove_dir_all(&dir);
+        let tts_state = TtsState::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let paths = RenderPaths {
+            onnx_dir: dir.join("onnx"),
+            voice_dir: dir.join("voice_styles"),
+            sound_effects_dir: dir.join("sounds"),
+            resource_dir: None,
+        };
 
-        // Test HTML entity unescaping
-        let input2 = "&amp; &lt; &gt;";
-        let result2 = preprocess_script(input2);
-        assert!(result2.contains("& < >"));
+        let rendered = runtime
+            .block_on(script_to_audio(
+                r#"<pause value="0.2"/><sound value="pop"/>"#,
+                &paths,
+                None,
+                "test".to_string(),
+                &RenderOptions::default(),
+                &tts_state,
+                None,
+            ))
+            .unwrap();
+        assert!(rendered.audio.length() > 0);
+        assert_eq!(tts_state.load_count(), 0);
+        assert!(!dir.exists());
+
+        assert!(!has_speech(&parse_script("<pause/><!-- say nothing -->")));
+        assert!(has_speech(&parse_script(
+            "<voice value=\"male\">Hi</voice>"
+        )));
+
+        // The wrapped engine is only built once speech is requested
+        let loads = Arc::new(AtomicUsize::new(0));
+        let counter = loads.clone();
+        let engine = Arc::new(Mutex::new(LazyEngine::new(24000, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Arc::new(Mutex::new(MockEngine::default())) as Arc<Mutex<dyn SpeechEngine>>)
+        })));
+        let voices = test_voice_dir("lazy-model");
+        let mut ctx =
+            ScriptToAudioContext::with_engine(engine.clone(), voices.clone(), voices).unwrap();
+        render_with_context(&mut ctx, r#"<pause value="0.1"/>"#).unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 0);
+        render_with_context(&mut ctx, "Hello. <pause/> Again.").unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(engine.lock().unwrap().is_loaded());
     }
 
     #[test]
-    fn test_audio_buffer_silence() {
-        let buffer = AudioBuffer::silence(1.0, 24000);
-        assert_eq!(buffer.length(), 24000);
-        assert_eq!(buffer.num_channels(), 1);
+    fn test_render_script_needs_no_app_handle() {
+        let dir = std::env::temp_dir().join("domgpt-render-script");
+        let _ = fs::remove_dir_all(&dir);
+        let sounds_dir = dir.join("sounds");
+        fs::create_dir_all(&sounds_dir).unwrap();
+        let tone: Vec<f32> = (0..2400).map(|i| (i as f32 * 0.05).sin() * 0.5).collect();
+        let chime = AudioBuffer::from_mono(tone, SAMPLE_RATE);
+        fs::write(sounds_dir.join("Chime.wav"), chime.to_wav_bytes().unwrap()).unwrap();
+
+        let paths = RenderPaths {
+            onnx_dir: dir.join("models").join("onnx"),
+            voice_dir: dir.join("models").join("voice_styles"),
+            sound_effects_dir: sounds_dir,
+            resource_dir: None,
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let audio = runtime
+            .block_on(render_script(
+                r#"<pause value="0.25"/><sound value="chime"/>"#,
+                paths,
+                RenderOptions::default(),
+            ))
+            .unwrap();
+
+        let pause = (0.25 * SAMPLE_RATE as f32) as usize;
+        assert!(audio.length().abs_diff(pause + chime.length()) <= 2);
+        assert!(audio.samples[0][pause..].iter().any(|s| s.abs() > 0.1));
+        // Nothing was downloaded for a script without speech
+        assert!(!dir.join("models").exists());
+    }
+
+    /// Collects the events of a render for inspection
+    #[derive(Default)]
+    struct ProgressCollector {
+        events: Mutex<Vec<TtsProgressEvent>>,
+    }
+
+    impl ProgressSink for ProgressCollector {
+        fn report(&self, event: TtsProgressEvent) {
+            self.events.lock().unwrap().push(event);
+        }
     }
 
     #[test]
-    fn test_audio_buffer_concat() {
-        let b1 = AudioBuffer::from_mono(vec![0.5; 100], 24000);
-        let b2 = AudioBuffer::from_mono(vec![-0.5; 100], 24000);
-        let result = AudioBuffer::concat(&[b1, b2]).unwrap();
-        assert_eq!(result.length(), 200);
+    fn test_progress_sink_sees_start_generate_complete() {
+        let dir = std::env::temp_dir().join("domgpt-progress-sink");
+        let _ = fs::remove_dir_all(&dir);
+        let paths = RenderPaths {
+            onnx_dir: dir.join("onnx"),
+            voice_dir: dir.join("voice_styles"),
+            sound_effects_dir: dir.join("sounds"),
+            resource_dir: None,
+        };
+        let collector = Arc::new(ProgressCollector::default());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(script_to_audio(
+                r#"<pause value="0.1"/><pause value="0.1"/>"#,
+                &paths,
+                Some(collector.clone()),
+                "progress-test".to_string(),
+                &RenderOptions::default(),
+                &TtsState::default(),
+                None,
+            ))
+            .unwrap();
+
+        let events = collector.events.lock().unwrap();
+        let mut stages: Vec<&str> = events.iter().map(|e| e.stage.as_str()).collect();
+        stages.dedup();
+        assert_eq!(stages, ["start", "generate", "complete"]);
+        assert!(events.iter().all(|e| e.job_id == "progress-test"));
+        assert_eq!(events.last().unwrap().progress, 1.0);
     }
 
     #[test]
-    fn test_apply_echo() {
-        let buffer = AudioBuffer::from_mono(vec![1.0; 1000], 24000);
-        let options = EffectOptions {
-            delay: Some(0.1),
-            decay: Some(0.5),
-            repeats: Some(2),
-            ..Default::default()
+    fn test_seed_makes_random_choices_reproducible() {
+        let dir = std::env::temp_dir().join("domgpt-seeded-render");
+        let paths = RenderPaths {
+            onnx_dir: dir.join("onnx"),
+            voice_dir: dir.join("voice_styles"),
+            sound_effects_dir: dir.join("sounds"),
+            resource_dir: None,
         };
-        let result = apply_echo(&buffer, &options);
-        assert!(result.length() > buffer.length());
+        let tts_state = TtsState::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let script = (1..=8)
+            .map(|i| format!(r#"<item><pause value="{}"/></item>"#, i as f32 / 10.0))
+            .collect::<String>();
+        let script = format!("<random>{}</random><random>{}</random>", script, script);
+        let render = |seed: Option<u64>| {
+            let options = RenderOptions {
+                seed,
+                ..Default::default()
+            };
+            runtime
+                .block_on(script_to_audio(
+                    &script,
+                    &paths,
+                    None,
+                    "test".to_string(),
+                    &options,
+                    &tts_state,
+                    None,
+                ))
+                .unwrap()
+        };
+
+        let first = render(Some(42));
+        let second = render(Some(42));
+        assert_eq!(first.seed, 42);
+        assert_eq!(first.audio.length(), second.audio.length());
+
+        // An unseeded render reports the seed it picked, which replays it
+        let unseeded = render(None);
+        let replayed = render(Some(unseeded.seed));
+        assert_eq!(unseeded.audio.length(), replayed.audio.length());
     }
 
     #[test]
-    fn test_effect_options_from_json() {
-        let json = r#"{"delay": 0.5, "decay": 0.3}"#;
-        let opts = EffectOptions::from_json(json);
-        assert_eq!(opts.delay, Some(0.5));
-        assert_eq!(opts.decay, Some(0.3));
+    fn test_shared_model_loads_once() {
+        let shared: SharedModel<u32> = SharedModel::default();
+        let dir = Path::new("models");
+        let (first, _) = shared
+            .get_or_load(dir, ExecutionProvider::Cpu, || {
+                Ok((7, ExecutionProvider::Cpu))
+            })
+            .unwrap();
+        let (second, _) = shared
+            .get_or_load(dir, ExecutionProvider::Cpu, || {
+                panic!("model should not be loaded twice")
+            })
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(shared.load_count(), 1);
     }
 
     #[test]
-    fn test_kuchiki_parsing() {
-        let html = "<root><voice value=\"female\">Hello world</voice></root>";
-        let document = kuchiki::parse_html().one(html);
-        let root = document.select_first("root").unwrap();
-        let voice = root.as_node().select_first("voice").unwrap();
-        let attrs = voice.as_node().as_element().unwrap().attributes.borrow();
-        assert_eq!(attrs.get("value"), Some("female"));
+    fn test_shared_model_reloads_for_other_provider_or_dir() {
+        let shared: SharedModel<u32> = SharedModel::default();
+        let dir = Path::new("models");
+        let (cpu, _) = shared
+            .get_or_load(dir, ExecutionProvider::Cpu, || {
+                Ok((1, ExecutionProvider::Cpu))
+            })
+            .unwrap();
+
+        // A CUDA request after a CPU warm-up loads again, even if it falls
+        // back to the CPU
+        let (cuda, used) = shared
+            .get_or_load(dir, ExecutionProvider::Cuda, || {
+                Ok((2, ExecutionProvider::Cpu))
+            })
+            .unwrap();
+        assert!(!Arc::ptr_eq(&cpu, &cuda));
+        assert_eq!(*cuda.lock().unwrap(), 2);
+        assert_eq!(used, ExecutionProvider::Cpu);
+        assert_eq!(shared.load_count(), 2);
+
+        // The fallback is cached under the requested provider
+        shared
+            .get_or_load(dir, ExecutionProvider::Cuda, || {
+                panic!("model should not be loaded again")
+            })
+            .unwrap();
+        assert_eq!(shared.load_count(), 2);
+
+        shared
+            .get_or_load(Path::new("other"), ExecutionProvider::Cuda, || {
+                Ok((3, ExecutionProvider::Cpu))
+            })
+            .unwrap();
+        assert_eq!(shared.load_count(), 3);
     }
 }
@@ -0,0 +1,555 @@
+// ============================================================================
+// Text normalization - expands abbreviations and units before synthesis
+// ============================================================================
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Abbreviations spoken as their full form. Matching ignores case and the
+/// replacement follows the capitalization of the matched text.
+pub const DEFAULT_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("Dr.", "Doctor"),
+    ("Mr.", "Mister"),
+    ("Mrs.", "Missus"),
+    ("Prof.", "Professor"),
+    ("Jr.", "Junior"),
+    ("Sr.", "Senior"),
+    ("Mt.", "Mount"),
+    ("Ave.", "Avenue"),
+    ("Dept.", "Department"),
+    ("approx.", "approximately"),
+    ("vs.", "versus"),
+    ("etc.", "et cetera"),
+    ("e.g.", "for example"),
+    ("i.e.", "that is"),
+    ("a.m.", "AM"),
+    ("p.m.", "PM"),
+];
+
+/// Units expanded only when they follow a number. Matching is case
+/// sensitive so that e.g. `mb` and `MB` can mean different things.
+pub const DEFAULT_UNITS: &[(&str, &str)] = &[
+    ("km/h", "kilometers per hour"),
+    ("kph", "kilometers per hour"),
+    ("mph", "miles per hour"),
+    ("km", "kilometers"),
+    ("cm", "centimeters"),
+    ("mm", "millimeters"),
+    ("mi", "miles"),
+    ("ft", "feet"),
+    ("kg", "kilograms"),
+    ("mg", "milligrams"),
+    ("g", "grams"),
+    ("lb", "pounds"),
+    ("lbs", "pounds"),
+    ("oz", "ounces"),
+    ("ml", "milliliters"),
+    ("mL", "milliliters"),
+    ("kHz", "kilohertz"),
+    ("Hz", "hertz"),
+    ("kB", "kilobytes"),
+    ("MB", "megabytes"),
+    ("GB", "gigabytes"),
+    ("TB", "terabytes"),
+    ("ms", "milliseconds"),
+    ("sec", "seconds"),
+    ("min", "minutes"),
+    ("hr", "hours"),
+    ("hrs", "hours"),
+    ("°C", "degrees Celsius"),
+    ("°F", "degrees Fahrenheit"),
+];
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [(u64, &str); 4] = [
+    (1_000_000_000_000, "trillion"),
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+
+/// Money amounts (`$5.50`, `€10`, `10 €`, `$2.5 million`) and percentages
+const AMOUNT_PATTERN: &str = concat!(
+    r"(?P<pre>[$€£¤])\s?(?P<amount>\d(?:[.,]?\d)*)",
+    r"(?:\s+(?P<scale>thousand|million|billion|trillion)\b)?",
+    r"|(?P<amount_post>\d(?:[.,]?\d)*)\s?(?P<post>[€£¤])",
+    r"|(?P<percent>\d(?:[.,]?\d)*)\s?%"
+);
+
+/// Currency read for the `¤` sign. It also decides whether `,` or `.` is
+/// tried first as the decimal separator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Eur,
+    Gbp,
+}
+
+impl Currency {
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        match symbol {
+            '$' => Some(Currency::Usd),
+            '€' => Some(Currency::Eur),
+            '£' => Some(Currency::Gbp),
+            _ => None,
+        }
+    }
+
+    /// Singular and plural of the main unit, then of the hundredth
+    fn names(&self) -> [&'static str; 4] {
+        match self {
+            Currency::Usd => ["dollar", "dollars", "cent", "cents"],
+            Currency::Eur => ["euro", "euros", "cent", "cents"],
+            Currency::Gbp => ["pound", "pounds", "penny", "pence"],
+        }
+    }
+
+    fn decimal_separator(&self) -> char {
+        match self {
+            Currency::Eur => ',',
+            Currency::Usd | Currency::Gbp => '.',
+        }
+    }
+}
+
+/// Rewrites abbreviations and units into words the model reads naturally
+#[derive(Clone, Debug)]
+pub struct TextNormalizer {
+    abbreviations: HashMap<String, String>,
+    units: HashMap<String, String>,
+    abbreviation_pattern: Option<Regex>,
+    unit_pattern: Option<Regex>,
+    amount_pattern: Regex,
+    currency: Currency,
+}
+
+impl Default for TextNormalizer {
+    fn default() -> Self {
+        Self::with_overrides(None, None)
+    }
+}
+
+impl TextNormalizer {
+    /// Build from the default maps with `abbreviations` and `units` layered
+    /// on top. An empty replacement removes a default entry.
+    pub fn with_overrides(
+        abbreviations: Option<&HashMap<String, String>>,
+        units: Option<&HashMap<String, String>>,
+    ) -> Self {
+        // Abbreviations are looked up case-insensitively, units exactly
+        let merge = |defaults: &[(&str, &str)],
+                     overrides: Option<&HashMap<String, String>>,
+                     fold_case: bool| {
+            let key = |from: &str| {
+                if fold_case {
+                    from.to_lowercase()
+                } else {
+                    from.to_string()
+                }
+            };
+            let mut map: HashMap<String, String> = defaults
+                .iter()
+                .map(|(from, to)| (key(from), to.to_string()))
+                .collect();
+            for (from, to) in overrides.into_iter().flatten() {
+                if to.is_empty() {
+                    map.remove(&key(from));
+                } else {
+                    map.insert(key(from), to.clone());
+                }
+            }
+            map.retain(|from, _| !from.trim().is_empty());
+            map
+        };
+        let abbreviations = merge(DEFAULT_ABBREVIATIONS, abbreviations, true);
+        let units = merge(DEFAULT_UNITS, units, false);
+
+        TextNormalizer {
+            abbreviation_pattern: alternation(abbreviations.keys(), true),
+            unit_pattern: alternation(units.keys(), false),
+            amount_pattern: Regex::new(AMOUNT_PATTERN).unwrap(),
+            currency: Currency::default(),
+            abbreviations,
+            units,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = currency;
+        self
+    }
+
+    /// Expand every abbreviation, money amount, percentage and unit that
+    /// follows a number. Matches inside a longer word are left alone.
+    pub fn normalize(&self, text: &str) -> String {
+        let text = match &self.abbreviation_pattern {
+            Some(pattern) => self.expand_abbreviations(pattern, text),
+            None => text.to_string(),
+        };
+        let text = self.expand_amounts(&text);
+        match &self.unit_pattern {
+            Some(pattern) => self.expand_units(pattern, &text),
+            None => text,
+        }
+    }
+
+    fn expand_abbreviations(&self, pattern: &Regex, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for found in pattern.find_iter(text) {
+            let matched = found.as_str();
+            if !is_word_start(text, found.start()) || !is_word_end(text, found.end()) {
+                continue;
+            }
+            let Some(expansion) = self.abbreviations.get(&matched.to_lowercase()) else {
+                continue;
+            };
+
+            result.push_str(&text[last..found.start()]);
+            result.push_str(&match_case(matched, expansion));
+            // The abbreviation's period also ended the text; keep it
+            if matched.ends_with('.') && text[found.end()..].trim().is_empty() {
+                result.push('.');
+            }
+            last = found.end();
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+
+    /// Spell out money and percentages; amounts that don't parse are kept
+    fn expand_amounts(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for caps in self.amount_pattern.captures_iter(text) {
+            let found = caps.get(0).unwrap();
+            if !is_word_start(text, found.start()) || !is_word_end(text, found.end()) {
+                continue;
+            }
+
+            let spoken = if let Some(percent) = caps.name("percent") {
+                self.parse_amount(percent.as_str(), usize::MAX)
+                    .map(|(whole, fraction)| format!("{} percent", spoken_decimal(whole, fraction)))
+            } else {
+                let symbol = caps
+                    .name("pre")
+                    .or_else(|| caps.name("post"))
+                    .and_then(|m| m.as_str().chars().next());
+                let currency = symbol
+                    .and_then(Currency::from_symbol)
+                    .unwrap_or(self.currency);
+                let digits = caps
+                    .name("amount")
+                    .or_else(|| caps.name("amount_post"))
+                    .map_or("", |m| m.as_str());
+                let scale = caps.name("scale").map(|m| m.as_str());
+                let max_fraction = if scale.is_some() { usize::MAX } else { 2 };
+                self.parse_amount(digits, max_fraction)
+                    .map(|(whole, fraction)| spoken_money(currency, whole, fraction, scale))
+            };
+            let Some(spoken) = spoken else {
+                continue;
+            };
+
+            result.push_str(&text[last..found.start()]);
+            result.push_str(&spoken);
+            last = found.end();
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+
+    /// Read `digits` with the currency's decimal separator, falling back to
+    /// the other one when that doesn't parse
+    fn parse_amount<'a>(&self, digits: &'a str, max_fraction: usize) -> Option<(u64, &'a str)> {
+        let decimal = self.currency.decimal_separator();
+        let other = if decimal == '.' { ',' } else { '.' };
+        [decimal, other]
+            .into_iter()
+            .filter_map(|separator| parse_number(digits, separator))
+            .find(|(_, fraction)| fraction.len() <= max_fraction)
+    }
+
+    fn expand_units(&self, pattern: &Regex, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for found in pattern.find_iter(text) {
+            let before = &text[..found.start()];
+            let number_end = before.strip_suffix(' ').unwrap_or(before);
+            if !number_end.ends_with(|c: char| c.is_ascii_digit())
+                || !is_word_end(text, found.end())
+            {
+                continue;
+            }
+            let Some(expansion) = self.units.get(found.as_str()) else {
+                continue;
+            };
+
+            result.push_str(&text[last..number_end.len()]);
+            result.push(' ');
+            result.push_str(expansion);
+            last = found.end();
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+}
+
+/// Spell out a whole number, e.g. 1205 as "one thousand two hundred five"
+pub fn number_to_words(n: u64) -> String {
+    if n < 20 {
+        return ONES[n as usize].to_string();
+    }
+    if n < 100 {
+        let tens = TENS[(n / 10) as usize];
+        return match n % 10 {
+            0 => tens.to_string(),
+            ones => format!("{} {}", tens, ONES[ones as usize]),
+        };
+    }
+
+    let (scale, name) = SCALES
+        .iter()
+        .copied()
+        .find(|&(scale, _)| n >= scale)
+        .unwrap_or((100, "hundred"));
+    let head = format!("{} {}", number_to_words(n / scale), name);
+    match n % scale {
+        0 => head,
+        rest => format!("{} {}", head, number_to_words(rest)),
+    }
+}
+
+/// Split `1,234.5` into its whole part and fraction digits, with
+/// `decimal` as the decimal separator and the other of `.`/`,` grouping
+/// thousands
+fn parse_number(digits: &str, decimal: char) -> Option<(u64, &str)> {
+    let group = if decimal == '.' { ',' } else { '.' };
+    let (whole, fraction) = digits.split_once(decimal).unwrap_or((digits, ""));
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if whole.contains(group) {
+        let mut groups = whole.split(group);
+        let first = groups.next()?;
+        if first.is_empty() || first.len() > 3 || groups.any(|g| g.len() != 3) {
+            return None;
+        }
+    }
+    let whole: String = whole.chars().filter(|&c| c != group).collect();
+    if whole.is_empty() || !whole.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((whole.parse().ok()?, fraction))
+}
+
+/// "two point five" for 2 and "5"
+fn spoken_decimal(whole: u64, fraction: &str) -> String {
+    let mut words = number_to_words(whole);
+    if !fraction.is_empty() {
+        words.push_str(" point");
+        for digit in fraction.chars().filter_map(|c| c.to_digit(10)) {
+            words.push(' ');
+            words.push_str(ONES[digit as usize]);
+        }
+    }
+    words
+}
+
+/// "five dollars and fifty cents", or "two point five million dollars"
+/// when a scale word follows the amount
+fn spoken_money(currency: Currency, whole: u64, fraction: &str, scale: Option<&str>) -> String {
+    let [major_one, major_many, minor_one, minor_many] = currency.names();
+    if let Some(scale) = scale {
+        return format!(
+            "{} {} {}",
+            spoken_decimal(whole, fraction),
+            scale,
+            major_many
+        );
+    }
+
+    let minor: u64 = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().unwrap_or(0) * 10,
+        _ => fraction.parse().unwrap_or(0),
+    };
+    let major_words = format!(
+        "{} {}",
+        number_to_words(whole),
+        if whole == 1 { major_one } else { major_many }
+    );
+    let minor_words = format!(
+        "{} {}",
+        number_to_words(minor),
+        if minor == 1 { minor_one } else { minor_many }
+    );
+    match (whole, minor) {
+        (_, 0) => major_words,
+        (0, _) => minor_words,
+        _ => format!("{} and {}", major_words, minor_words),
+    }
+}
+
+/// One regex matching any of `keys`, longest first so `km/h` wins over `km`
+fn alternation<'a>(keys: impl Iterator<Item = &'a String>, ignore_case: bool) -> Option<Regex> {
+    let mut keys: Vec<&String> = keys.collect();
+    if keys.is_empty() {
+        return None;
+    }
+    keys.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    let body = keys
+        .iter()
+        .map(|key| regex::escape(key))
+        .collect::<Vec<_>>()
+        .join("|");
+    let flags = if ignore_case { "(?i)" } else { "" };
+    Regex::new(&format!("{}(?:{})", flags, body)).ok()
+}
+
+fn is_word_start(text: &str, at: usize) -> bool {
+    !text[..at].ends_with(|c: char| c.is_alphanumeric())
+}
+
+/// A match ending in punctuation (`Dr.`, `%`) needs no boundary after it
+fn is_word_end(text: &str, at: usize) -> bool {
+    if text[..at].ends_with(|c: char| !c.is_alphanumeric()) {
+        return true;
+    }
+    !text[at..].starts_with(|c: char| c.is_alphanumeric())
+}
+
+/// Uppercase the expansion for an all-caps match and capitalize it for a
+/// capitalized one; otherwise use it as written
+fn match_case(matched: &str, expansion: &str) -> String {
+    let letters: Vec<char> = matched.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+        return expansion.to_uppercase();
+    }
+    match letters.first() {
+        Some(first) if first.is_uppercase() => {
+            let mut chars = expansion.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+        _ => expansion.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expands_abbreviations() {
+        let normalizer = TextNormalizer::default();
+        assert_eq!(
+            normalizer.normalize("Dr. Smith lives nearby."),
+            "Doctor Smith lives nearby."
+        );
+        assert_eq!(
+            normalizer.normalize("See you at 3 p.m. sharp"),
+            "See you at 3 PM sharp"
+        );
+        assert_eq!(normalizer.normalize("Ask the DR."), "Ask the DOCTOR.");
+        // Not inside longer words
+        assert_eq!(
+            normalizer.normalize("Drake met Mr. Lee"),
+            "Drake met Mister Lee"
+        );
+        assert_eq!(normalizer.normalize("Redr. x"), "Redr. x");
+    }
+
+    #[test]
+    fn test_expands_units_after_numbers() {
+        let normalizer = TextNormalizer::default();
+        assert_eq!(
+            normalizer.normalize("She lives 5 km away, 12kg heavier."),
+            "She lives 5 kilometers away, 12 kilograms heavier."
+        );
+        assert_eq!(
+            normalizer.normalize("Drive at 50 km/h"),
+            "Drive at 50 kilometers per hour"
+        );
+        // No number, part of a word, or a different case
+        assert_eq!(normalizer.normalize("km markers"), "km markers");
+        assert_eq!(normalizer.normalize("5 kmart 5 KM"), "5 kmart 5 KM");
+    }
+
+    #[test]
+    fn test_expands_money_and_percentages() {
+        let normalizer = TextNormalizer::default();
+        assert_eq!(
+            normalizer.normalize("$5.50"),
+            "five dollars and fifty cents"
+        );
+        assert_eq!(normalizer.normalize("€10"), "ten euros");
+        assert_eq!(normalizer.normalize("25%"), "twenty five percent");
+        assert_eq!(
+            normalizer.normalize("Pay £1.01 or 10 €, up 2.5% from $1.000."),
+            concat!(
+                "Pay one pound and one penny or ten euros, ",
+                "up two point five percent from one thousand dollars."
+            )
+        );
+        assert_eq!(
+            normalizer.normalize("A $2.5 million deal"),
+            "A two point five million dollars deal"
+        );
+        // Malformed amounts pass through
+        assert_eq!(
+            normalizer.normalize("$5.5.5 and $12abc"),
+            "$5.5.5 and $12abc"
+        );
+
+        let euro = TextNormalizer::default().with_currency(Currency::Eur);
+        assert_eq!(euro.normalize("¤3,50"), "three euros and fifty cents");
+        assert_eq!(number_to_words(1_205), "one thousand two hundred five");
+    }
+
+    #[test]
+    fn test_overrides_replace_and_remove_defaults() {
+        let abbreviations = HashMap::from([
+            ("st.".to_string(), "Street".to_string()),
+            ("dr.".to_string(), String::new()),
+        ]);
+        let units = HashMap::from([("km".to_string(), "klicks".to_string())]);
+        let normalizer = TextNormalizer::with_overrides(Some(&abbreviations), Some(&units));
+
+        assert_eq!(
+            normalizer.normalize("Dr. Lee, Main St. is 2 km"),
+            "Dr. Lee, Main Street is 2 klicks"
+        );
+    }
+}
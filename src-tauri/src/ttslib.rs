@@ -28,6 +28,8 @@ SOFTWARE.
 use anyhow::{Context, Result};
 use hound::{SampleFormat, WavSpec, WavWriter};
 use ndarray::{Array, Array3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand_distr::{Distribution, Normal};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -270,13 +272,17 @@ pub fn get_text_mask(text_ids_lengths: &[usize]) -> Array3<f32> {
     length_to_mask(text_ids_lengths, Some(max_len))
 }
 
-/// Sample noisy latent from normal distribution and apply mask
+/// Sample noisy latent from normal distribution and apply mask.
+/// `temperature` scales the standard deviation of the initial noise; a
+/// `seed` makes the noise, and so the speech, the same on every call.
 pub fn sample_noisy_latent(
     duration: &[f32],
     sample_rate: i32,
     base_chunk_size: i32,
     chunk_compress: i32,
     latent_dim: i32,
+    temperature: f32,
+    seed: Option<u64>,
 ) -> (Array3<f32>, Array3<f32>) {
     let bsz = duration.len();
     let max_dur = duration.iter().fold(0.0f32, |a, &b| a.max(b));
@@ -293,8 +299,11 @@ pub fn sample_noisy_latent(
 
     let mut noisy_latent = Array3::<f32>::zeros((bsz, latent_dim_val, latent_len));
 
-    let normal = Normal::new(0.0, 1.0).unwrap();
-    let mut rng = rand::thread_rng();
+    let normal = Normal::new(0.0, temperature.max(0.0)).unwrap();
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
 
     for b in 0..bsz {
         for d in 0..latent_dim_val {
@@ -357,7 +366,7 @@ pub fn write_wav_file<P: AsRef<Path>>(
 
 const MAX_CHUNK_LENGTH: usize = 300;
 
-const ABBREVIATIONS: &[&str] = &[
+pub const ABBREVIATIONS: &[&str] = &[
     "Dr.", "Mr.", "Mrs.", "Ms.", "Prof.", "Sr.", "Jr.", "St.", "Ave.", "Rd.", "Blvd.", "Dept.",
     "Inc.", "Ltd.", "Co.", "Corp.", "etc.", "vs.", "i.e.", "e.g.", "Ph.D.",
 ];
@@ -565,11 +574,26 @@ pub fn sanitize_filename(text: &str, max_len: usize) -> String {
 
 use ort::{session::Session, value::Value};
 
+#[derive(Clone)]
 pub struct Style {
     pub ttl: Array3<f32>,
     pub dp: Array3<f32>,
 }
 
+impl Style {
+    /// Linearly interpolate between two styles: `t = 0` gives `a`, `t = 1` gives `b`
+    pub fn blend(a: &Style, b: &Style, t: f32) -> Result<Style> {
+        if a.ttl.shape() != b.ttl.shape() || a.dp.shape() != b.dp.shape() {
+            anyhow::bail!("Cannot blend voice styles with different shapes");
+        }
+        let t = t.clamp(0.0, 1.0);
+        Ok(Style {
+            ttl: &a.ttl * (1.0 - t) + &b.ttl * t,
+            dp: &a.dp * (1.0 - t) + &b.dp * t,
+        })
+    }
+}
+
 pub struct TextToSpeech {
     cfgs: Config,
     text_processor: UnicodeProcessor,
@@ -578,6 +602,10 @@ pub struct TextToSpeech {
     vector_est_ort: Session,
     vocoder_ort: Session,
     pub sample_rate: i32,
+    pub temperature: f32,
+    /// Seed for the initial noise of the next call; each chunk of a call
+    /// uses the seed plus its index. `None` draws fresh noise every time.
+    pub noise_seed: Option<u64>,
 }
 
 impl TextToSpeech {
@@ -598,6 +626,8 @@ impl TextToSpeech {
             vector_est_ort,
             vocoder_ort,
             sample_rate,
+            temperature: 1.0,
+            noise_seed: None,
         }
     }
 
@@ -607,6 +637,7 @@ impl TextToSpeech {
         style: &Style,
         total_step: usize,
         speed: f32,
+        seed: Option<u64>,
     ) -> Result<(Vec<f32>, Vec<f32>)> {
         let bsz = text_list.len();
 
@@ -667,6 +698,8 @@ impl TextToSpeech {
             self.cfgs.ae.base_chunk_size,
             self.cfgs.ttl.chunk_compress_factor,
             self.cfgs.ttl.latent_dim,
+            self.temperature,
+            seed,
         );
 
         // Prepare constant arrays
@@ -731,7 +764,8 @@ impl TextToSpeech {
         let mut dur_cat: f32 = 0.0;
 
         for (i, chunk) in chunks.iter().enumerate() {
-            let (wav, duration) = self._infer(&[chunk.clone()], style, total_step, speed)?;
+            let seed = self.noise_seed.map(|seed| seed.wrapping_add(i as u64));
+            let (wav, duration) = self._infer(&[chunk.clone()], style, total_step, speed, seed)?;
 
             let dur = duration[0];
             let wav_len = (self.sample_rate as f32 * dur) as usize;
@@ -760,7 +794,7 @@ impl TextToSpeech {
         total_step: usize,
         speed: f32,
     ) -> Result<(Vec<f32>, Vec<f32>)> {
-        self._infer(text_list, style, total_step, speed)
+        self._infer(text_list, style, total_step, speed, self.noise_seed)
     }
 }
 
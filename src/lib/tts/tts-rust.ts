@@ -8,6 +8,14 @@ export interface AudioScript {
   title: string;
   script: string;
   filename?: string;
+  /** Length of the rendered audio in seconds, set by generateAudio */
+  duration_secs?: number;
+  /** Stem files written when export_stems is set, set by generateAudio */
+  stems?: string[];
+  /** Samples clipped to ±1 in the written file, set by generateAudio */
+  clipped_samples?: number;
+  /** Seed for random choices; generateAudio returns the seed it used */
+  seed?: number;
 }
 
 /**
@@ -17,7 +25,127 @@ export interface TtsProgressEvent {
   job_id: string;
   message: string;
   progress: number;
-  stage: "start" | "download" | "generate" | "write" | "complete";
+  stage:
+    | "queued"
+    | "start"
+    | "download"
+    | "load"
+    | "generate"
+    | "write"
+    | "verify"
+    | "complete"
+    | "warning";
+  /** Position of the script in a generateAudioBatch call */
+  index?: number;
+}
+
+/**
+ * Outcome of one script of a generateAudioBatch call
+ */
+export interface BatchItemResult {
+  index: number;
+  /** The script with its output fields filled in, if it rendered */
+  script?: AudioScript;
+  error?: string;
+}
+
+/**
+ * ONNX Runtime execution provider used for inference
+ */
+export type ExecutionProvider = "cpu" | "cuda" | "coreml" | "directml";
+
+/**
+ * Optional render settings passed alongside an AudioScript
+ */
+export interface RenderOptions {
+  execution_provider?: ExecutionProvider;
+  /** Maximum characters per TTS call before text is split further */
+  max_chunk_chars?: number;
+  /** Silence in seconds between sentence chunks of a text node */
+  chunk_pause_secs?: number;
+  /** Whether to trim leading/trailing silence from generated speech */
+  trim?: boolean;
+  trim_threshold?: number;
+  trim_min_silence_ms?: number;
+  /** Linear gain applied to generated speech */
+  tts_gain?: number;
+  /** Crossfade length in milliseconds at voice-change boundaries */
+  voice_crossfade_ms?: number;
+  /** Seed for reproducible random choices */
+  seed?: number;
+  /** Share of the progress bar reserved for downloads (0-1) when files are missing */
+  download_weight?: number;
+  /** Fail on unknown tags or attributes instead of emitting warnings */
+  strict?: boolean;
+  /** Replace an existing file with the same name (default true); otherwise use "Title (1).wav" */
+  overwrite?: boolean;
+  /** Embed title/artist metadata in the WAV (default true) */
+  embed_metadata?: boolean;
+  artist?: string;
+  /** Remove DC offset from sound effects when they are loaded */
+  remove_dc?: boolean;
+  /** Fold sound effects with more than two channels down to stereo on load */
+  downmix_sounds?: boolean;
+  /** Expand abbreviations and units such as "Dr." and "km" (default true) */
+  normalize_text?: boolean;
+  /** Abbreviation -> spoken form, merged over the defaults ("" disables one) */
+  abbreviations?: Record<string, string>;
+  /** Unit -> spoken form, expanded after numbers */
+  units?: Record<string, string>;
+  /** Currency for the ¤ sign and ambiguous amounts like 1,50 (default USD) */
+  currency?: "USD" | "EUR" | "GBP";
+  /** Apply short fades where segments join to avoid clicks */
+  declick?: boolean;
+  /** Speaker name -> voice key for <speaker> tags */
+  speakers?: Record<string, string>;
+  /** Amount (0-1) of seeded random speed variation per sentence chunk */
+  humanize?: number;
+  /** Relative speed variation at full humanize (default 0.08) */
+  humanize_range?: number;
+  /** Longest output in seconds a <loop> may produce (default 7200) */
+  max_output_secs?: number;
+  /** Render <overlay> parts on separate threads (default true) */
+  parallel_overlay?: boolean;
+  /** Also write each <overlay> part to "{basename}.stem{N}.wav" */
+  export_stems?: boolean;
+  /** Emit each finished top-level segment as a "tts-audio-chunk" event */
+  stream?: boolean;
+  /** Seconds of silence for an empty or comment-only script instead of an error */
+  empty_silence_secs?: number;
+  /** Force every file to mono or stereo */
+  output_channels?: "mono" | "stereo";
+  /** Resample the finished audio to this rate (default 24000) */
+  output_sample_rate?: number;
+  /** Limit peaks of the finished audio instead of clamping them when written */
+  limiter?: boolean;
+  /** Highest peak the limiter lets through, in dBFS (default -0.3) */
+  limiter_ceiling_db?: number;
+  /** Effect presets for this render in the presets.json layout */
+  presets?: Record<string, Record<string, Record<string, number>>>;
+  /** Base directory for models, voices and sounds (overrides DOMGPT_DATA_DIR) */
+  data_dir?: string;
+}
+
+/**
+ * A script saved with the settings needed to render it again the same way.
+ * options carries the speaker map, custom presets and output format.
+ */
+export interface ProjectBundle {
+  title: string;
+  script: string;
+  /** Seed for random choices, overriding options.seed */
+  seed?: number;
+  options: RenderOptions;
+}
+
+/**
+ * A problem found in a script by validateScript (line and column are 1-based)
+ */
+export interface Diagnostic {
+  severity: "error" | "warning";
+  message: string;
+  line: number;
+  column: number;
 }
 
 /**
@@ -25,17 +153,40 @@ export interface TtsProgressEvent {
  */
 export type TtsProgressListener = (event: TtsProgressEvent) => void;
 
+/**
+ * A finished top-level segment of a streamed render (options.stream)
+ */
+export interface AudioChunkEvent {
+  job_id: string;
+  /** Position of this chunk in the stream, starting at 0 */
+  index: number;
+  /** Set on the last chunk of the job */
+  final: boolean;
+  /** Base64-encoded standalone WAV */
+  data: string;
+}
+
+export type AudioChunkListener = (event: AudioChunkEvent) => void;
+
 /**
  * Generate audio from an AudioScript using the Rust TTS backend.
  * This will automatically download model and voice files if they don't exist.
  *
  * @param script - The audio script containing title, script content, and optional filename
  * @param onProgress - Optional callback for progress updates
+ * @param options - Optional render settings
+ * @param timeoutSecs - Optional limit on render time; the job fails with a
+ *   timeout error once it is exceeded
+ * @param project - Optional loaded project whose script, seed and options
+ *   replace those of `script` and `options`; `script` still names the file
  * @returns Promise resolving to the AudioScript with the filename populated
  */
 export async function generateAudio(
   script: AudioScript,
   onProgress?: TtsProgressListener,
+  options?: RenderOptions,
+  timeoutSecs?: number,
+  project?: ProjectBundle,
 ): Promise<AudioScript> {
   let unlisten: UnlistenFn | undefined;
 
@@ -46,7 +197,12 @@ export async function generateAudio(
   }
 
   try {
-    const result = await invoke<AudioScript>("generate_audio", { script });
+    const result = await invoke<AudioScript>("generate_audio", {
+      script,
+      options,
+      timeoutSecs,
+      project,
+    });
     return result;
   } finally {
     if (unlisten) {
@@ -55,6 +211,255 @@ export async function generateAudio(
   }
 }
 
+/**
+ * Render several scripts with one loaded model, writing each to its own file.
+ * Scripts that fail are reported in their result instead of stopping the
+ * batch; progress events carry the script's index.
+ */
+export async function generateAudioBatch(
+  scripts: AudioScript[],
+  onProgress?: TtsProgressListener,
+  options?: RenderOptions,
+): Promise<BatchItemResult[]> {
+  let unlisten: UnlistenFn | undefined;
+
+  if (onProgress) {
+    unlisten = await listen<TtsProgressEvent>("tts-progress", (event) => {
+      onProgress(event.payload);
+    });
+  }
+
+  try {
+    return await invoke<BatchItemResult[]>("generate_audio_batch", {
+      scripts,
+      options,
+    });
+  } finally {
+    if (unlisten) {
+      unlisten();
+    }
+  }
+}
+
+/**
+ * Render a script and play it directly on the default output device, without
+ * writing a file. Stops any clip that is already playing.
+ *
+ * @returns Promise resolving to the clip length in seconds once playback starts
+ */
+export async function playAudio(
+  script: string,
+  options?: RenderOptions,
+): Promise<number> {
+  return invoke<number>("play_audio", { script, options });
+}
+
+/**
+ * Render a script to a `data:audio/wav;base64,...` URI that an <audio>
+ * element can play directly. Intended for short clips and previews, since the
+ * whole WAV is inlined in the string.
+ */
+export async function previewAudio(
+  script: string,
+  options?: RenderOptions,
+): Promise<string> {
+  return invoke<string>("preview_audio", { script, options });
+}
+
+/**
+ * The same script in two voices as `data:audio/wav;base64,...` URIs
+ */
+export interface VoiceComparison {
+  voice_a: string;
+  voice_b: string;
+  audio_a: string;
+  audio_b: string;
+  /** Both versions back to back, announced as "Version A" and "Version B" */
+  combined: string | null;
+}
+
+/**
+ * Render a script once in each of two voices, ignoring its own <voice> and
+ * <speaker> changes. With `separator` the result also holds both versions
+ * back to back with a spoken "Version A" / "Version B" before each.
+ */
+export async function compareVoices(
+  script: string,
+  voiceA: string,
+  voiceB: string,
+  separator?: boolean,
+  options?: RenderOptions,
+): Promise<VoiceComparison> {
+  return invoke<VoiceComparison>("compare_voices", {
+    script,
+    voiceA,
+    voiceB,
+    separator,
+    options,
+  });
+}
+
+/**
+ * Where the time of a profiled render went
+ */
+export interface RenderProfile {
+  /**
+   * Seconds per operation: "model_load", "tts:<tag>" (by the tag around the
+   * text), "effect:<name>", "sound_load", "resample", "write"
+   */
+  stages: Record<string, number>;
+  tts_calls: number;
+  /** Speech generated by the model before trimming, in seconds */
+  tts_audio_secs: number;
+  /** Wall time of the whole profiled run */
+  total_secs: number;
+}
+
+/**
+ * Render a script without saving it and report where the time went
+ */
+export async function profileScript(
+  script: string,
+  options?: RenderOptions,
+): Promise<RenderProfile> {
+  return invoke<RenderProfile>("profile_script", { script, options });
+}
+
+/**
+ * Stop backend playback. Resolves to whether a clip was still playing.
+ */
+export async function stopPlayback(): Promise<boolean> {
+  return invoke<boolean>("stop_playback");
+}
+
+/**
+ * Check a script for problems without rendering it. Voice files in the data
+ * directory `options` selects count as known voices.
+ * Resolves to an empty array for a valid script.
+ */
+export async function validateScript(
+  script: string,
+  options?: RenderOptions,
+): Promise<Diagnostic[]> {
+  return invoke<Diagnostic[]>("validate_script", { script, options });
+}
+
+/**
+ * Download and load the TTS model ahead of the first generation.
+ * Subsequent calls to generateAudio reuse the loaded model.
+ */
+export async function warmUpTts(options?: RenderOptions): Promise<void> {
+  await invoke("warm_up_tts", { options });
+}
+
+/**
+ * Health of one cached model or voice file
+ */
+export interface ModelFileStatus {
+  /** Path relative to the model repository, e.g. "onnx/vocoder.onnx" */
+  file: string;
+  state: "ok" | "missing" | "empty" | "corrupt" | "repaired";
+  /** Why a repair attempt failed */
+  error: string | null;
+}
+
+/**
+ * Check the cached model and voice files. With repair, broken or missing
+ * files are downloaded again. Progress is reported as "tts-progress" events.
+ */
+export async function verifyModels(
+  repair?: boolean,
+  options?: RenderOptions,
+): Promise<ModelFileStatus[]> {
+  return invoke<ModelFileStatus[]>("verify_models", { repair, options });
+}
+
+/**
+ * Register a sound effect usable as <sound value="key">, from a file path or
+ * WAV bytes. Registering an existing key replaces it.
+ */
+export async function registerSoundEffect(
+  key: string,
+  source: string | Uint8Array | number[],
+): Promise<void> {
+  const payload = typeof source === "string" ? source : Array.from(source);
+  await invoke("register_sound_effect", { key, source: payload });
+}
+
+/**
+ * List built-in and registered sound effect keys
+ */
+export async function listSoundEffects(): Promise<string[]> {
+  return invoke<string[]>("list_sound_effects");
+}
+
+/**
+ * Write a script and its render settings to one JSON file
+ */
+export async function saveProject(
+  path: string,
+  project: ProjectBundle,
+): Promise<void> {
+  await invoke("save_project", { path, project });
+}
+
+/**
+ * Read a project written by saveProject
+ */
+export async function loadProject(path: string): Promise<ProjectBundle> {
+  return invoke<ProjectBundle>("load_project", { path });
+}
+
+/**
+ * Save effect settings as a preset usable as <effect value="effect"
+ * preset="name">. Registering an existing name replaces it.
+ */
+export async function registerPreset(
+  effect: EffectName,
+  name: string,
+  options: Record<string, number>,
+): Promise<void> {
+  await invoke("register_preset", {
+    effect,
+    name,
+    optionsJson: JSON.stringify(options),
+  });
+}
+
+/**
+ * List built-in, presets.json and registered preset names for an effect
+ */
+export async function listPresets(effect: EffectName): Promise<string[]> {
+  return invoke<string[]>("list_presets", { effect });
+}
+
+/**
+ * Voice style and sound effect files found in the data directories, keyed by
+ * lowercase file name without extension
+ */
+export interface AssetScan {
+  voices: Record<string, string>;
+  sounds: Record<string, string>;
+}
+
+/**
+ * Scan the voice and sound directories again so files added while the app
+ * runs can be used by the next render, without restarting
+ */
+export async function refreshAssets(
+  options?: RenderOptions,
+): Promise<AssetScan> {
+  return invoke<AssetScan>("refresh_assets", { options });
+}
+
+/**
+ * Set how many generateAudio jobs may render at once. Further jobs wait in
+ * order and report a "queued" progress event with their position.
+ */
+export async function setMaxConcurrentJobs(max: number): Promise<void> {
+  await invoke("set_max_concurrent_jobs", { max });
+}
+
 /**
  * Subscribe to all TTS progress events.
  * Returns an unsubscribe function.
@@ -67,6 +472,18 @@ export async function subscribeTtsProgress(
   });
 }
 
+/**
+ * Subscribe to streamed audio chunks from renders started with
+ * options.stream. Returns an unsubscribe function.
+ */
+export async function subscribeAudioChunks(
+  listener: AudioChunkListener,
+): Promise<UnlistenFn> {
+  return listen<AudioChunkEvent>("tts-audio-chunk", (event) => {
+    listener(event.payload);
+  });
+}
+
 /**
  * Sound effects available in the TTS system
  */
@@ -111,6 +528,32 @@ export const echoPresets = {
   heavy: "heavy",
 } as const;
 
+/**
+ * Distortion effect presets
+ */
+export const distortionPresets = {
+  warm: "warm",
+  crunch: "crunch",
+  fuzz: "fuzz",
+} as const;
+
+/** Effects understood by <effect value="..."> and <effect chain="..."> */
+export type EffectName =
+  | "echo"
+  | "binaural"
+  | "telephone"
+  | "radio"
+  | "bitcrush"
+  | "ringmod"
+  | "pingpong"
+  | "haas"
+  | "robot"
+  | "doppler"
+  | "spatial"
+  | "autowah"
+  | "distortion"
+  | "denoise";
+
 /**
  * Build an XML script string from components.
  * Helper for constructing audio scripts programmatically.
@@ -130,12 +573,24 @@ export class ScriptBuilder {
     return this;
   }
 
+  /** Add exactly this much silence */
+  silence(seconds: number): this {
+    this.content.push(`<silence value="${seconds}"/>`);
+    return this;
+  }
+
   /** Add a sound effect */
   sound(effect: keyof typeof soundEffects): this {
     this.content.push(`<sound value="${effect}"></sound>`);
     return this;
   }
 
+  /** Insert an audio clip (URL, sound key or file path); fallback text is spoken if it fails to load */
+  audio(src: string, fallback?: string): this {
+    this.content.push(`<audio src="${src}">${fallback ?? ""}</audio>`);
+    return this;
+  }
+
   /** Wrap content in a voice tag */
   voice(voiceKey: keyof typeof voices, content: string | ScriptBuilder): this {
     const inner = content instanceof ScriptBuilder ? content.build() : content;
@@ -143,6 +598,30 @@ export class ScriptBuilder {
     return this;
   }
 
+  /** Wrap content in a quality tag (fast = 15, balanced = 30, best = 60 steps) */
+  quality(
+    value: "fast" | "balanced" | "best",
+    content: string | ScriptBuilder,
+  ): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    this.content.push(`<quality value="${value}">${inner}</quality>`);
+    return this;
+  }
+
+  /** Wrap content in an SSML paragraph, followed by the paragraph pause */
+  paragraph(content: string | ScriptBuilder): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    this.content.push(`<p>${inner}</p>`);
+    return this;
+  }
+
+  /** Wrap content in an SSML sentence, followed by the sentence pause */
+  sentence(content: string | ScriptBuilder): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    this.content.push(`<s>${inner}</s>`);
+    return this;
+  }
+
   /** Wrap content in a speed tag */
   speed(value: number, content: string | ScriptBuilder): this {
     const inner = content instanceof ScriptBuilder ? content.build() : content;
@@ -150,6 +629,13 @@ export class ScriptBuilder {
     return this;
   }
 
+  /** Wrap content in a speed tag ramping from one speed to another */
+  speedRamp(from: number, to: number, content: string | ScriptBuilder): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    this.content.push(`<speed from="${from}" to="${to}">${inner}</speed>`);
+    return this;
+  }
+
   /** Wrap content in a volume tag */
   volume(value: number, content: string | ScriptBuilder): this {
     const inner = content instanceof ScriptBuilder ? content.build() : content;
@@ -159,11 +645,13 @@ export class ScriptBuilder {
 
   /** Wrap content in an effect tag */
   effect(
-    effectName: "echo" | "binaural",
+    effectName: EffectName,
     content: string | ScriptBuilder,
     options?: {
       preset?: string;
       options?: Record<string, number>;
+      /** Downmix the result to mono, e.g. after pan or binaural */
+      mono?: boolean;
     },
   ): this {
     const inner = content instanceof ScriptBuilder ? content.build() : content;
@@ -174,10 +662,38 @@ export class ScriptBuilder {
     if (options?.options) {
       attrs += ` options='${JSON.stringify(options.options)}'`;
     }
+    if (options?.mono) {
+      attrs += ` mono="true"`;
+    }
+    this.content.push(`<effect ${attrs}>${inner}</effect>`);
+    return this;
+  }
+
+  /** Apply several effects in order, each configured by its entry in `options` */
+  effectChain(
+    effects: EffectName[],
+    content: string | ScriptBuilder,
+    options?: Partial<Record<EffectName, Record<string, number>>>,
+  ): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    let attrs = `chain="${effects.join(",")}"`;
+    if (options) {
+      attrs += ` options='${JSON.stringify(options)}'`;
+    }
     this.content.push(`<effect ${attrs}>${inner}</effect>`);
     return this;
   }
 
+  /** Restrict the processing tags inside to one channel, or to the mid or side signal */
+  channel(
+    side: "left" | "right" | "mid" | "side",
+    content: string | ScriptBuilder,
+  ): this {
+    const inner = content instanceof ScriptBuilder ? content.build() : content;
+    this.content.push(`<channel side="${side}">${inner}</channel>`);
+    return this;
+  }
+
   /** Loop content multiple times */
   loop(times: number, content: string | ScriptBuilder): this {
     const inner = content instanceof ScriptBuilder ? content.build() : content;